@@ -1,24 +1,38 @@
 
-use machine::isa::*;
-use machine::isa::rv32i::*;
+use machine::elf::*;
+use machine::isa::rv32i::disasm::*;
 
 use std::fs::File;
 use std::io::Read;
 
 fn main() { 
 
-    let mut f = File::open("tests/rv32i/test.bin").unwrap();
+    // Accepts either an ELF file or a raw binary (loaded at address 0).
+    let path = std::env::args().nth(1)
+        .unwrap_or_else(|| "tests/rv32i/test.elf".to_string());
+    let mut f = File::open(&path).unwrap();
     let len = f.metadata().unwrap().len() as usize;
     let mut buf = vec![0u8; len];
-    f.read(&mut buf).unwrap();
+    f.read_exact(&mut buf).unwrap();
 
-    let instrs = unsafe { 
-        std::slice::from_raw_parts(buf.as_ptr() as *const u32, len/4)
+    let lines = match Elf32::parse(&buf) {
+        Ok(elf) => {
+            let text = elf.section(".text").expect("no .text section");
+            let symbols = elf.symbol_table();
+            println!("entry: 0x{:08x}", elf.entry());
+            Disassembler::new(text.addr)
+                .with_symbols(&symbols)
+                .disassemble(elf.section_data(text).unwrap())
+                .iter().map(|l| l.to_string()).collect::<Vec<_>>()
+        },
+        Err(ElfError::BadMagic) => {
+            Disassembler::new(0).disassemble(&buf)
+                .iter().map(|l| l.to_string()).collect::<Vec<_>>()
+        },
+        Err(e) => panic!("{}: {}", path, e),
     };
-
-    for inst in instrs {
-        let res = Rv32::decode(*inst);
-        println!("{}", res);
+    for line in lines {
+        println!("{}", line);
     }
 
 }
@@ -0,0 +1,529 @@
+//! A loader for 32-bit little-endian RISC-V ELF files.
+
+use crate::isa::rv32i::disasm::SymbolTable;
+use crate::memory::Bus;
+
+/// The 'e_machine' value for RISC-V.
+pub const EM_RISCV: u16 = 243;
+
+/// Program header type for loadable segments.
+pub const PT_LOAD: u32 = 1;
+
+/// Section header types.
+pub const SHT_NULL: u32     = 0;
+pub const SHT_PROGBITS: u32 = 1;
+pub const SHT_SYMTAB: u32   = 2;
+pub const SHT_STRTAB: u32   = 3;
+pub const SHT_NOBITS: u32   = 8;
+
+/// The section index used when there is no section (ie. no section name
+/// string table).
+pub const SHN_UNDEF: u16 = 0;
+
+const PHDR_SIZE: usize = 32;
+const SHDR_SIZE: usize = 40;
+const SYM_SIZE: usize  = 16;
+
+/// Reasons why an ELF file cannot be parsed or loaded.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ElfError {
+    /// Some structure extends beyond the end of the file.
+    Truncated,
+    /// The file does not begin with the ELF magic number.
+    BadMagic,
+    /// The file is not a 32-bit ELF file.
+    UnsupportedClass(u8),
+    /// The file is not little-endian.
+    UnsupportedEndian(u8),
+    /// The file is not for RISC-V.
+    UnsupportedMachine(u16),
+    /// A header table has an unexpected entry size.
+    BadEntrySize,
+    /// A segment does not fit in the target memory.
+    SegmentOutOfRange { addr: u32, size: u32 },
+}
+impl std::fmt::Display for ElfError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Truncated => write!(f, "truncated ELF file"),
+            Self::BadMagic => write!(f, "not an ELF file"),
+            Self::UnsupportedClass(c) => write!(f, "unsupported ELF class {}", c),
+            Self::UnsupportedEndian(e) => {
+                write!(f, "unsupported ELF data encoding {}", e)
+            },
+            Self::UnsupportedMachine(m) => write!(f, "unsupported machine {}", m),
+            Self::BadEntrySize => write!(f, "unexpected header entry size"),
+            Self::SegmentOutOfRange { addr, size } => {
+                write!(f, "segment at 0x{:08x} (0x{:x} bytes) is out of range",
+                    addr, size)
+            },
+        }
+    }
+}
+impl std::error::Error for ElfError {}
+
+/// Returns 'len' bytes at offset 'off'.
+fn slice(data: &[u8], off: usize, len: usize) -> Result<&[u8], ElfError> {
+    let end = off.checked_add(len).ok_or(ElfError::Truncated)?;
+    data.get(off..end).ok_or(ElfError::Truncated)
+}
+fn read_u16(data: &[u8], off: usize) -> Result<u16, ElfError> {
+    let b = slice(data, off, 2)?;
+    Ok(u16::from_le_bytes([b[0], b[1]]))
+}
+fn read_u32(data: &[u8], off: usize) -> Result<u32, ElfError> {
+    let b = slice(data, off, 4)?;
+    Ok(u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+}
+
+/// Read a NUL-terminated string at offset 'off'.
+fn read_str(data: &[u8], off: usize) -> Result<String, ElfError> {
+    let rest = data.get(off..).ok_or(ElfError::Truncated)?;
+    let len = rest.iter().position(|c| *c == 0).ok_or(ElfError::Truncated)?;
+    Ok(String::from_utf8_lossy(&rest[..len]).into_owned())
+}
+
+/// The ELF file header.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ElfHeader {
+    pub ty: u16,
+    pub machine: u16,
+    pub entry: u32,
+    pub phoff: u32,
+    pub shoff: u32,
+    pub flags: u32,
+    pub phnum: u16,
+    pub shnum: u16,
+    pub shstrndx: u16,
+}
+impl ElfHeader {
+    fn parse(data: &[u8]) -> Result<Self, ElfError> {
+        let ident = slice(data, 0, 16)?;
+        if ident[0..4] != [0x7f, b'E', b'L', b'F'] {
+            return Err(ElfError::BadMagic);
+        }
+        if ident[4] != 1 {
+            return Err(ElfError::UnsupportedClass(ident[4]));
+        }
+        if ident[5] != 1 {
+            return Err(ElfError::UnsupportedEndian(ident[5]));
+        }
+        let machine = read_u16(data, 18)?;
+        if machine != EM_RISCV {
+            return Err(ElfError::UnsupportedMachine(machine));
+        }
+        let phnum = read_u16(data, 44)?;
+        let shnum = read_u16(data, 48)?;
+        if (phnum != 0 && read_u16(data, 42)? as usize != PHDR_SIZE)
+        || (shnum != 0 && read_u16(data, 46)? as usize != SHDR_SIZE)
+        {
+            return Err(ElfError::BadEntrySize);
+        }
+        Ok(Self {
+            ty: read_u16(data, 16)?,
+            machine,
+            entry: read_u32(data, 24)?,
+            phoff: read_u32(data, 28)?,
+            shoff: read_u32(data, 32)?,
+            flags: read_u32(data, 36)?,
+            phnum,
+            shnum,
+            shstrndx: read_u16(data, 50)?,
+        })
+    }
+}
+
+/// An entry in the program header table.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ProgramHeader {
+    pub ty: u32,
+    pub offset: u32,
+    pub vaddr: u32,
+    pub paddr: u32,
+    pub filesz: u32,
+    pub memsz: u32,
+    pub flags: u32,
+    pub align: u32,
+}
+impl ProgramHeader {
+    fn parse(data: &[u8], off: usize) -> Result<Self, ElfError> {
+        let b = slice(data, off, PHDR_SIZE)?;
+        let w = |i: usize| u32::from_le_bytes([b[i], b[i+1], b[i+2], b[i+3]]);
+        Ok(Self {
+            ty: w(0), offset: w(4), vaddr: w(8), paddr: w(12),
+            filesz: w(16), memsz: w(20), flags: w(24), align: w(28),
+        })
+    }
+}
+
+/// An entry in the section header table.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SectionHeader {
+    pub name: String,
+    pub ty: u32,
+    pub flags: u32,
+    pub addr: u32,
+    pub offset: u32,
+    pub size: u32,
+    pub link: u32,
+    pub info: u32,
+    pub addralign: u32,
+    pub entsize: u32,
+}
+impl SectionHeader {
+    /// Parse a section header, returning the offset of its name in the
+    /// section header string table.
+    fn parse(data: &[u8], off: usize) -> Result<(u32, Self), ElfError> {
+        let b = slice(data, off, SHDR_SIZE)?;
+        let w = |i: usize| u32::from_le_bytes([b[i], b[i+1], b[i+2], b[i+3]]);
+        Ok((w(0x00), Self {
+            name: String::new(),
+            ty: w(0x04), flags: w(0x08), addr: w(0x0c), offset: w(0x10),
+            size: w(0x14), link: w(0x18), info: w(0x1c), addralign: w(0x20),
+            entsize: w(0x24),
+        }))
+    }
+}
+
+/// The type of a symbol.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SymbolKind { NoType, Object, Func, Section, File, Other(u8) }
+impl From<u8> for SymbolKind {
+    fn from(x: u8) -> Self {
+        match x {
+            0 => Self::NoType,
+            1 => Self::Object,
+            2 => Self::Func,
+            3 => Self::Section,
+            4 => Self::File,
+            _ => Self::Other(x),
+        }
+    }
+}
+
+/// An entry in the symbol table.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Symbol {
+    pub name: String,
+    pub value: u32,
+    pub size: u32,
+    pub kind: SymbolKind,
+    /// True for global (or weak) symbols.
+    pub global: bool,
+    /// Index of the section containing this symbol (zero if undefined).
+    pub shndx: u16,
+}
+
+/// The result of loading an ELF file into memory.
+#[derive(Clone, Debug)]
+pub struct LoadedImage {
+    /// The entry point.
+    pub entry: u32,
+    /// Names for the defined code and data symbols.
+    pub symbols: SymbolTable,
+}
+
+/// A parsed ELF32 file.
+pub struct Elf32<'a> {
+    data: &'a [u8],
+    pub header: ElfHeader,
+    pub segments: Vec<ProgramHeader>,
+    pub sections: Vec<SectionHeader>,
+    pub symbols: Vec<Symbol>,
+}
+impl <'a> Elf32<'a> {
+    /// Parse the headers and symbol table of an ELF file.
+    pub fn parse(data: &'a [u8]) -> Result<Self, ElfError> {
+        let header = ElfHeader::parse(data)?;
+
+        let segments = (0..header.phnum as usize)
+            .map(|i| ProgramHeader::parse(data,
+                header.phoff as usize + i * PHDR_SIZE))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let (names, mut sections): (Vec<u32>, Vec<SectionHeader>) =
+            (0..header.shnum as usize)
+            .map(|i| SectionHeader::parse(data,
+                header.shoff as usize + i * SHDR_SIZE))
+            .collect::<Result<Vec<_>, _>>()?
+            .into_iter().unzip();
+        let shstrtab = match header.shstrndx {
+            SHN_UNDEF => None,
+            idx => sections.get(idx as usize),
+        };
+        if let Some(shstrtab) = shstrtab {
+            let strtab = Self::data_for(data, shstrtab)?;
+            for (sh, name) in sections.iter_mut().zip(names) {
+                sh.name = read_str(strtab, name as usize)?;
+            }
+        }
+
+        let mut symbols = Vec::new();
+        for sh in sections.iter().filter(|sh| sh.ty == SHT_SYMTAB) {
+            let strtab = sections.get(sh.link as usize)
+                .ok_or(ElfError::Truncated)?;
+            let strtab = Self::data_for(data, strtab)?;
+            let symtab = Self::data_for(data, sh)?;
+            for sym in symtab.chunks_exact(SYM_SIZE) {
+                let w = |i: usize| {
+                    u32::from_le_bytes([sym[i], sym[i+1], sym[i+2], sym[i+3]])
+                };
+                symbols.push(Symbol {
+                    name: read_str(strtab, w(0) as usize)?,
+                    value: w(4),
+                    size: w(8),
+                    kind: SymbolKind::from(sym[12] & 0xf),
+                    global: (sym[12] >> 4) != 0,
+                    shndx: u16::from_le_bytes([sym[14], sym[15]]),
+                });
+            }
+        }
+
+        Ok(Self { data, header, segments, sections, symbols })
+    }
+
+    /// Returns the contents of a section in the file.
+    fn data_for(data: &'a [u8], sh: &SectionHeader) -> Result<&'a [u8], ElfError> {
+        if sh.ty == SHT_NOBITS || sh.ty == SHT_NULL {
+            return Ok(&[]);
+        }
+        slice(data, sh.offset as usize, sh.size as usize)
+    }
+
+    /// Returns the entry point.
+    pub fn entry(&self) -> u32 {
+        self.header.entry
+    }
+
+    /// Returns the section with the given name.
+    pub fn section(&self, name: &str) -> Option<&SectionHeader> {
+        self.sections.iter().find(|sh| sh.name == name)
+    }
+
+    /// Returns the contents of a section in the file.
+    pub fn section_data(&self, sh: &SectionHeader) -> Result<&'a [u8], ElfError> {
+        Self::data_for(self.data, sh)
+    }
+
+    /// Returns the contents of a segment in the file.
+    pub fn segment_data(&self, ph: &ProgramHeader) -> Result<&'a [u8], ElfError> {
+        slice(self.data, ph.offset as usize, ph.filesz as usize)
+    }
+
+    /// Returns a table of the defined function, object, and untyped symbols.
+    ///
+    /// Local assembler labels ('.L*') and mapping symbols ('$x') are
+    /// omitted.
+    pub fn symbol_table(&self) -> SymbolTable {
+        let mut res = SymbolTable::new();
+        for sym in self.symbols.iter() {
+            let named = !sym.name.is_empty() && !sym.name.starts_with(".L")
+                && !sym.name.starts_with('$');
+            let kind = matches!(sym.kind,
+                SymbolKind::NoType | SymbolKind::Func | SymbolKind::Object);
+            if named && kind && sym.shndx != 0 {
+                res.insert(sym.value, sym.name.clone());
+            }
+        }
+        res
+    }
+
+    /// Load each PT_LOAD segment at its physical address using 'write',
+    /// which is called with an address and the bytes to be written there.
+    /// Any part of a segment beyond the data in the file is zero-filled.
+    pub fn load<F>(&self, mut write: F) -> Result<LoadedImage, ElfError>
+        where F: FnMut(u32, &[u8]) -> Result<(), ElfError>
+    {
+        for ph in self.segments.iter().filter(|ph| ph.ty == PT_LOAD) {
+            if ph.paddr.checked_add(ph.memsz).is_none() {
+                return Err(ElfError::SegmentOutOfRange {
+                    addr: ph.paddr, size: ph.memsz
+                });
+            }
+            let data = self.segment_data(ph)?;
+            let data = &data[..data.len().min(ph.memsz as usize)];
+            write(ph.paddr, data)?;
+
+            // Zero-fill in chunks, since 'memsz' comes from the file
+            const ZEROS: [u8; 0x1000] = [0; 0x1000];
+            let mut addr = ph.paddr + data.len() as u32;
+            let end = ph.paddr + ph.memsz;
+            while addr < end {
+                let len = (end - addr).min(ZEROS.len() as u32);
+                write(addr, &ZEROS[..len as usize])?;
+                addr += len;
+            }
+        }
+        Ok(LoadedImage { entry: self.entry(), symbols: self.symbol_table() })
+    }
+
+    /// Load each PT_LOAD segment onto a bus, where 'base' is the physical
+    /// address which corresponds to address zero on the bus.
+    pub fn load_into(&self, mem: &mut impl Bus, base: u32)
+        -> Result<LoadedImage, ElfError>
+    {
+        self.load(|addr, data| {
+            let err = ElfError::SegmentOutOfRange {
+                addr, size: data.len() as u32
+            };
+            let off = addr.checked_sub(base).ok_or(err.clone())? as usize;
+            mem.write(off, data).map_err(|_| err)
+        })
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use crate::elf::*;
+    use crate::memory::NaiveRAM;
+
+    /// Build a small executable with a single segment and two symbols.
+    fn build_elf() -> Vec<u8> {
+        fn h(v: &mut Vec<u8>, x: u16) { v.extend_from_slice(&x.to_le_bytes()); }
+        fn w(v: &mut Vec<u8>, x: u32) { v.extend_from_slice(&x.to_le_bytes()); }
+
+        let text: [u32; 4] = [0x0010_8093, 0xfe20_8ee3, 0x0000_0013, 0x0000_8067];
+        let strtab = b"\0_start\0loop\0.L0\0";
+        let shstrtab = b"\0.text\0.symtab\0.strtab\0.shstrtab\0.bss\0";
+
+        let mut v = Vec::new();
+        // ELF header
+        v.extend_from_slice(&[0x7f, b'E', b'L', b'F', 1, 1, 1, 0]);
+        v.extend_from_slice(&[0; 8]);
+        h(&mut v, 2); h(&mut v, EM_RISCV); w(&mut v, 1);
+        w(&mut v, 0x8000_0000); // entry
+        w(&mut v, 52);          // phoff
+        w(&mut v, 0);           // shoff (fixed below)
+        w(&mut v, 0);           // flags
+        h(&mut v, 52); h(&mut v, 32); h(&mut v, 1);
+        h(&mut v, 40); h(&mut v, 6); h(&mut v, 4);
+
+        // Program header
+        w(&mut v, PT_LOAD); w(&mut v, 84);
+        w(&mut v, 0x8000_0000); w(&mut v, 0x8000_0000);
+        w(&mut v, 16); w(&mut v, 32); w(&mut v, 5); w(&mut v, 4);
+
+        // .text at 84
+        for x in text { w(&mut v, x); }
+        // .strtab at 100
+        v.extend_from_slice(strtab);
+        while v.len() % 4 != 0 { v.push(0); }
+        // .symtab
+        let symoff = v.len() as u32;
+        v.extend_from_slice(&[0; 16]);
+        for (name, value, info) in [(1, 0x8000_0000, 0x12), (8, 0x8000_0004, 0x00),
+            (13, 0x8000_0008, 0x00)]
+        {
+            w(&mut v, name); w(&mut v, value); w(&mut v, 0);
+            v.push(info); v.push(0); h(&mut v, 1);
+        }
+        // .shstrtab
+        let shstroff = v.len() as u32;
+        v.extend_from_slice(shstrtab);
+        while v.len() % 4 != 0 { v.push(0); }
+
+        // Section headers
+        let shoff = v.len() as u32;
+        v[32..36].copy_from_slice(&shoff.to_le_bytes());
+        let sections = [
+            (0, SHT_NULL, 0, 0, 0, 0, 0),
+            (1, SHT_PROGBITS, 0x8000_0000, 84, 16, 0, 0),
+            (7, SHT_SYMTAB, 0, symoff, 64, 3, 16),
+            (15, SHT_STRTAB, 0, 100, strtab.len() as u32, 0, 0),
+            (23, SHT_STRTAB, 0, shstroff, shstrtab.len() as u32, 0, 0),
+            (33, SHT_NOBITS, 0x8000_0010, 0, 16, 0, 0),
+        ];
+        for (name, ty, addr, off, size, link, entsize) in sections {
+            w(&mut v, name); w(&mut v, ty); w(&mut v, 0); w(&mut v, addr);
+            w(&mut v, off); w(&mut v, size); w(&mut v, link); w(&mut v, 0);
+            w(&mut v, 4); w(&mut v, entsize);
+        }
+        v
+    }
+
+    #[test]
+    fn parse_elf() {
+        let data = build_elf();
+        let elf = Elf32::parse(&data).unwrap();
+        assert_eq!(elf.entry(), 0x8000_0000);
+        assert_eq!(elf.segments.len(), 1);
+        let names: Vec<&str> = elf.sections.iter().map(|s| s.name.as_str()).collect();
+        assert_eq!(names, ["", ".text", ".symtab", ".strtab", ".shstrtab", ".bss"]);
+
+        let text = elf.section(".text").unwrap();
+        assert_eq!(text.addr, 0x8000_0000);
+        assert_eq!(elf.section_data(text).unwrap()[..4], 0x0010_8093u32.to_le_bytes());
+        assert_eq!(elf.section_data(elf.section(".bss").unwrap()).unwrap(), &[]);
+
+        assert_eq!(elf.symbols.len(), 4);
+        assert_eq!(elf.symbols[1].name, "_start");
+        assert_eq!(elf.symbols[1].kind, SymbolKind::Func);
+        assert!(elf.symbols[1].global);
+        let symbols = elf.symbol_table();
+        assert_eq!(symbols.get(0x8000_0000), Some("_start"));
+        assert_eq!(symbols.get(0x8000_0004), Some("loop"));
+        assert_eq!(symbols.get(0x8000_0008), None);
+    }
+
+    #[test]
+    fn load_elf() {
+        let data = build_elf();
+        let elf = Elf32::parse(&data).unwrap();
+        let mut ram = NaiveRAM::<0x1000>::new();
+        ram.write_bytes(0x10, &[0xff; 16]);
+        let image = elf.load_into(&mut ram, 0x8000_0000).unwrap();
+        assert_eq!(image.entry, 0x8000_0000);
+        assert_eq!(image.symbols.get(0x8000_0004), Some("loop"));
+
+        let mut buf = [0u8; 32];
+        ram.read_bytes(0, &mut buf);
+        assert_eq!(buf[..16], data[84..100]);
+        // The rest of the segment is zero-filled
+        assert_eq!(buf[16..], [0; 16]);
+
+        // The segment must fit in memory
+        let mut ram = NaiveRAM::<0x1000>::new();
+        assert_eq!(elf.load_into(&mut ram, 0x8000_0100).unwrap_err(),
+            ElfError::SegmentOutOfRange { addr: 0x8000_0000, size: 16 });
+        let mut ram = NaiveRAM::<0x1000>::new();
+        assert!(elf.load_into(&mut ram, 0x7fff_f000).is_err());
+
+        // An oversized segment fails without allocating all of 'memsz'
+        let mut data = build_elf();
+        data[72..76].copy_from_slice(&0xffff_0000u32.to_le_bytes());
+        data[60..68].copy_from_slice(&[0; 8]);
+        let elf = Elf32::parse(&data).unwrap();
+        let mut ram = NaiveRAM::<0x1000>::new();
+        assert_eq!(elf.load_into(&mut ram, 0).unwrap_err(),
+            ElfError::SegmentOutOfRange { addr: 0x10, size: 0x1000 });
+        let mut total = 0u64;
+        elf.load(|_, data| { total += data.len() as u64; Ok(()) }).unwrap();
+        assert_eq!(total, 0xffff_0000);
+    }
+
+    #[test]
+    fn parse_without_section_names() {
+        let mut data = build_elf();
+        data[50..52].copy_from_slice(&SHN_UNDEF.to_le_bytes());
+        let elf = Elf32::parse(&data).unwrap();
+        assert_eq!(elf.header.shstrndx, SHN_UNDEF);
+        assert!(elf.sections.iter().all(|s| s.name.is_empty()));
+        assert!(elf.section(".text").is_none());
+        assert_eq!(elf.symbols.len(), 4);
+        assert_eq!(elf.symbol_table().get(0x8000_0000), Some("_start"));
+    }
+
+    #[test]
+    fn parse_invalid() {
+        let mut data = build_elf();
+        assert_eq!(Elf32::parse(&data[..40]).err(), Some(ElfError::Truncated));
+        data[4] = 2;
+        assert_eq!(Elf32::parse(&data).err(), Some(ElfError::UnsupportedClass(2)));
+        data[4] = 1;
+        data[18] = 62;
+        assert_eq!(Elf32::parse(&data).err(), Some(ElfError::UnsupportedMachine(62)));
+        data[0] = 0;
+        assert_eq!(Elf32::parse(&data).err(), Some(ElfError::BadMagic));
+    }
+}
@@ -1,5 +1,6 @@
 
 pub mod rv32i;
+pub mod rv64i;
 
 
 pub trait InstructionSet {
@@ -7,9 +8,11 @@ pub trait InstructionSet {
     type Encoding;
     /// The set of unique types of instructions.
     type Inst;
+    /// The type of error produced when an encoding cannot be decoded.
+    type DecodeError;
 
     /// Decode a single instruction.
-    fn decode(enc: Self::Encoding) -> Self::Inst;
+    fn decode(enc: Self::Encoding) -> Result<Self::Inst, Self::DecodeError>;
 
 }
 
@@ -24,8 +27,11 @@ pub trait ArchitecturalState {
 
 /// Interface to an assembler for the instruction set.
 pub trait Assembler: InstructionSet {
+    /// The type of error produced when an instruction cannot be encoded.
+    type EncodeError;
+
     /// Encode an instruction.
-    fn encode(inst: Self::Inst) -> Self::Encoding;
+    fn encode(inst: Self::Inst) -> Result<Self::Encoding, Self::EncodeError>;
 }
 
 
@@ -1,8 +1,164 @@
 
 use crate::isa::*;
 
+pub mod asm;
+pub mod compressed;
+pub mod disasm;
+pub mod exec;
+pub mod fp;
+
+use fp::*;
+
+/// Fields in an encoding which may cause decoding to fail.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DecodeField { Opcode, Funct3, Funct7, Operand }
+impl std::fmt::Display for DecodeField {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let s = match self {
+            Self::Opcode => "opcode",
+            Self::Funct3 => "funct3",
+            Self::Funct7 => "funct7",
+            Self::Operand => "operand",
+        };
+        write!(f, "{}", s)
+    }
+}
+
+/// Reasons why an encoding cannot be decoded.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DecodeReason {
+    /// The encoding is reserved by the specification.
+    Reserved,
+    /// The encoding belongs to a custom (non-standard) extension.
+    Custom,
+    /// The encoding is defined, but not supported by this decoder.
+    Unsupported,
+    /// The encoding belongs to an extension which is not enabled.
+    Disabled,
+    /// The encoding is incomplete.
+    Truncated,
+}
+impl std::fmt::Display for DecodeReason {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let s = match self {
+            Self::Reserved    => "reserved encoding",
+            Self::Custom      => "custom extension",
+            Self::Unsupported => "unsupported encoding",
+            Self::Disabled    => "extension not enabled",
+            Self::Truncated   => "truncated encoding",
+        };
+        write!(f, "{}", s)
+    }
+}
+
+/// An error produced when some encoding cannot be decoded.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct DecodeError {
+    /// The raw encoding.
+    pub enc: u32,
+    /// The field which caused decoding to fail.
+    pub field: DecodeField,
+    /// Why decoding failed.
+    pub reason: DecodeReason,
+}
+impl DecodeError {
+    pub fn new(enc: u32, field: DecodeField, reason: DecodeReason) -> Self {
+        Self { enc, field, reason }
+    }
+}
+impl std::fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "invalid {} in 0x{:08x}: {}", self.field, self.enc, 
+            self.reason)
+    }
+}
+impl std::error::Error for DecodeError {}
+
+/// A set of standard extensions to the base integer ISA.
+#[repr(transparent)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RvExtensions(u32);
+impl RvExtensions {
+    /// No extensions (only the base integer ISA).
+    pub const NONE: Self = Self(0);
+    /// Integer multiplication and division
+    pub const M: Self = Self(1 << 0);
+    /// Atomic memory operations
+    pub const A: Self = Self(1 << 1);
+    /// Compressed (16-bit) instructions
+    pub const C: Self = Self(1 << 2);
+    /// Single-precision floating-point
+    pub const F: Self = Self(1 << 3);
+    /// Double-precision floating-point
+    pub const D: Self = Self(1 << 4);
+    /// All extensions supported by the decoder.
+    pub const ALL: Self = Self(
+        Self::M.0 | Self::A.0 | Self::C.0 | Self::F.0 | Self::D.0
+    );
+
+    /// Returns true if all extensions in 'other' are present.
+    pub fn contains(&self, other: Self) -> bool {
+        (self.0 & other.0) == other.0
+    }
+}
+impl Default for RvExtensions {
+    fn default() -> Self {
+        Self::ALL
+    }
+}
+impl std::ops::BitOr for RvExtensions {
+    type Output = Self;
+    fn bitor(self, rhs: Self) -> Self {
+        Self(self.0 | rhs.0)
+    }
+}
+
+/// Reasons why an instruction cannot be encoded.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum EncodeReason {
+    /// The immediate does not fit in the available bits.
+    OutOfRange,
+    /// The immediate has low bits which cannot be represented.
+    Misaligned,
+    /// The instruction does not exist in the target instruction set.
+    Unsupported,
+}
+impl std::fmt::Display for EncodeReason {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let s = match self {
+            Self::OutOfRange => "immediate out of range",
+            Self::Misaligned => "misaligned immediate",
+            Self::Unsupported => "unsupported instruction",
+        };
+        write!(f, "{}", s)
+    }
+}
+
+/// An error produced when some instruction cannot be encoded.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct EncodeError {
+    /// The format of the offending encoding.
+    pub format: InstFormat,
+    /// The offending immediate value.
+    pub imm: i64,
+    /// Why encoding failed.
+    pub reason: EncodeReason,
+}
+impl EncodeError {
+    pub fn new(format: InstFormat, imm: i64, reason: EncodeReason) -> Self {
+        Self { format, imm, reason }
+    }
+}
+impl std::fmt::Display for EncodeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} for {:?}-type encoding: {}", self.reason, self.format,
+            self.imm)
+    }
+}
+impl std::error::Error for EncodeError {}
+
 /// RV32I instruction formats.
-#[derive(Debug)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum InstFormat { R, I, S, B, U, J }
 
 /// RV32I opcodes.
@@ -16,14 +172,14 @@ pub enum Opcode {
     MISC_MEM   = 0b00011, // [fence, fence.i]
     OP_IMM     = 0b00100, // [addi, slti, sltiu, xori, ori, andi]
     AUIPC      = 0b00101, 
-    OP_IMM_32  = 0b00110,
+    OP_IMM_32  = 0b00110, // [addiw, slliw, srliw, sraiw]
     STORE      = 0b01000, // [sb, sh, sw]
     STORE_FP   = 0b01001,
     CUSTOM_1   = 0b01010,
-    AMO        = 0b01011,
-    OP         = 0b01100, // [add, sub, sll, slt, sltu, xor, srl, sra, or, and]
+    AMO        = 0b01011, // [lr.w, sc.w, amo*.w]
+    OP         = 0b01100, // [add, sub, sll, slt, sltu, xor, srl, sra, or, and, mul*, div*, rem*]
     LUI        = 0b01101,
-    OP_32      = 0b01110,
+    OP_32      = 0b01110, // [addw, subw, sllw, srlw, sraw]
     MADD       = 0b10000,
     MSUB       = 0b10001,
     NMSUB      = 0b10010,
@@ -35,63 +191,105 @@ pub enum Opcode {
     JALR       = 0b11001,
     RES_1      = 0b11010,
     JAL        = 0b11011,
-    SYSTEM     = 0b11100,
+    SYSTEM     = 0b11100, // [ecall, ebreak, mret, sret, wfi, csrr*]
     RES_2      = 0b11101,
     CUSTOM_3   = 0b11110,
 }
-impl From<u32> for Opcode {
-    fn from(x: u32) -> Self {
+impl TryFrom<u32> for Opcode {
+    type Error = DecodeReason;
+    fn try_from(x: u32) -> Result<Self, Self::Error> {
         match x {
-         0b00000 => Self::LOAD,
-         0b00001 => Self::LOAD_FP,
-         0b00010 => Self::CUSTOM_0,
-         0b00011 => Self::MISC_MEM,
-         0b00100 => Self::OP_IMM,
-         0b00101 => Self::AUIPC,
-         0b00110 => Self::OP_IMM_32,
-         0b01000 => Self::STORE,
-         0b01001 => Self::STORE_FP,
-         0b01010 => Self::CUSTOM_1,
-         0b01011 => Self::AMO,
-         0b01100 => Self::OP,
-         0b01101 => Self::LUI,
-         0b01110 => Self::OP_32,
-         0b10000 => Self::MADD,
-         0b10001 => Self::MSUB,
-         0b10010 => Self::NMSUB,
-         0b10011 => Self::NMADD,
-         0b10100 => Self::OP_FP,
-         0b10101 => Self::RES_0,
-         0b10110 => Self::CUSTOM_2,
-         0b11000 => Self::BRANCH,
-         0b11001 => Self::JALR,
-         0b11010 => Self::RES_1,
-         0b11011 => Self::JAL,
-         0b11100 => Self::SYSTEM,
-         0b11101 => Self::RES_2,
-         0b11110 => Self::CUSTOM_3,
-         _ => unimplemented!(),
+         0b00000 => Ok(Self::LOAD),
+         0b00001 => Ok(Self::LOAD_FP),
+         0b00010 => Ok(Self::CUSTOM_0),
+         0b00011 => Ok(Self::MISC_MEM),
+         0b00100 => Ok(Self::OP_IMM),
+         0b00101 => Ok(Self::AUIPC),
+         0b00110 => Ok(Self::OP_IMM_32),
+         0b01000 => Ok(Self::STORE),
+         0b01001 => Ok(Self::STORE_FP),
+         0b01010 => Ok(Self::CUSTOM_1),
+         0b01011 => Ok(Self::AMO),
+         0b01100 => Ok(Self::OP),
+         0b01101 => Ok(Self::LUI),
+         0b01110 => Ok(Self::OP_32),
+         0b10000 => Ok(Self::MADD),
+         0b10001 => Ok(Self::MSUB),
+         0b10010 => Ok(Self::NMSUB),
+         0b10011 => Ok(Self::NMADD),
+         0b10100 => Ok(Self::OP_FP),
+         0b10101 => Ok(Self::RES_0),
+         0b10110 => Ok(Self::CUSTOM_2),
+         0b11000 => Ok(Self::BRANCH),
+         0b11001 => Ok(Self::JALR),
+         0b11010 => Ok(Self::RES_1),
+         0b11011 => Ok(Self::JAL),
+         0b11100 => Ok(Self::SYSTEM),
+         0b11101 => Ok(Self::RES_2),
+         0b11110 => Ok(Self::CUSTOM_3),
+         // 0b00111, 0b01111, 0b10111, and 0b11111 select encodings which
+         // are longer than 32 bits.
+         _ => Err(DecodeReason::Unsupported),
         }
     }
 }
 
 /// ALU opcodes for I-type encodings.
-#[derive(Debug)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum RvALUOpImm { Addi, Slti, Sltiu, Xori, Ori, Andi, Slli, Srli, Srai }
-impl From<(u32, u32)> for RvALUOpImm {
-    fn from(x: (u32, u32)) -> Self {
+impl TryFrom<(u32, u32)> for RvALUOpImm {
+    type Error = DecodeReason;
+    fn try_from(x: (u32, u32)) -> Result<Self, Self::Error> {
         match x {
-            (0b000, _) => Self::Addi,
-            (0b010, _) => Self::Slti,
-            (0b011, _) => Self::Sltiu,
-            (0b100, _) => Self::Xori,
-            (0b110, _) => Self::Ori,
-            (0b111, _) => Self::Andi,
+            (0b000, _) => Ok(Self::Addi),
+            (0b010, _) => Ok(Self::Slti),
+            (0b011, _) => Ok(Self::Sltiu),
+            (0b100, _) => Ok(Self::Xori),
+            (0b110, _) => Ok(Self::Ori),
+            (0b111, _) => Ok(Self::Andi),
+
+            (0b001, 0b0000000) => Ok(Self::Slli),
+            (0b101, 0b0000000) => Ok(Self::Srli),
+            (0b101, 0b0100000) => Ok(Self::Srai),
+            _ => Err(DecodeReason::Reserved),
+        }
+    }
+}
+impl RvALUOpImm {
+    /// Returns true for shift operations (which take a shift amount).
+    pub fn is_shift(&self) -> bool {
+        matches!(self, Self::Slli | Self::Srli | Self::Srai)
+    }
 
-            (0b001, 0b0000000) => Self::Slli,
-            (0b101, 0b0000000) => Self::Srli,
-            (0b101, 0b0100000) => Self::Srai,
-            _ => unimplemented!("ALU op f3={:03b} f7={:07b}", x.0, x.1),
+    /// Returns the 'funct3' and 'funct7' fields for this operation.
+    pub fn funct(&self) -> (u32, u32) {
+        match self {
+            Self::Addi  => (0b000, 0b0000000),
+            Self::Slti  => (0b010, 0b0000000),
+            Self::Sltiu => (0b011, 0b0000000),
+            Self::Xori  => (0b100, 0b0000000),
+            Self::Ori   => (0b110, 0b0000000),
+            Self::Andi  => (0b111, 0b0000000),
+            Self::Slli  => (0b001, 0b0000000),
+            Self::Srli  => (0b101, 0b0000000),
+            Self::Srai  => (0b101, 0b0100000),
+        }
+    }
+
+    /// Compute the result of this operation with a sign-extended immediate
+    /// (or a shift amount).
+    pub fn eval(&self, a: u32, imm: i32) -> u32 {
+        let b = imm as u32;
+        match self {
+            Self::Addi  => a.wrapping_add(b),
+            Self::Slti  => ((a as i32) < imm) as u32,
+            Self::Sltiu => (a < b) as u32,
+            Self::Xori  => a ^ b,
+            Self::Ori   => a | b,
+            Self::Andi  => a & b,
+            Self::Slli  => a << (b & 0x1f),
+            Self::Srli  => a >> (b & 0x1f),
+            Self::Srai  => ((a as i32) >> (b & 0x1f)) as u32,
         }
     }
 }
@@ -115,25 +313,59 @@ impl std::fmt::Display for RvALUOpImm {
 
 
 /// ALU opcodes for R-type encodings.
-#[derive(Debug)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum RvALUOp { Add, Sub, Sll, Slt, Sltu, Xor, Srl, Sra, Or, And }
-impl From<(u32, u32)> for RvALUOp {
-    fn from(x: (u32, u32)) -> Self {
+impl TryFrom<(u32, u32)> for RvALUOp {
+    type Error = DecodeReason;
+    fn try_from(x: (u32, u32)) -> Result<Self, Self::Error> {
         match x {
-            (0b000, 0b0000000) => Self::Add,
-            (0b000, 0b0100000) => Self::Sub,
+            (0b000, 0b0000000) => Ok(Self::Add),
+            (0b000, 0b0100000) => Ok(Self::Sub),
+
+            (0b001, 0b0000000) => Ok(Self::Sll),
+            (0b010, 0b0000000) => Ok(Self::Slt),
+            (0b011, 0b0000000) => Ok(Self::Sltu),
+            (0b100, 0b0000000) => Ok(Self::Xor),
 
-            (0b001, 0b0000000) => Self::Sll,
-            (0b010, 0b0000000) => Self::Slt,
-            (0b011, 0b0000000) => Self::Sltu,
-            (0b100, 0b0000000) => Self::Xor,
+            (0b101, 0b0000000) => Ok(Self::Srl),
+            (0b101, 0b0100000) => Ok(Self::Sra),
 
-            (0b101, 0b0000000) => Self::Srl,
-            (0b101, 0b0100000) => Self::Sra,
+            (0b110, 0b0000000) => Ok(Self::Or),
+            (0b111, 0b0000000) => Ok(Self::And),
+            _ => Err(DecodeReason::Reserved),
+        }
+    }
+}
+impl RvALUOp {
+    /// Returns the 'funct3' and 'funct7' fields for this operation.
+    pub fn funct(&self) -> (u32, u32) {
+        match self {
+            Self::Add  => (0b000, 0b0000000),
+            Self::Sub  => (0b000, 0b0100000),
+            Self::Sll  => (0b001, 0b0000000),
+            Self::Slt  => (0b010, 0b0000000),
+            Self::Sltu => (0b011, 0b0000000),
+            Self::Xor  => (0b100, 0b0000000),
+            Self::Srl  => (0b101, 0b0000000),
+            Self::Sra  => (0b101, 0b0100000),
+            Self::Or   => (0b110, 0b0000000),
+            Self::And  => (0b111, 0b0000000),
+        }
+    }
 
-            (0b110, 0b0000000) => Self::Or,
-            (0b111, 0b0000000) => Self::And,
-            _ => unimplemented!("ALU op f3={:03b} f7[1]={}", x.0, x.1),
+    /// Compute the result of this operation.
+    pub fn eval(&self, a: u32, b: u32) -> u32 {
+        match self {
+            Self::Add  => a.wrapping_add(b),
+            Self::Sub  => a.wrapping_sub(b),
+            Self::Sll  => a << (b & 0x1f),
+            Self::Slt  => ((a as i32) < (b as i32)) as u32,
+            Self::Sltu => (a < b) as u32,
+            Self::Xor  => a ^ b,
+            Self::Srl  => a >> (b & 0x1f),
+            Self::Sra  => ((a as i32) >> (b & 0x1f)) as u32,
+            Self::Or   => a | b,
+            Self::And  => a & b,
         }
     }
 }
@@ -156,18 +388,210 @@ impl std::fmt::Display for RvALUOp {
 }
 
 
-/// RV32I load/store width encodings.
-#[derive(Debug)]
-pub enum RvWidth { Byte, Half, Word, ByteUnsigned, HalfUnsigned }
-impl From<u32> for RvWidth {
+/// RV32M multiply/divide opcodes for R-type encodings.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RvMulOp { Mul, Mulh, Mulhsu, Mulhu, Div, Divu, Rem, Remu }
+impl From<u32> for RvMulOp {
     fn from(x: u32) -> Self {
+        match x & 0b111 {
+            0b000 => Self::Mul,
+            0b001 => Self::Mulh,
+            0b010 => Self::Mulhsu,
+            0b011 => Self::Mulhu,
+            0b100 => Self::Div,
+            0b101 => Self::Divu,
+            0b110 => Self::Rem,
+            _     => Self::Remu,
+        }
+    }
+}
+impl RvMulOp {
+    /// The 'funct7' field shared by all RV32M operations.
+    pub const FUNCT7: u32 = 0b0000001;
+
+    /// Returns the 'funct3' field for this operation.
+    pub fn funct3(&self) -> u32 {
+        match self {
+            Self::Mul    => 0b000,
+            Self::Mulh   => 0b001,
+            Self::Mulhsu => 0b010,
+            Self::Mulhu  => 0b011,
+            Self::Div    => 0b100,
+            Self::Divu   => 0b101,
+            Self::Rem    => 0b110,
+            Self::Remu   => 0b111,
+        }
+    }
+
+    /// Compute the result of this operation.
+    ///
+    /// Division by zero and signed overflow do not trap: division by zero
+    /// yields all ones (and the remainder yields the dividend), and 
+    /// dividing the most-negative integer by -1 yields the dividend (and 
+    /// the remainder yields zero).
+    pub fn eval(&self, a: u32, b: u32) -> u32 {
+        let (sa, sb) = (a as i32, b as i32);
+        match self {
+            Self::Mul    => a.wrapping_mul(b),
+            Self::Mulh   => ((sa as i64 * sb as i64) >> 32) as u32,
+            Self::Mulhsu => ((sa as i64 * b as i64) >> 32) as u32,
+            Self::Mulhu  => ((a as u64 * b as u64) >> 32) as u32,
+            Self::Div    => {
+                if b == 0 { u32::MAX } else { sa.wrapping_div(sb) as u32 }
+            },
+            Self::Divu   => a.checked_div(b).unwrap_or(u32::MAX),
+            Self::Rem    => {
+                if b == 0 { a } else { sa.wrapping_rem(sb) as u32 }
+            },
+            Self::Remu   => a.checked_rem(b).unwrap_or(a),
+        }
+    }
+}
+impl std::fmt::Display for RvMulOp {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let s = match self {
+            Self::Mul    => "mul",
+            Self::Mulh   => "mulh",
+            Self::Mulhsu => "mulhsu",
+            Self::Mulhu  => "mulhu",
+            Self::Div    => "div",
+            Self::Divu   => "divu",
+            Self::Rem    => "rem",
+            Self::Remu   => "remu",
+        };
+        write!(f, "{}", s)
+    }
+}
+
+
+/// RV32A atomic memory opcodes.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RvAmoOp { 
+    Lr, Sc, Swap, Add, Xor, And, Or, Min, Max, Minu, Maxu 
+}
+impl TryFrom<u32> for RvAmoOp {
+    type Error = DecodeReason;
+    fn try_from(x: u32) -> Result<Self, Self::Error> {
         match x {
-            0b000 => Self::Byte,
-            0b001 => Self::Half,
-            0b010 => Self::Word,
-            0b100 => Self::ByteUnsigned,
-            0b101 => Self::HalfUnsigned,
-            _ => unimplemented!(),
+            0b00010 => Ok(Self::Lr),
+            0b00011 => Ok(Self::Sc),
+            0b00001 => Ok(Self::Swap),
+            0b00000 => Ok(Self::Add),
+            0b00100 => Ok(Self::Xor),
+            0b01100 => Ok(Self::And),
+            0b01000 => Ok(Self::Or),
+            0b10000 => Ok(Self::Min),
+            0b10100 => Ok(Self::Max),
+            0b11000 => Ok(Self::Minu),
+            0b11100 => Ok(Self::Maxu),
+            _ => Err(DecodeReason::Reserved),
+        }
+    }
+}
+impl RvAmoOp {
+    /// Returns the 'funct5' field for this operation.
+    pub fn funct5(&self) -> u32 {
+        match self {
+            Self::Lr   => 0b00010,
+            Self::Sc   => 0b00011,
+            Self::Swap => 0b00001,
+            Self::Add  => 0b00000,
+            Self::Xor  => 0b00100,
+            Self::And  => 0b01100,
+            Self::Or   => 0b01000,
+            Self::Min  => 0b10000,
+            Self::Max  => 0b10100,
+            Self::Minu => 0b11000,
+            Self::Maxu => 0b11100,
+        }
+    }
+
+    /// Compute the value written back to memory by this operation, given 
+    /// the 'old' value in memory and the 'src' value from 'rs2'.
+    ///
+    /// 'lr' leaves memory unchanged, and 'sc' writes 'src' (when it
+    /// succeeds).
+    pub fn eval(&self, old: u32, src: u32) -> u32 {
+        match self {
+            Self::Lr   => old,
+            Self::Sc   => src,
+            Self::Swap => src,
+            Self::Add  => old.wrapping_add(src),
+            Self::Xor  => old ^ src,
+            Self::And  => old & src,
+            Self::Or   => old | src,
+            Self::Min  => (old as i32).min(src as i32) as u32,
+            Self::Max  => (old as i32).max(src as i32) as u32,
+            Self::Minu => old.min(src),
+            Self::Maxu => old.max(src),
+        }
+    }
+}
+impl std::fmt::Display for RvAmoOp {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let s = match self {
+            Self::Lr   => "lr",
+            Self::Sc   => "sc",
+            Self::Swap => "amoswap",
+            Self::Add  => "amoadd",
+            Self::Xor  => "amoxor",
+            Self::And  => "amoand",
+            Self::Or   => "amoor",
+            Self::Min  => "amomin",
+            Self::Max  => "amomax",
+            Self::Minu => "amominu",
+            Self::Maxu => "amomaxu",
+        };
+        write!(f, "{}", s)
+    }
+}
+
+
+/// RV32I load/store width encodings.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RvWidth { 
+    Byte, Half, Word, ByteUnsigned, HalfUnsigned, 
+    /// Doubleword (RV64I only)
+    Double, 
+    /// Unsigned word (RV64I only)
+    WordUnsigned,
+}
+impl TryFrom<u32> for RvWidth {
+    type Error = DecodeReason;
+    fn try_from(x: u32) -> Result<Self, Self::Error> {
+        match x {
+            0b000 => Ok(Self::Byte),
+            0b001 => Ok(Self::Half),
+            0b010 => Ok(Self::Word),
+            0b011 => Ok(Self::Double),
+            0b100 => Ok(Self::ByteUnsigned),
+            0b101 => Ok(Self::HalfUnsigned),
+            0b110 => Ok(Self::WordUnsigned),
+            _ => Err(DecodeReason::Reserved),
+        }
+    }
+}
+impl RvWidth {
+    /// Returns the 'funct3' field for this width.
+    pub fn funct3(&self) -> u32 {
+        match self {
+            Self::Byte => 0b000,
+            Self::Half => 0b001,
+            Self::Word => 0b010,
+            Self::ByteUnsigned => 0b100,
+            Self::HalfUnsigned => 0b101,
+            Self::Double => 0b011,
+            Self::WordUnsigned => 0b110,
+        }
+    }
+
+    /// Returns the size of an access (in bytes).
+    pub fn size(&self) -> usize {
+        match self {
+            Self::Byte | Self::ByteUnsigned => 1,
+            Self::Half | Self::HalfUnsigned => 2,
+            Self::Word | Self::WordUnsigned => 4,
+            Self::Double => 8,
         }
     }
 }
@@ -179,6 +603,8 @@ impl std::fmt::Display for RvWidth {
             Self::Word => "w",
             Self::ByteUnsigned => "bu",
             Self::HalfUnsigned => "hu",
+            Self::Double => "d",
+            Self::WordUnsigned => "wu",
         };
         write!(f, "{}", s)
     }
@@ -186,20 +612,44 @@ impl std::fmt::Display for RvWidth {
 
 
 /// RV32I branch opcodes.
-#[derive(Debug)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum RvBranchOp { Eq, Ne, Lt, Ge, Ltu, Geu }
-impl From<u32> for RvBranchOp {
-    fn from(x: u32) -> Self {
+impl TryFrom<u32> for RvBranchOp {
+    type Error = DecodeReason;
+    fn try_from(x: u32) -> Result<Self, Self::Error> {
         match x {
-            0b000 => Self::Eq,
-            0b001 => Self::Ne,
-            0b010 => unimplemented!(),
-            0b011 => unimplemented!(),
-            0b100 => Self::Lt,
-            0b101 => Self::Ge,
-            0b110 => Self::Ltu,
-            0b111 => Self::Geu,
-            _ => unimplemented!(),
+            0b000 => Ok(Self::Eq),
+            0b001 => Ok(Self::Ne),
+            0b100 => Ok(Self::Lt),
+            0b101 => Ok(Self::Ge),
+            0b110 => Ok(Self::Ltu),
+            0b111 => Ok(Self::Geu),
+            _ => Err(DecodeReason::Reserved),
+        }
+    }
+}
+impl RvBranchOp {
+    /// Returns the 'funct3' field for this operation.
+    pub fn funct3(&self) -> u32 {
+        match self {
+            Self::Eq  => 0b000,
+            Self::Ne  => 0b001,
+            Self::Lt  => 0b100,
+            Self::Ge  => 0b101,
+            Self::Ltu => 0b110,
+            Self::Geu => 0b111,
+        }
+    }
+
+    /// Returns true if the branch is taken.
+    pub fn eval(&self, a: u32, b: u32) -> bool {
+        match self {
+            Self::Eq  => a == b,
+            Self::Ne  => a != b,
+            Self::Lt  => (a as i32) < (b as i32),
+            Self::Ge  => (a as i32) >= (b as i32),
+            Self::Ltu => a < b,
+            Self::Geu => a >= b,
         }
     }
 }
@@ -212,17 +662,185 @@ impl std::fmt::Display for RvBranchOp {
             Self::Ge => "ge",
             Self::Ltu => "ltu",
             Self::Geu => "geu",
-            _ => unimplemented!(),
         };
         write!(f, "{}", s)
     }
 }
 
 
-/// 
+/// Zicsr opcodes.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RvCsrOp { Rw, Rs, Rc }
+impl TryFrom<u32> for RvCsrOp {
+    type Error = DecodeReason;
+    fn try_from(x: u32) -> Result<Self, Self::Error> {
+        match x {
+            0b01 => Ok(Self::Rw),
+            0b10 => Ok(Self::Rs),
+            0b11 => Ok(Self::Rc),
+            _ => Err(DecodeReason::Reserved),
+        }
+    }
+}
+impl RvCsrOp {
+    /// Returns the low two bits of the 'funct3' field for this operation.
+    pub fn funct3(&self) -> u32 {
+        match self {
+            Self::Rw => 0b01,
+            Self::Rs => 0b10,
+            Self::Rc => 0b11,
+        }
+    }
+}
+impl std::fmt::Display for RvCsrOp {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let s = match self {
+            Self::Rw => "rw",
+            Self::Rs => "rs",
+            Self::Rc => "rc",
+        };
+        write!(f, "{}", s)
+    }
+}
+
+
+/// A set of memory operations ordered by a fence.
+///
+/// Bits [3:0] correspond to device input (I), device output (O), 
+/// memory reads (R), and memory writes (W), respectively.
+#[repr(transparent)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RvFenceSet(u32);
+impl RvFenceSet {
+    pub const I: u32 = 0b1000;
+    pub const O: u32 = 0b0100;
+    pub const R: u32 = 0b0010;
+    pub const W: u32 = 0b0001;
+
+    pub fn new(bits: u32) -> Self {
+        assert!(bits < 16);
+        Self(bits)
+    }
+    pub fn val(&self) -> u32 {
+        self.0
+    }
+}
+impl std::fmt::Display for RvFenceSet {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.0 == 0 {
+            return write!(f, "0");
+        }
+        for (bit, c) in [(Self::I, 'i'), (Self::O, 'o'), 
+                         (Self::R, 'r'), (Self::W, 'w')] 
+        {
+            if self.0 & bit != 0 {
+                write!(f, "{}", c)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+
+/// A control and status register (CSR) number.
 #[repr(transparent)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Csr(u32);
+impl Csr {
+    /// Names for some well-known CSRs.
+    pub const NAMES: &'static [(u32, &'static str)] = &[
+        // Unprivileged floating-point CSRs
+        (0x001, "fflags"),
+        (0x002, "frm"),
+        (0x003, "fcsr"),
+
+        // Unprivileged counters/timers
+        (0xc00, "cycle"),
+        (0xc01, "time"),
+        (0xc02, "instret"),
+        (0xc80, "cycleh"),
+        (0xc81, "timeh"),
+        (0xc82, "instreth"),
+
+        // Supervisor CSRs
+        (0x100, "sstatus"),
+        (0x104, "sie"),
+        (0x105, "stvec"),
+        (0x106, "scounteren"),
+        (0x140, "sscratch"),
+        (0x141, "sepc"),
+        (0x142, "scause"),
+        (0x143, "stval"),
+        (0x144, "sip"),
+        (0x180, "satp"),
+
+        // Machine CSRs
+        (0xf11, "mvendorid"),
+        (0xf12, "marchid"),
+        (0xf13, "mimpid"),
+        (0xf14, "mhartid"),
+        (0x300, "mstatus"),
+        (0x301, "misa"),
+        (0x302, "medeleg"),
+        (0x303, "mideleg"),
+        (0x304, "mie"),
+        (0x305, "mtvec"),
+        (0x306, "mcounteren"),
+        (0x310, "mstatush"),
+        (0x340, "mscratch"),
+        (0x341, "mepc"),
+        (0x342, "mcause"),
+        (0x343, "mtval"),
+        (0x344, "mip"),
+        (0xb00, "mcycle"),
+        (0xb02, "minstret"),
+        (0xb80, "mcycleh"),
+        (0xb82, "minstreth"),
+    ];
+
+    pub fn new(num: u32) -> Self {
+        assert!(num < 4096);
+        Self(num)
+    }
+    pub fn val(&self) -> u32 {
+        self.0
+    }
+
+    /// Returns the name of this CSR, if it is well-known.
+    pub fn name(&self) -> Option<&'static str> {
+        Self::NAMES.iter().find(|(num, _)| *num == self.0)
+            .map(|(_, name)| *name)
+    }
+
+    /// Look up a well-known CSR by name.
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::NAMES.iter().find(|(_, n)| *n == name)
+            .map(|(num, _)| Self(*num))
+    }
+}
+impl std::fmt::Display for Csr {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.name() {
+            Some(name) => write!(f, "{}", name),
+            None => write!(f, "0x{:03x}", self.0),
+        }
+    }
+}
+
+
+/// A general-purpose register.
+#[repr(transparent)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub struct Reg(u32);
 impl Reg {
+    /// Names for each register in the standard calling convention.
+    pub const ABI_NAMES: [&'static str; 32] = [
+        "zero", "ra", "sp", "gp", "tp", "t0", "t1", "t2",
+        "s0",   "s1", "a0", "a1", "a2", "a3", "a4", "a5",
+        "a6",   "a7", "s2", "s3", "s4", "s5", "s6", "s7",
+        "s8",   "s9", "s10", "s11", "t3", "t4", "t5", "t6",
+    ];
+
     pub fn new(idx: u32) -> Self {
         assert!(idx < 32);
         Self(idx)
@@ -230,6 +848,30 @@ impl Reg {
     pub fn val(&self) -> u32 { 
         self.0 
     }
+
+    /// Returns the calling convention name for this register.
+    pub fn abi_name(&self) -> &'static str {
+        Self::ABI_NAMES[self.0 as usize]
+    }
+
+    /// Look up a register by numeric ('x2') or calling convention ('sp')
+    /// name.
+    pub fn from_name(name: &str) -> Option<Self> {
+        if name == "fp" {
+            return Some(Self(8));
+        }
+        if let Some(idx) = name.strip_prefix('x') {
+            if idx.is_empty() || !idx.bytes().all(|c| c.is_ascii_digit()) {
+                return None;
+            }
+            return match idx.parse::<u32>() {
+                Ok(idx) if idx < 32 => Some(Self(idx)),
+                _ => None,
+            };
+        }
+        Self::ABI_NAMES.iter().position(|n| *n == name)
+            .map(|idx| Self(idx as u32))
+    }
 }
 impl std::fmt::Display for Reg {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
@@ -237,14 +879,26 @@ impl std::fmt::Display for Reg {
     }
 }
 
-
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum Instr {
     /// ALU operation
     Op { rd: Reg, rs1: Reg, rs2: Reg, alu_op: RvALUOp },
 
+    /// Multiply/divide operation (RV32M)
+    MulDiv { rd: Reg, rs1: Reg, rs2: Reg, mul_op: RvMulOp },
+
+    /// Atomic memory operation (RV32A)
+    Amo { rd: Reg, rs1: Reg, rs2: Reg, aq: bool, rl: bool, amo_op: RvAmoOp },
+
     /// ALU operation with immediate
     OpImm { rd: Reg, rs1: Reg, simm: i32, alu_op: RvALUOpImm },
 
+    /// ALU operation on the low 32 bits (RV64I)
+    Op32 { rd: Reg, rs1: Reg, rs2: Reg, alu_op: RvALUOp },
+
+    /// ALU operation with immediate on the low 32 bits (RV64I)
+    OpImm32 { rd: Reg, rs1: Reg, simm: i32, alu_op: RvALUOpImm },
+
     /// Memory load
     Load { rd: Reg, rs1: Reg, simm: i32, width: RvWidth },
 
@@ -265,43 +919,92 @@ pub enum Instr {
 
     /// Conditional branch
     Branch { rs1: Reg, rs2: Reg, simm: i32, brn_op: RvBranchOp },
+
+    /// Memory ordering fence
+    Fence { pred: RvFenceSet, succ: RvFenceSet },
+
+    /// Memory ordering fence with total store ordering (ie. 'fence.tso')
+    FenceTso,
+
+    /// Instruction fetch fence
+    FenceI,
+
+    /// Environment call
+    Ecall,
+
+    /// Environment breakpoint
+    Ebreak,
+
+    /// Return from machine-mode trap
+    Mret,
+
+    /// Return from supervisor-mode trap
+    Sret,
+
+    /// Wait for interrupt
+    Wfi,
+
+    /// CSR access
+    Csr { rd: Reg, rs1: Reg, csr: Csr, csr_op: RvCsrOp },
+
+    /// CSR access with immediate
+    CsrImm { rd: Reg, uimm: u32, csr: Csr, csr_op: RvCsrOp },
+
+    /// Floating-point load (RV32F/RV32D)
+    FpLoad { rd: FReg, rs1: Reg, simm: i32, fmt: RvFpFmt },
+
+    /// Floating-point store (RV32F/RV32D)
+    FpStore { rs1: Reg, rs2: FReg, simm: i32, fmt: RvFpFmt },
+
+    /// Floating-point fused multiply-add
+    FpFma { 
+        rd: FReg, rs1: FReg, rs2: FReg, rs3: FReg, 
+        fmt: RvFpFmt, rm: RvRoundingMode, fma_op: RvFmaOp 
+    },
+
+    /// Floating-point arithmetic
+    FpArith { 
+        rd: FReg, rs1: FReg, rs2: FReg, 
+        fmt: RvFpFmt, rm: RvRoundingMode, fp_op: RvFpArithOp 
+    },
+
+    /// Floating-point square root
+    FpSqrt { rd: FReg, rs1: FReg, fmt: RvFpFmt, rm: RvRoundingMode },
+
+    /// Floating-point sign injection, minimum and maximum
+    FpOp { rd: FReg, rs1: FReg, rs2: FReg, fmt: RvFpFmt, fp_op: RvFpOp },
+
+    /// Floating-point comparison
+    FpCmp { rd: Reg, rs1: FReg, rs2: FReg, fmt: RvFpFmt, cmp_op: RvFpCmpOp },
+
+    /// Floating-point classification
+    FpClass { rd: Reg, rs1: FReg, fmt: RvFpFmt },
+
+    /// Conversion from floating-point to a 32-bit integer
+    FpCvtToInt { 
+        rd: Reg, rs1: FReg, fmt: RvFpFmt, rm: RvRoundingMode, unsigned: bool 
+    },
+
+    /// Conversion from a 32-bit integer to floating-point
+    FpCvtFromInt { 
+        rd: FReg, rs1: Reg, fmt: RvFpFmt, rm: RvRoundingMode, unsigned: bool 
+    },
+
+    /// Conversion between floating-point formats
+    FpCvt { 
+        rd: FReg, rs1: FReg, fmt: RvFpFmt, src_fmt: RvFpFmt, 
+        rm: RvRoundingMode 
+    },
+
+    /// Move single-precision bits to an integer register
+    FpMvToInt { rd: Reg, rs1: FReg },
+
+    /// Move single-precision bits from an integer register
+    FpMvFromInt { rd: FReg, rs1: Reg },
 }
 impl std::fmt::Display for Instr {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        match self {
-            Self::Op { rd, rs1, rs2, alu_op } => {
-                let alu_op = format!("{}", alu_op);
-                write!(f, "{:6} {}, {}, {}", alu_op, rd, rs1, rs2)
-            },
-            Self::OpImm { rd, rs1, simm, alu_op } => {
-                let alu_op = format!("{}", alu_op);
-                write!(f, "{:6} {}, {}, {}", alu_op, rd, rs1, simm)
-            },
-            Self::Load { rd, rs1, simm, width } => {
-                let inst = format!("l{}", width);
-                write!(f, "{:6} {}, {}({})", inst, rd, simm, rs1)
-            },
-            Self::Jalr { rd, rs1, simm } => {
-                write!(f, "{:6} {}, {}({})", "jalr", rd, simm, rs1)
-            },
-            Self::AuiPc { rd, uimm } => {
-                write!(f, "{:6} {}, 0x{:08x}", "auipc", rd, uimm)
-            },
-            Self::Lui { rd, uimm } => {
-                write!(f, "{:6} {}, 0x{:08x}", "lui", rd, uimm)
-            },
-            Self::Store { rs1, rs2, simm, width } => {
-                let inst = format!("s{}", width);
-                write!(f, "{:6} {}, {}({})", inst, rs2, simm, rs1)
-            },
-            Self::Jal { rd, simm } => {
-                write!(f, "{:6} {}, {}", "jal", rd, simm)
-            },
-            Self::Branch { rs1, rs2, simm, brn_op } => {
-                let inst = format!("b{}", brn_op);
-                write!(f, "{:6} {}, {}, {}", inst, rs1, rs2, simm)
-            },
-        }
+        write!(f, "{}", self.display(disasm::DisasmOptions::default()))
     }
 }
 
@@ -313,6 +1016,7 @@ pub struct Rv32;
 impl Rv32 {
 
     // Bitmasks for fixed fields
+    const MASK_LEN_0:  u32 = 0b0000000_00000_00000_000_00000_0000011;
     const MASK_OP_2:   u32 = 0b0000000_00000_00000_000_00000_1111100;
     const MASK_RD_7:   u32 = 0b0000000_00000_00000_000_11111_0000000;
     const MASK_F3_12:  u32 = 0b0000000_00000_00000_111_00000_0000000;
@@ -349,13 +1053,18 @@ impl Rv32 {
     }
 
     /// Build an immediate for I-type encodings.
-    fn build_i_imm(enc: u32) -> i32 {
+    pub(crate) fn build_i_imm(enc: u32) -> i32 {
         let imm = (enc & Self::MASK_I_IMM12_20_31) >> 20;
         Self::sext32(imm, 12)
     }
 
+    /// Build an unsigned immediate for I-type encodings.
+    fn build_i_uimm(enc: u32) -> u32 {
+        (enc & Self::MASK_I_IMM12_20_31) >> 20
+    }
+
     /// Build an immediate for S-type encodings.
-    fn build_s_imm(enc: u32) -> i32 {
+    pub(crate) fn build_s_imm(enc: u32) -> i32 {
         let imm = (
                ((enc & Self::MASK_S_IMM5_07_11) >>  7) 
             | (((enc & Self::MASK_S_IMM7_25_31) >> 25) << 5)
@@ -390,17 +1099,104 @@ impl Rv32 {
         );
         Self::sext32(imm, 20) << 1
     }
-}
 
+    /// Check that a signed immediate fits in 'bits' and is a multiple of
+    /// 'align' bytes.
+    pub(crate) fn check_simm(format: InstFormat, imm: i32, bits: u32,
+        align: i32) -> Result<(), EncodeError> 
+    {
+        let min = -(1i64 << (bits - 1));
+        let max =  (1i64 << (bits - 1)) - 1;
+        if (imm as i64) < min || (imm as i64) > max {
+            return Err(EncodeError::new(format, imm as i64, 
+                EncodeReason::OutOfRange));
+        }
+        if imm % align != 0 {
+            return Err(EncodeError::new(format, imm as i64, 
+                EncodeReason::Misaligned));
+        }
+        Ok(())
+    }
+
+    /// Check that an unsigned immediate fits in 'bits'.
+    pub(crate) fn check_uimm(format: InstFormat, imm: u32, bits: u32) 
+        -> Result<(), EncodeError> 
+    {
+        if imm >= (1 << bits) {
+            return Err(EncodeError::new(format, imm as i64, 
+                EncodeReason::OutOfRange));
+        }
+        Ok(())
+    }
 
-impl InstructionSet for Rv32 {
-    type Encoding = u32;
-    type Inst     = Instr;
+    /// Build an R-type encoding.
+    pub(crate) fn enc_r(op: Opcode, rd: Reg, f3: u32, rs1: Reg, rs2: Reg,
+        f7: u32) -> u32 
+    {
+        (f7 << 25) | (rs2.val() << 20) | (rs1.val() << 15) | (f3 << 12)
+            | (rd.val() << 7) | ((op as u32) << 2) | Self::MASK_LEN_0
+    }
 
-    /// Decode an RV32I instruction.
-    fn decode(enc: Self::Encoding) -> Self::Inst {
+    /// Build an I-type encoding.
+    pub(crate) fn enc_i(op: Opcode, rd: Reg, f3: u32, rs1: Reg, imm: u32)
+        -> u32
+    {
+        ((imm << 20) & Self::MASK_I_IMM12_20_31) | (rs1.val() << 15) 
+            | (f3 << 12) | (rd.val() << 7) | ((op as u32) << 2) 
+            | Self::MASK_LEN_0
+    }
+
+    /// Build an S-type encoding.
+    pub(crate) fn enc_s(op: Opcode, f3: u32, rs1: Reg, rs2: Reg, imm: i32)
+        -> u32
+    {
+        let imm = imm as u32;
+        (((imm >> 5) << 25) & Self::MASK_S_IMM7_25_31)
+            | (rs2.val() << 20) | (rs1.val() << 15) | (f3 << 12) 
+            | ((imm << 7) & Self::MASK_S_IMM5_07_11)
+            | ((op as u32) << 2) | Self::MASK_LEN_0
+    }
+
+    /// Build a B-type encoding.
+    fn enc_b(op: Opcode, f3: u32, rs1: Reg, rs2: Reg, imm: i32) -> u32 {
+        let imm = (imm as u32) >> 1;
+        (((imm >> 11) << 31) & Self::MASK_B_IMM1_31_31)
+            | ((imm << 21) & Self::MASK_B_IMM6_25_30)
+            | (rs2.val() << 20) | (rs1.val() << 15) | (f3 << 12)
+            | ((imm << 8)  & Self::MASK_B_IMM4_08_11)
+            | (((imm >> 10) << 7) & Self::MASK_B_IMM1_07_07)
+            | ((op as u32) << 2) | Self::MASK_LEN_0
+    }
+
+    /// Build a U-type encoding.
+    fn enc_u(op: Opcode, rd: Reg, imm: u32) -> u32 {
+        (imm & Self::MASK_U_IMM20_12_31) | (rd.val() << 7) 
+            | ((op as u32) << 2) | Self::MASK_LEN_0
+    }
+
+    /// Build a J-type encoding.
+    fn enc_j(op: Opcode, rd: Reg, imm: i32) -> u32 {
+        let imm = (imm as u32) >> 1;
+        (((imm >> 19) << 31) & Self::MASK_J_IMM1_31_31)
+            | ((imm << 21) & Self::MASK_J_IMM6_25_30)
+            | ((imm << 21) & Self::MASK_J_IMM4_21_24)
+            | (((imm >> 10) << 20) & Self::MASK_J_IMM1_20_20)
+            | (((imm >> 11) << 12) & Self::MASK_J_IMM8_12_19)
+            | (rd.val() << 7) | ((op as u32) << 2) | Self::MASK_LEN_0
+    }
+}
+
+impl Rv32 {
+    /// Split a 32-bit encoding into the fields whose positions are always
+    /// fixed: the opcode, 'rd', 'funct3', 'rs1', 'rs2', and 'funct7'.
+    pub(crate) fn decode_fields(enc: u32)
+        -> Result<(Opcode, Reg, u32, Reg, Reg, u32), DecodeError>
+    {
+        let err = |field, reason| DecodeError::new(enc, field, reason);
+        if (enc & Self::MASK_LEN_0) != Self::MASK_LEN_0 {
+            return Err(err(DecodeField::Opcode, DecodeReason::Unsupported));
+        }
 
-        // The positions of these fields are always fixed.
         let op  = (enc & Self::MASK_OP_2)   >>  2;
         let rd  = (enc & Self::MASK_RD_7)   >>  7;
         let f3  = (enc & Self::MASK_F3_12)  >> 12;
@@ -408,47 +1204,185 @@ impl InstructionSet for Rv32 {
         let rs2 = (enc & Self::MASK_RS2_20) >> 20;
         let f7  = (enc & Self::MASK_F7_25)  >> 25;
 
-        let rd  = Reg::new(rd);
-        let rs1 = Reg::new(rs1);
-        let rs2 = Reg::new(rs2);
+        let opcode = Opcode::try_from(op)
+            .map_err(|r| err(DecodeField::Opcode, r))?;
+        Ok((opcode, Reg::new(rd), f3, Reg::new(rs1), Reg::new(rs2), f7))
+    }
+
+    /// Decode an instruction from the SYSTEM opcode with funct3=0b000. 
+    fn decode_priv(enc: u32) -> Result<Instr, DecodeError> {
+        let rd  = (enc & Self::MASK_RD_7)   >>  7;
+        let rs1 = (enc & Self::MASK_RS1_15) >> 15;
+        let f7  = (enc & Self::MASK_F7_25)  >> 25;
+        let err = |field, reason| DecodeError::new(enc, field, reason);
+
+        // 'sfence.vma' and friends are the only instructions which use
+        // the register fields.
+        if f7 == 0b0001001 || f7 == 0b0010001 || f7 == 0b1010001 {
+            return Err(err(DecodeField::Funct7, DecodeReason::Unsupported));
+        }
+        if rd != 0 || rs1 != 0 {
+            return Err(err(DecodeField::Funct7, DecodeReason::Reserved));
+        }
+        match Self::build_i_uimm(enc) {
+            0x000 => Ok(Instr::Ecall),
+            0x001 => Ok(Instr::Ebreak),
+            0x102 => Ok(Instr::Sret),
+            0x105 => Ok(Instr::Wfi),
+            0x302 => Ok(Instr::Mret),
+            _ => Err(err(DecodeField::Funct7, DecodeReason::Reserved)),
+        }
+    }
+}
+
+impl InstructionSet for Rv32 {
+    type Encoding    = u32;
+    type Inst        = Instr;
+    type DecodeError = DecodeError;
+
+    /// Decode an instruction, accepting all supported extensions.
+    fn decode(enc: Self::Encoding) -> Result<Self::Inst, Self::DecodeError> {
+        Self::decode_with(enc, RvExtensions::ALL)
+    }
+}
 
+impl Rv32 {
+    /// Decode an instruction, accepting only the base integer ISA and the
+    /// given set of extensions.
+    ///
+    /// Only 32-bit encodings are accepted here: compressed encodings must be
+    /// decoded with [Rv32::decode_compressed] or [Rv32::decode_bytes].
+    pub fn decode_with(enc: u32, ext: RvExtensions) 
+        -> Result<Instr, DecodeError> 
+    {
+        let err = |field, reason| DecodeError::new(enc, field, reason);
+        let (opcode, rd, f3, rs1, rs2, f7) = Self::decode_fields(enc)?;
 
-        match Opcode::from(op) {
+        let res = match opcode {
             // R-type formats
+            Opcode::OP if f7 == RvMulOp::FUNCT7 => {
+                if !ext.contains(RvExtensions::M) {
+                    return Err(err(DecodeField::Funct7, DecodeReason::Disabled));
+                }
+                let mul_op = RvMulOp::from(f3);
+                Instr::MulDiv { rd, rs1, rs2, mul_op }
+            },
             Opcode::OP     => {
-                let alu_op = RvALUOp::from((f3, f7));
+                let alu_op = RvALUOp::try_from((f3, f7))
+                    .map_err(|r| err(DecodeField::Funct7, r))?;
                 Instr::Op { rd, rs1, rs2, alu_op }
             },
 
+            Opcode::AMO => {
+                if !ext.contains(RvExtensions::A) {
+                    return Err(err(DecodeField::Opcode, DecodeReason::Disabled));
+                }
+                // Only 32-bit operations are supported here
+                if f3 != 0b010 {
+                    return Err(err(DecodeField::Funct3, DecodeReason::Unsupported));
+                }
+                let amo_op = RvAmoOp::try_from(f7 >> 2)
+                    .map_err(|r| err(DecodeField::Funct7, r))?;
+                if amo_op == RvAmoOp::Lr && rs2.val() != 0 {
+                    return Err(err(DecodeField::Funct7, DecodeReason::Reserved));
+                }
+                let aq = (f7 & 0b10) != 0;
+                let rl = (f7 & 0b01) != 0;
+                Instr::Amo { rd, rs1, rs2, aq, rl, amo_op }
+            },
+
             // I-type formats
-            Opcode::MISC_MEM => unimplemented!(),
-            Opcode::SYSTEM   => unimplemented!(),
             Opcode::OP_IMM   => {
-                let simm   = Self::build_i_imm(enc);
-                let alu_op = RvALUOpImm::from((f3, f7));
+                let alu_op = RvALUOpImm::try_from((f3, f7))
+                    .map_err(|r| err(DecodeField::Funct7, r))?;
+                // Shifts only use the low bits of the immediate
+                let simm   = if alu_op.is_shift() { 
+                    rs2.val() as i32
+                } else { 
+                    Self::build_i_imm(enc) 
+                };
                 Instr::OpImm { rd, rs1, simm, alu_op }
             },
             Opcode::JALR     => {
+                if f3 != 0b000 {
+                    return Err(err(DecodeField::Funct3, DecodeReason::Reserved));
+                }
                 let simm   = Self::build_i_imm(enc);
                 Instr::Jalr { rd, rs1, simm }
             },
             Opcode::LOAD => {
                 let simm   = Self::build_i_imm(enc);
-                let width  = RvWidth::from(f3);
+                let width  = match RvWidth::try_from(f3) {
+                    Ok(RvWidth::Double | RvWidth::WordUnsigned) => return Err(
+                        err(DecodeField::Funct3, DecodeReason::Reserved)
+                    ),
+                    res => res.map_err(|r| err(DecodeField::Funct3, r))?,
+                };
                 Instr::Load { rd, rs1, simm, width }
             },
 
+            Opcode::MISC_MEM => {
+                let imm = Self::build_i_uimm(enc);
+                match f3 {
+                    0b000 => {
+                        // The 'fm' field selects 'fence.tso' (which is only
+                        // defined for 'rw, rw'). Other values are reserved,
+                        // and must be treated as a normal fence.
+                        let fm   = imm >> 8;
+                        let pred = RvFenceSet::new((imm >> 4) & 0b1111);
+                        let succ = RvFenceSet::new(imm & 0b1111);
+                        let rw   = RvFenceSet::R | RvFenceSet::W;
+                        let tso  = pred.val() == rw && succ.val() == rw;
+                        if fm == 0b1000 && tso {
+                            Instr::FenceTso
+                        } else {
+                            Instr::Fence { pred, succ }
+                        }
+                    },
+                    0b001 => Instr::FenceI,
+                    _ => return Err(
+                        err(DecodeField::Funct3, DecodeReason::Reserved)
+                    ),
+                }
+            },
+            Opcode::SYSTEM => {
+                let csr = Csr::new(Self::build_i_uimm(enc));
+                match f3 {
+                    0b000 => Self::decode_priv(enc)?,
+                    0b100 => return Err(
+                        err(DecodeField::Funct3, DecodeReason::Unsupported)
+                    ),
+                    _ => {
+                        let csr_op = RvCsrOp::try_from(f3 & 0b011)
+                            .map_err(|r| err(DecodeField::Funct3, r))?;
+                        if (f3 & 0b100) != 0 {
+                            let uimm = rs1.val();
+                            Instr::CsrImm { rd, uimm, csr, csr_op }
+                        } else {
+                            Instr::Csr { rd, rs1, csr, csr_op }
+                        }
+                    },
+                }
+            },
+
             // S-type formats
             Opcode::STORE  => {
                 let simm   = Self::build_s_imm(enc);
-                let width  = RvWidth::from(f3);
+                let width  = match RvWidth::try_from(f3) {
+                    Ok(w @ (RvWidth::Byte | RvWidth::Half | RvWidth::Word)) 
+                        => w,
+                    _ => return Err(
+                        err(DecodeField::Funct3, DecodeReason::Reserved)
+                    ),
+                };
                 Instr::Store { rs1, rs2, simm, width }
             },
 
             // B-type formats
             Opcode::BRANCH => {
                 let simm   = Self::build_b_imm(enc);
-                let brn_op = RvBranchOp::from(f3);
+                let brn_op = RvBranchOp::try_from(f3)
+                    .map_err(|r| err(DecodeField::Funct3, r))?;
                 Instr::Branch { rs1, rs2, simm, brn_op }
             },
 
@@ -467,8 +1401,160 @@ impl InstructionSet for Rv32 {
                 let simm  = Self::build_j_imm(enc);
                 Instr::Jal { rd, simm }
             },
-            _ => unimplemented!(),
-        }
+
+            // Floating-point formats (RV32F/RV32D)
+            Opcode::LOAD_FP | Opcode::STORE_FP | Opcode::OP_FP |
+            Opcode::MADD | Opcode::MSUB | Opcode::NMSUB | Opcode::NMADD => {
+                if !ext.contains(RvExtensions::F) {
+                    return Err(err(DecodeField::Opcode, DecodeReason::Disabled));
+                }
+                Self::decode_fp(enc, opcode, ext)?
+            },
+
+            Opcode::CUSTOM_0 | Opcode::CUSTOM_1 | 
+            Opcode::CUSTOM_2 | Opcode::CUSTOM_3 => {
+                return Err(err(DecodeField::Opcode, DecodeReason::Custom));
+            },
+            Opcode::RES_0 | Opcode::RES_1 | Opcode::RES_2 => {
+                return Err(err(DecodeField::Opcode, DecodeReason::Reserved));
+            },
+            _ => {
+                return Err(err(DecodeField::Opcode, DecodeReason::Unsupported));
+            },
+        };
+        Ok(res)
+    }
+}
+
+impl Assembler for Rv32 {
+    type EncodeError = EncodeError;
+
+    /// Encode an RV32I instruction.
+    fn encode(inst: Self::Inst) -> Result<Self::Encoding, Self::EncodeError> {
+        use InstFormat::*;
+        let x0 = Reg::new(0);
+        let res = match inst {
+            // R-type formats
+            Instr::Op { rd, rs1, rs2, alu_op } => {
+                let (f3, f7) = alu_op.funct();
+                Self::enc_r(Opcode::OP, rd, f3, rs1, rs2, f7)
+            },
+            Instr::MulDiv { rd, rs1, rs2, mul_op } => {
+                let (f3, f7) = (mul_op.funct3(), RvMulOp::FUNCT7);
+                Self::enc_r(Opcode::OP, rd, f3, rs1, rs2, f7)
+            },
+            Instr::Amo { rd, rs1, rs2, aq, rl, amo_op } => {
+                let f7 = (amo_op.funct5() << 2) 
+                    | ((aq as u32) << 1) | (rl as u32);
+                Self::enc_r(Opcode::AMO, rd, 0b010, rs1, rs2, f7)
+            },
+
+            // I-type formats
+            Instr::OpImm { rd, rs1, simm, alu_op } => {
+                let (f3, f7) = alu_op.funct();
+                if alu_op.is_shift() {
+                    Self::check_uimm(I, simm as u32, 5)?;
+                    let imm = (f7 << 5) | simm as u32;
+                    Self::enc_i(Opcode::OP_IMM, rd, f3, rs1, imm)
+                } else {
+                    Self::check_simm(I, simm, 12, 1)?;
+                    Self::enc_i(Opcode::OP_IMM, rd, f3, rs1, simm as u32)
+                }
+            },
+            Instr::Load { rd, rs1, simm, width } => {
+                if let RvWidth::Double | RvWidth::WordUnsigned = width {
+                    return Err(EncodeError::new(I, simm as i64,
+                        EncodeReason::Unsupported));
+                }
+                Self::check_simm(I, simm, 12, 1)?;
+                Self::enc_i(Opcode::LOAD, rd, width.funct3(), rs1, simm as u32)
+            },
+            Instr::Jalr { rd, rs1, simm } => {
+                Self::check_simm(I, simm, 12, 1)?;
+                Self::enc_i(Opcode::JALR, rd, 0b000, rs1, simm as u32)
+            },
+            Instr::Fence { pred, succ } => {
+                let imm = (pred.val() << 4) | succ.val();
+                Self::enc_i(Opcode::MISC_MEM, x0, 0b000, x0, imm)
+            },
+            Instr::FenceTso => {
+                Self::enc_i(Opcode::MISC_MEM, x0, 0b000, x0, 0x833)
+            },
+            Instr::FenceI => Self::enc_i(Opcode::MISC_MEM, x0, 0b001, x0, 0),
+            Instr::Ecall  => Self::enc_i(Opcode::SYSTEM, x0, 0b000, x0, 0x000),
+            Instr::Ebreak => Self::enc_i(Opcode::SYSTEM, x0, 0b000, x0, 0x001),
+            Instr::Sret   => Self::enc_i(Opcode::SYSTEM, x0, 0b000, x0, 0x102),
+            Instr::Wfi    => Self::enc_i(Opcode::SYSTEM, x0, 0b000, x0, 0x105),
+            Instr::Mret   => Self::enc_i(Opcode::SYSTEM, x0, 0b000, x0, 0x302),
+            Instr::Csr { rd, rs1, csr, csr_op } => {
+                Self::enc_i(Opcode::SYSTEM, rd, csr_op.funct3(), rs1, csr.val())
+            },
+            Instr::CsrImm { rd, uimm, csr, csr_op } => {
+                Self::check_uimm(I, uimm, 5)?;
+                let f3 = 0b100 | csr_op.funct3();
+                Self::enc_i(Opcode::SYSTEM, rd, f3, Reg::new(uimm), csr.val())
+            },
+
+            // S-type formats
+            Instr::Store { rs1, rs2, simm, width } => {
+                let f3 = match width {
+                    RvWidth::Byte | RvWidth::Half | RvWidth::Word => {
+                        width.funct3()
+                    },
+                    _ => return Err(EncodeError::new(S, simm as i64,
+                        EncodeReason::Unsupported)),
+                };
+                Self::check_simm(S, simm, 12, 1)?;
+                Self::enc_s(Opcode::STORE, f3, rs1, rs2, simm)
+            },
+
+            // B-type formats
+            Instr::Branch { rs1, rs2, simm, brn_op } => {
+                Self::check_simm(B, simm, 13, 2)?;
+                Self::enc_b(Opcode::BRANCH, brn_op.funct3(), rs1, rs2, simm)
+            },
+
+            // U-type formats
+            Instr::AuiPc { rd, uimm } => {
+                if (uimm & !Self::MASK_U_IMM20_12_31) != 0 {
+                    return Err(EncodeError::new(U, uimm as i64, 
+                        EncodeReason::Misaligned));
+                }
+                Self::enc_u(Opcode::AUIPC, rd, uimm)
+            },
+            Instr::Lui { rd, uimm } => {
+                if (uimm & !Self::MASK_U_IMM20_12_31) != 0 {
+                    return Err(EncodeError::new(U, uimm as i64, 
+                        EncodeReason::Misaligned));
+                }
+                Self::enc_u(Opcode::LUI, rd, uimm)
+            },
+
+            // J-type formats
+            Instr::Jal { rd, simm } => {
+                Self::check_simm(J, simm, 21, 2)?;
+                Self::enc_j(Opcode::JAL, rd, simm)
+            },
+
+            // RV64I only (see [crate::isa::rv64i::Rv64])
+            Instr::Op32 { .. } => {
+                return Err(EncodeError::new(R, 0, EncodeReason::Unsupported));
+            },
+            Instr::OpImm32 { simm, .. } => {
+                return Err(EncodeError::new(I, simm as i64,
+                    EncodeReason::Unsupported));
+            },
+
+            // Floating-point formats
+            Instr::FpLoad { .. } | Instr::FpStore { .. } |
+            Instr::FpFma { .. } | Instr::FpArith { .. } |
+            Instr::FpSqrt { .. } | Instr::FpOp { .. } |
+            Instr::FpCmp { .. } | Instr::FpClass { .. } |
+            Instr::FpCvtToInt { .. } | Instr::FpCvtFromInt { .. } |
+            Instr::FpCvt { .. } | Instr::FpMvToInt { .. } |
+            Instr::FpMvFromInt { .. } => Self::encode_fp(inst)?,
+        };
+        Ok(res)
     }
 }
 
@@ -479,8 +1565,100 @@ pub enum Rv32Reg {
 pub enum Rv32Mem {
 }
 
+/// A model of the reservation set used by 'lr' and 'sc'.
+///
+/// This tracks a single reservation on a naturally-aligned word.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ReservationSet {
+    addr: Option<u32>,
+}
+impl ReservationSet {
+    /// The size (in bytes) of a reservation.
+    pub const GRANULE: u32 = 4;
+
+    /// Returns the address of the current reservation (if any).
+    pub fn addr(&self) -> Option<u32> {
+        self.addr
+    }
+
+    /// Acquire a reservation on 'addr' (for 'lr'), replacing any existing
+    /// reservation.
+    pub fn acquire(&mut self, addr: u32) {
+        self.addr = Some(addr & !(Self::GRANULE - 1));
+    }
+
+    /// Returns true if there is a reservation on 'addr', without releasing
+    /// it.
+    pub fn matches(&self, addr: u32) -> bool {
+        self.addr == Some(addr & !(Self::GRANULE - 1))
+    }
+
+    /// Check for a reservation on 'addr' (for 'sc'), returning true if the 
+    /// store should succeed. 
+    ///
+    /// The reservation is always released, regardless of the outcome.
+    pub fn check(&mut self, addr: u32) -> bool {
+        let res = self.matches(addr);
+        self.addr = None;
+        res
+    }
+
+    /// Release the reservation if it overlaps with a store of 'len' bytes
+    /// to 'addr' (ie. from another hart or device).
+    pub fn invalidate(&mut self, addr: u32, len: u32) {
+        if let Some(res) = self.addr {
+            let end = addr.wrapping_add(len);
+            if addr < res.wrapping_add(Self::GRANULE) && res < end {
+                self.addr = None;
+            }
+        }
+    }
+
+    /// Release any reservation.
+    pub fn clear(&mut self) {
+        self.addr = None;
+    }
+}
+
+/// Architectural state for a single RV32I hart.
+///
+/// See [Rv32State::step] for executing instructions.
 pub struct Rv32State {
+    /// The program counter
+    pub pc: u32,
+    /// Extensions accepted when decoding instructions
+    pub ext: RvExtensions,
     gpr: [u32; 32],
+    /// Reservation set for 'lr' and 'sc'
+    reservation: ReservationSet,
+}
+impl Rv32State {
+    /// Create a new hart with all registers cleared, starting at 'pc'.
+    pub fn new(pc: u32) -> Self {
+        Self {
+            pc,
+            ext: RvExtensions::ALL,
+            gpr: [0; 32],
+            reservation: ReservationSet::default(),
+        }
+    }
+
+    /// Read a general-purpose register ('x0' always reads as zero).
+    pub fn reg(&self, r: Reg) -> u32 {
+        if r.val() == 0 { 0 } else { self.gpr[r.val() as usize] }
+    }
+
+    /// Write a general-purpose register (writes to 'x0' are ignored).
+    pub fn set_reg(&mut self, r: Reg, val: u32) {
+        if r.val() != 0 {
+            self.gpr[r.val() as usize] = val;
+        }
+    }
+
+    /// Returns the reservation set used by 'lr' and 'sc'.
+    pub fn reservation(&mut self) -> &mut ReservationSet {
+        &mut self.reservation
+    }
 }
 impl ArchitecturalState for Rv32State {
     type RegType = Rv32Reg;
@@ -491,3 +1669,421 @@ impl ArchitecturalState for Rv32State {
 
 
 
+
+
+#[cfg(test)]
+mod test {
+    use crate::isa::*;
+    use crate::isa::rv32i::*;
+
+    #[test]
+    fn decode_valid() {
+        // addi x1, x2, -4
+        let inst = Rv32::decode(0xffc1_0093).unwrap();
+        assert_eq!(inst, Instr::OpImm { 
+            rd: Reg::new(1), rs1: Reg::new(2), simm: -4, 
+            alu_op: RvALUOpImm::Addi
+        });
+        // lw x5, 8(x2)
+        let inst = Rv32::decode(0x0081_2283).unwrap();
+        assert_eq!(inst, Instr::Load { 
+            rd: Reg::new(5), rs1: Reg::new(2), simm: 8, width: RvWidth::Word
+        });
+    }
+
+    #[test]
+    fn decode_system() {
+        let cases = [
+            (0x0ff0_000f, "fence  iorw, iorw"),
+            (0x0330_000f, "fence  rw, rw"),
+            (0x0000_100f, "fence.i"),
+            (0x8330_000f, "fence.tso"),
+            // Reserved values of 'fm' are treated as a normal fence
+            (0x8ff0_000f, "fence  iorw, iorw"),
+            (0x1330_000f, "fence  rw, rw"),
+            (0x0000_0073, "ecall"),
+            (0x0010_0073, "ebreak"),
+            (0x3020_0073, "mret"),
+            (0x1020_0073, "sret"),
+            (0x1050_0073, "wfi"),
+            (0x3000_22f3, "csrrs  x5, mstatus, x0"),
+            (0x3052_d073, "csrrwi x0, mtvec, 5"),
+            (0xc000_3173, "csrrc  x2, cycle, x0"),
+            (0x7c01_1073, "csrrw  x0, 0x7c0, x2"),
+        ];
+        for (enc, text) in cases {
+            let inst = Rv32::decode(enc).unwrap();
+            assert_eq!(format!("{}", inst), text);
+        }
+        assert_eq!(Csr::from_name("mstatus"), Some(Csr::new(0x300)));
+    }
+
+    #[test]
+    fn decode_rv32m() {
+        // mul x1, x2, x3
+        let enc = 0x0231_00b3;
+        assert_eq!(Rv32::decode(enc), Ok(Instr::MulDiv { 
+            rd: Reg::new(1), rs1: Reg::new(2), rs2: Reg::new(3), 
+            mul_op: RvMulOp::Mul 
+        }));
+        assert_eq!(Rv32::decode_with(enc, RvExtensions::M), Rv32::decode(enc));
+        assert_eq!(Rv32::decode_with(enc, RvExtensions::NONE), Err(
+            DecodeError::new(enc, DecodeField::Funct7, DecodeReason::Disabled)
+        ));
+    }
+
+    #[test]
+    fn decode_rv32a() {
+        let cases = [
+            (0x1005_22af, "lr.w   x5, (x10)"),
+            (0x1c65_252f, "sc.w.aq x10, x6, (x10)"),
+            (0x0e73_22af, "amoswap.w.aqrl x5, x7, (x6)"),
+            (0xe0b5_202f, "amomaxu.w x0, x11, (x10)"),
+        ];
+        for (enc, text) in cases {
+            let inst = Rv32::decode(enc).unwrap();
+            assert_eq!(format!("{}", inst), text);
+        }
+        // lr.w with nonzero rs2
+        assert_eq!(Rv32::decode(0x1015_22af).unwrap_err().reason, 
+            DecodeReason::Reserved);
+        // amoadd.d
+        assert_eq!(Rv32::decode(0x0073_32af).unwrap_err().reason, 
+            DecodeReason::Unsupported);
+        assert_eq!(Rv32::decode_with(0x1005_22af, RvExtensions::M)
+            .unwrap_err().reason, DecodeReason::Disabled);
+    }
+
+    #[test]
+    fn reservation_set() {
+        let mut rs = ReservationSet::default();
+
+        // 'sc' without a reservation fails
+        assert!(!rs.check(0x1000));
+
+        // 'sc' to the reserved address succeeds (once)
+        rs.acquire(0x1000);
+        assert!(rs.matches(0x1002));
+        assert!(rs.check(0x1000));
+        assert!(!rs.check(0x1000));
+
+        // 'sc' to a different address fails and releases the reservation
+        rs.acquire(0x1000);
+        assert!(!rs.check(0x2000));
+        assert_eq!(rs.addr(), None);
+
+        // Overlapping stores release the reservation
+        rs.acquire(0x1000);
+        rs.invalidate(0x0ffc, 4);
+        assert_eq!(rs.addr(), Some(0x1000));
+        rs.invalidate(0x1003, 1);
+        assert!(!rs.check(0x1000));
+
+        assert_eq!(RvAmoOp::Min.eval(-1i32 as u32, 1), -1i32 as u32);
+        assert_eq!(RvAmoOp::Minu.eval(-1i32 as u32, 1), 1);
+        assert_eq!(RvAmoOp::Add.eval(u32::MAX, 2), 1);
+    }
+
+    #[test]
+    fn rv32m_semantics() {
+        let min = i32::MIN as u32;
+        let neg1 = -1i32 as u32;
+        let cases = [
+            (RvMulOp::Mul,    7, neg1, -7i32 as u32),
+            (RvMulOp::Mulh,   min, min, 0x4000_0000),
+            (RvMulOp::Mulh,   neg1, 1, neg1),
+            (RvMulOp::Mulhsu, neg1, u32::MAX, neg1),
+            (RvMulOp::Mulhu,  u32::MAX, u32::MAX, 0xffff_fffe),
+            (RvMulOp::Div,    -7i32 as u32, 2, -3i32 as u32),
+            (RvMulOp::Rem,    -7i32 as u32, 2, neg1),
+            // Division by zero
+            (RvMulOp::Div,    5, 0, u32::MAX),
+            (RvMulOp::Divu,   5, 0, u32::MAX),
+            (RvMulOp::Rem,    5, 0, 5),
+            (RvMulOp::Remu,   5, 0, 5),
+            // Signed overflow
+            (RvMulOp::Div,    min, neg1, min),
+            (RvMulOp::Rem,    min, neg1, 0),
+        ];
+        for (op, a, b, res) in cases {
+            assert_eq!(op.eval(a, b), res, "{} {:08x} {:08x}", op, a, b);
+        }
+    }
+
+    #[test]
+    fn decode_invalid() {
+        let cases = [
+            // Compressed encodings
+            (0x0000_0001, DecodeField::Opcode, DecodeReason::Unsupported),
+            (0xdead_0001, DecodeField::Opcode, DecodeReason::Unsupported),
+            // 48-bit encoding
+            (0x0000_001f, DecodeField::Opcode, DecodeReason::Unsupported),
+            // custom-0
+            (0x0000_000b, DecodeField::Opcode, DecodeReason::Custom),
+            // reserved opcode
+            (0x0000_0057, DecodeField::Opcode, DecodeReason::Reserved),
+            // OP with funct7=0b0100000 and funct3=0b001
+            (0x4000_1033, DecodeField::Funct7, DecodeReason::Reserved),
+            // slli with nonzero funct7
+            (0x0200_1013, DecodeField::Funct7, DecodeReason::Reserved),
+            // Branch with funct3=0b010
+            (0x0000_2063, DecodeField::Funct3, DecodeReason::Reserved),
+            // Load with funct3=0b111
+            (0x0000_7003, DecodeField::Funct3, DecodeReason::Reserved),
+            // Store with funct3=0b100
+            (0x0000_4023, DecodeField::Funct3, DecodeReason::Reserved),
+            // sfence.vma x0, x0
+            (0x1200_0073, DecodeField::Funct7, DecodeReason::Unsupported),
+            // SYSTEM with funct3=0b000 and an unknown immediate
+            (0x0020_0073, DecodeField::Funct7, DecodeReason::Reserved),
+        ];
+        for (enc, field, reason) in cases {
+            let err = Rv32::decode(enc).unwrap_err();
+            assert_eq!(err, DecodeError::new(enc, field, reason), 
+                "{:08x}", enc);
+        }
+    }
+
+    /// Every variant of [Instr], with a spread of registers and boundary
+    /// values for each immediate.
+    fn all_instrs() -> Vec<Instr> {
+        let x0 = Reg::new(0);
+        let regs = [0, 1, 15, 31].map(Reg::new);
+        let i12 = [-2048, -1, 0, 1, 2047];
+        let b13 = [-4096, -2, 0, 2, 4094];
+        let j21 = [-(1 << 20), -2, 0, 2, (1 << 20) - 2];
+        let u20 = [0, 0x0000_1000, 0x7fff_f000, 0xffff_f000];
+        let alu_ops = [
+            RvALUOp::Add, RvALUOp::Sub, RvALUOp::Sll, RvALUOp::Slt, 
+            RvALUOp::Sltu, RvALUOp::Xor, RvALUOp::Srl, RvALUOp::Sra, 
+            RvALUOp::Or, RvALUOp::And,
+        ];
+        let alu_imm_ops = [
+            RvALUOpImm::Addi, RvALUOpImm::Slti, RvALUOpImm::Sltiu, 
+            RvALUOpImm::Xori, RvALUOpImm::Ori, RvALUOpImm::Andi, 
+            RvALUOpImm::Slli, RvALUOpImm::Srli, RvALUOpImm::Srai,
+        ];
+        let widths = [
+            RvWidth::Byte, RvWidth::Half, RvWidth::Word, 
+            RvWidth::ByteUnsigned, RvWidth::HalfUnsigned,
+        ];
+        let brn_ops = [
+            RvBranchOp::Eq, RvBranchOp::Ne, RvBranchOp::Lt, RvBranchOp::Ge, 
+            RvBranchOp::Ltu, RvBranchOp::Geu,
+        ];
+        let mul_ops = [
+            RvMulOp::Mul, RvMulOp::Mulh, RvMulOp::Mulhsu, RvMulOp::Mulhu, 
+            RvMulOp::Div, RvMulOp::Divu, RvMulOp::Rem, RvMulOp::Remu,
+        ];
+        let amo_ops = [
+            RvAmoOp::Sc, RvAmoOp::Swap, RvAmoOp::Add, RvAmoOp::Xor, 
+            RvAmoOp::And, RvAmoOp::Or, RvAmoOp::Min, RvAmoOp::Max, 
+            RvAmoOp::Minu, RvAmoOp::Maxu,
+        ];
+        let csr_ops = [RvCsrOp::Rw, RvCsrOp::Rs, RvCsrOp::Rc];
+        let csrs = [0x000, 0x300, 0xc00, 0xfff].map(Csr::new);
+
+        let mut res = vec![
+            Instr::FenceTso, Instr::FenceI, Instr::Ecall, Instr::Ebreak, 
+            Instr::Mret, Instr::Sret, Instr::Wfi,
+        ];
+        for pred in 0..16 {
+            for succ in 0..16 {
+                let pred = RvFenceSet::new(pred);
+                let succ = RvFenceSet::new(succ);
+                res.push(Instr::Fence { pred, succ });
+            }
+        }
+        for rd in regs {
+            for rs1 in regs {
+                for rs2 in regs {
+                    for alu_op in alu_ops {
+                        res.push(Instr::Op { rd, rs1, rs2, alu_op });
+                    }
+                    for mul_op in mul_ops {
+                        res.push(Instr::MulDiv { rd, rs1, rs2, mul_op });
+                    }
+                    for (aq, rl) in [(false, false), (true, true)] {
+                        for amo_op in amo_ops {
+                            res.push(Instr::Amo { rd, rs1, rs2, aq, rl, amo_op });
+                        }
+                    }
+                    for simm in b13 {
+                        for brn_op in brn_ops {
+                            res.push(Instr::Branch { rs1, rs2, simm, brn_op });
+                        }
+                    }
+                }
+                for simm in i12 {
+                    for alu_op in alu_imm_ops {
+                        let simm = if alu_op.is_shift() { simm & 0x1f } 
+                            else { simm };
+                        res.push(Instr::OpImm { rd, rs1, simm, alu_op });
+                    }
+                    for width in widths {
+                        res.push(Instr::Load { rd, rs1, simm, width });
+                    }
+                    for width in &widths[..3] {
+                        let width = *width;
+                        res.push(Instr::Store { rs1, rs2: rd, simm, width });
+                    }
+                    res.push(Instr::Jalr { rd, rs1, simm });
+                }
+                for (aq, rl) in [(false, true), (true, false)] {
+                    let amo_op = RvAmoOp::Lr;
+                    res.push(Instr::Amo { rd, rs1, rs2: x0, aq, rl, amo_op });
+                }
+                for csr in csrs {
+                    for csr_op in csr_ops {
+                        res.push(Instr::Csr { rd, rs1, csr, csr_op });
+                        let uimm = rs1.val();
+                        res.push(Instr::CsrImm { rd, uimm, csr, csr_op });
+                    }
+                }
+            }
+            for uimm in u20 {
+                res.push(Instr::Lui { rd, uimm });
+                res.push(Instr::AuiPc { rd, uimm });
+            }
+            for simm in j21 {
+                res.push(Instr::Jal { rd, simm });
+            }
+        }
+
+        let fregs = [0, 1, 15, 31].map(FReg::new);
+        let fmts = [RvFpFmt::S, RvFpFmt::D];
+        let rms = [
+            RvRoundingMode::Rne, RvRoundingMode::Rtz, RvRoundingMode::Rdn,
+            RvRoundingMode::Rup, RvRoundingMode::Rmm, RvRoundingMode::Dyn,
+        ];
+        let fma_ops = [RvFmaOp::Madd, RvFmaOp::Msub, RvFmaOp::Nmsub, RvFmaOp::Nmadd];
+        let fp_arith_ops = [
+            RvFpArithOp::Add, RvFpArithOp::Sub, RvFpArithOp::Mul, RvFpArithOp::Div,
+        ];
+        let fp_ops = [
+            RvFpOp::Sgnj, RvFpOp::Sgnjn, RvFpOp::Sgnjx, RvFpOp::Min, RvFpOp::Max,
+        ];
+        let cmp_ops = [RvFpCmpOp::Eq, RvFpCmpOp::Lt, RvFpCmpOp::Le];
+        for (fd, xd) in fregs.into_iter().zip(regs) {
+            for (fs, xs) in fregs.into_iter().zip(regs) {
+                res.push(Instr::FpMvToInt { rd: xd, rs1: fs });
+                res.push(Instr::FpMvFromInt { rd: fd, rs1: xs });
+                for fmt in fmts {
+                    for simm in i12 {
+                        res.push(Instr::FpLoad { rd: fd, rs1: xs, simm, fmt });
+                        res.push(Instr::FpStore { rs1: xs, rs2: fd, simm, fmt });
+                    }
+                    res.push(Instr::FpClass { rd: xd, rs1: fs, fmt });
+                    for rm in rms {
+                        res.push(Instr::FpSqrt { rd: fd, rs1: fs, fmt, rm });
+                        for unsigned in [false, true] {
+                            res.push(Instr::FpCvtToInt { 
+                                rd: xd, rs1: fs, fmt, rm, unsigned 
+                            });
+                            res.push(Instr::FpCvtFromInt { 
+                                rd: fd, rs1: xs, fmt, rm, unsigned 
+                            });
+                        }
+                        let src_fmt = if fmt == RvFpFmt::S { 
+                            RvFpFmt::D 
+                        } else { 
+                            RvFpFmt::S 
+                        };
+                        res.push(Instr::FpCvt { rd: fd, rs1: fs, fmt, src_fmt, rm });
+                    }
+                    for rs2 in fregs {
+                        for fp_op in fp_ops {
+                            res.push(Instr::FpOp { rd: fd, rs1: fs, rs2, fmt, fp_op });
+                        }
+                        for cmp_op in cmp_ops {
+                            res.push(Instr::FpCmp { rd: xd, rs1: fs, rs2, fmt, cmp_op });
+                        }
+                        for rm in rms {
+                            for fp_op in fp_arith_ops {
+                                res.push(Instr::FpArith { 
+                                    rd: fd, rs1: fs, rs2, fmt, rm, fp_op 
+                                });
+                            }
+                            for (rs3, fma_op) in fregs.into_iter().zip(fma_ops) {
+                                res.push(Instr::FpFma { 
+                                    rd: fd, rs1: fs, rs2, rs3, fmt, rm, fma_op 
+                                });
+                            }
+                        }
+                    }
+                }
+            }
+        }
+        res
+    }
+
+    #[test]
+    fn encode_roundtrip() {
+        for inst in all_instrs() {
+            let enc = Rv32::encode(inst).unwrap();
+            assert_eq!(Rv32::decode(enc), Ok(inst), "{:08x} {}", enc, inst);
+        }
+    }
+
+    #[test]
+    fn decode_roundtrip() {
+        // Walk a deterministic sequence of pseudo-random encodings.
+        let mut x: u32 = 0x1234_5678;
+        for _ in 0..(1 << 20) {
+            x ^= x << 13;
+            x ^= x >> 17;
+            x ^= x << 5;
+            // Force the low bits to select a 32-bit encoding
+            let enc = x | 0b11;
+            if let Ok(inst) = Rv32::decode(enc) {
+                let res = Rv32::encode(inst).unwrap();
+                // Fences ignore the 'rd' and 'rs1' fields (and reserved
+                // values of the 'fm' field)
+                if let Instr::Fence { .. } | Instr::FenceTso | Instr::FenceI
+                    = inst
+                {
+                    assert_eq!(Rv32::decode(res), Ok(inst));
+                } else {
+                    assert_eq!(res, enc, "{}", inst);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn encode_invalid() {
+        let r = Reg::new(1);
+        let cases = [
+            (Instr::OpImm { rd: r, rs1: r, simm: 2048, alu_op: RvALUOpImm::Addi },
+             EncodeError::new(InstFormat::I, 2048, EncodeReason::OutOfRange)),
+            (Instr::OpImm { rd: r, rs1: r, simm: 32, alu_op: RvALUOpImm::Slli },
+             EncodeError::new(InstFormat::I, 32, EncodeReason::OutOfRange)),
+            (Instr::Store { rs1: r, rs2: r, simm: -2049, width: RvWidth::Word },
+             EncodeError::new(InstFormat::S, -2049, EncodeReason::OutOfRange)),
+            (Instr::Branch { rs1: r, rs2: r, simm: 4096, brn_op: RvBranchOp::Eq },
+             EncodeError::new(InstFormat::B, 4096, EncodeReason::OutOfRange)),
+            (Instr::Branch { rs1: r, rs2: r, simm: 3, brn_op: RvBranchOp::Eq },
+             EncodeError::new(InstFormat::B, 3, EncodeReason::Misaligned)),
+            (Instr::Lui { rd: r, uimm: 0x1234 },
+             EncodeError::new(InstFormat::U, 0x1234, EncodeReason::Misaligned)),
+            (Instr::Jal { rd: r, simm: 1 << 20 },
+             EncodeError::new(InstFormat::J, 1 << 20, EncodeReason::OutOfRange)),
+            (Instr::Jal { rd: r, simm: -5 },
+             EncodeError::new(InstFormat::J, -5, EncodeReason::Misaligned)),
+            // RV64I instructions
+            (Instr::Op32 { rd: r, rs1: r, rs2: r, alu_op: RvALUOp::Add },
+             EncodeError::new(InstFormat::R, 0, EncodeReason::Unsupported)),
+            (Instr::OpImm32 { rd: r, rs1: r, simm: 1, alu_op: RvALUOpImm::Addi },
+             EncodeError::new(InstFormat::I, 1, EncodeReason::Unsupported)),
+            (Instr::Load { rd: r, rs1: r, simm: 8, width: RvWidth::Double },
+             EncodeError::new(InstFormat::I, 8, EncodeReason::Unsupported)),
+            (Instr::Load { rd: r, rs1: r, simm: 4, width: RvWidth::WordUnsigned },
+             EncodeError::new(InstFormat::I, 4, EncodeReason::Unsupported)),
+            (Instr::Store { rs1: r, rs2: r, simm: -8, width: RvWidth::Double },
+             EncodeError::new(InstFormat::S, -8, EncodeReason::Unsupported)),
+        ];
+        for (inst, err) in cases {
+            assert_eq!(Rv32::encode(inst), Err(err));
+        }
+    }
+}
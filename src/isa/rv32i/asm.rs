@@ -0,0 +1,751 @@
+//! A simple textual assembler for RV32I.
+//!
+//! This accepts the same syntax produced by the [std::fmt::Display]
+//! implementation for [Instr], along with:
+//!
+//! - Calling convention register names (`ra`, `sp`, `a0`, ...)
+//! - Labels (`loop:`), which may be used as branch/jump targets
+//! - Comments, starting with `#`
+//! - The `.word` and `.org` directives
+//! - Some common pseudo-instructions (`nop`, `li`, `la`, `mv`, `not`,
+//!   `neg`, `j`, `jr`, `ret`, `beqz`, `bnez`, `csrr`, `csrw`, ...)
+//!
+//! Numeric branch/jump targets are interpreted as offsets relative to the
+//! instruction, which matches the output of the disassembler.
+//!
+//! Floating-point (RV32F/RV32D) instructions are not accepted yet.
+
+use crate::isa::*;
+use crate::isa::rv32i::*;
+use std::collections::HashMap;
+
+/// The maximum size of an assembled image (in bytes).
+pub const MAX_IMAGE_SIZE: u32 = 0x0100_0000;
+
+/// Different kinds of errors produced by the assembler.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum AsmErrorKind {
+    /// The mnemonic is not a known instruction or directive.
+    UnknownMnemonic(String),
+    /// An operand could not be parsed.
+    InvalidOperand(String),
+    /// The wrong number of operands were provided.
+    OperandCount { expected: usize, found: usize },
+    /// A label was used but never defined.
+    UndefinedLabel(String),
+    /// A label was defined more than once.
+    DuplicateLabel(String),
+    /// An '.org' directive would move the location counter backwards.
+    OrgBackwards(u32),
+    /// The image would be larger than [MAX_IMAGE_SIZE].
+    ImageTooLarge,
+    /// The instruction could not be encoded.
+    Encode(EncodeError),
+}
+impl std::fmt::Display for AsmErrorKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::UnknownMnemonic(s) => write!(f, "unknown mnemonic '{}'", s),
+            Self::InvalidOperand(s) => write!(f, "invalid operand '{}'", s),
+            Self::OperandCount { expected, found } => {
+                write!(f, "expected {} operands, found {}", expected, found)
+            },
+            Self::UndefinedLabel(s) => write!(f, "undefined label '{}'", s),
+            Self::DuplicateLabel(s) => write!(f, "duplicate label '{}'", s),
+            Self::OrgBackwards(addr) => {
+                write!(f, "'.org 0x{:x}' moves backwards", addr)
+            },
+            Self::ImageTooLarge => {
+                write!(f, "image is larger than 0x{:x} bytes", MAX_IMAGE_SIZE)
+            },
+            Self::Encode(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+/// An error produced by the assembler.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct AsmError {
+    /// The line number (starting from 1) where the error occurred.
+    pub line: usize,
+    /// The kind of error.
+    pub kind: AsmErrorKind,
+}
+impl std::fmt::Display for AsmError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "line {}: {}", self.line, self.kind)
+    }
+}
+impl std::error::Error for AsmError {}
+
+/// A single statement (an instruction or directive) in the source.
+struct Stmt<'a> {
+    /// Line number
+    line: usize,
+    /// Offset of this statement in the output image
+    pc: u32,
+    /// Instruction mnemonic or directive
+    mnemonic: &'a str,
+    /// List of operands
+    ops: Vec<&'a str>,
+}
+
+/// Assemble some source text into a flat binary image.
+///
+/// The image starts at offset zero, and labels evaluate to offsets into
+/// the image. Images may be at most [MAX_IMAGE_SIZE] bytes.
+pub fn assemble(src: &str) -> Result<Vec<u8>, AsmError> {
+    let mut labels: HashMap<&str, u32> = HashMap::new();
+    let mut stmts = Vec::new();
+    let mut pc = 0u32;
+
+    // Collect all labels and statements, and compute the location of each
+    // statement. Label references are not resolved during this pass.
+    for (idx, text) in src.lines().enumerate() {
+        let line = idx + 1;
+        let err = |kind| AsmError { line, kind };
+        let mut text = match text.split_once('#') {
+            Some((text, _comment)) => text.trim(),
+            None => text.trim(),
+        };
+
+        while let Some((label, rest)) = split_label(text) {
+            if labels.insert(label, pc).is_some() {
+                return Err(err(AsmErrorKind::DuplicateLabel(label.into())));
+            }
+            text = rest.trim_start();
+        }
+        if text.is_empty() {
+            continue;
+        }
+
+        let (mnemonic, rest) = text.split_once(char::is_whitespace)
+            .unwrap_or((text, ""));
+        let ops = if rest.trim().is_empty() {
+            Vec::new()
+        } else {
+            rest.split(',').map(str::trim).collect()
+        };
+        let stmt = Stmt { line, pc, mnemonic, ops };
+
+        if mnemonic == ".org" {
+            let addr = stmt.org()?;
+            if addr < pc {
+                return Err(err(AsmErrorKind::OrgBackwards(addr)));
+            }
+            pc = addr;
+        } else {
+            let len = 4 * stmt.lower(None)?.len() as u32;
+            pc = pc.checked_add(len)
+                .ok_or(err(AsmErrorKind::ImageTooLarge))?;
+        }
+        if pc > MAX_IMAGE_SIZE {
+            return Err(err(AsmErrorKind::ImageTooLarge));
+        }
+        stmts.push(stmt);
+    }
+
+    // Emit all statements
+    let mut res = Vec::new();
+    for stmt in stmts.iter() {
+        if stmt.mnemonic == ".org" {
+            res.resize(stmt.org()? as usize, 0);
+            continue;
+        }
+        for word in stmt.lower(Some(&labels))? {
+            res.extend_from_slice(&word.to_le_bytes());
+        }
+    }
+    Ok(res)
+}
+
+/// Split a leading label definition (`label:`) from some text.
+fn split_label(text: &str) -> Option<(&str, &str)> {
+    let (label, rest) = text.split_once(':')?;
+    if is_ident(label) { Some((label, rest)) } else { None }
+}
+
+/// Returns true if the string is a valid label name.
+fn is_ident(s: &str) -> bool {
+    let mut chars = s.chars();
+    match chars.next() {
+        Some(c) if c.is_ascii_alphabetic() || c == '_' || c == '.' => {},
+        _ => return false,
+    }
+    chars.all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '.')
+}
+
+/// Parse an integer literal (decimal, or hexadecimal/binary with a '0x' or
+/// '0b' prefix, with an optional leading '-').
+fn parse_int(s: &str) -> Option<i64> {
+    let (neg, s) = match s.strip_prefix('-') {
+        Some(rest) => (true, rest),
+        None => (false, s),
+    };
+    let val = if let Some(hex) = s.strip_prefix("0x") {
+        i64::from_str_radix(hex, 16).ok()?
+    } else if let Some(bin) = s.strip_prefix("0b") {
+        i64::from_str_radix(bin, 2).ok()?
+    } else if !s.is_empty() && s.bytes().all(|c| c.is_ascii_digit()) {
+        s.parse::<i64>().ok()?
+    } else {
+        return None;
+    };
+    Some(if neg { -val } else { val })
+}
+
+/// All R-type ALU operations.
+const ALU_OPS: [RvALUOp; 10] = [
+    RvALUOp::Add, RvALUOp::Sub, RvALUOp::Sll, RvALUOp::Slt, RvALUOp::Sltu,
+    RvALUOp::Xor, RvALUOp::Srl, RvALUOp::Sra, RvALUOp::Or, RvALUOp::And,
+];
+
+/// All RV32M operations.
+const MUL_OPS: [RvMulOp; 8] = [
+    RvMulOp::Mul, RvMulOp::Mulh, RvMulOp::Mulhsu, RvMulOp::Mulhu,
+    RvMulOp::Div, RvMulOp::Divu, RvMulOp::Rem, RvMulOp::Remu,
+];
+
+/// All I-type ALU operations.
+const ALU_IMM_OPS: [RvALUOpImm; 9] = [
+    RvALUOpImm::Addi, RvALUOpImm::Slti, RvALUOpImm::Sltiu, RvALUOpImm::Xori,
+    RvALUOpImm::Ori, RvALUOpImm::Andi, RvALUOpImm::Slli, RvALUOpImm::Srli,
+    RvALUOpImm::Srai,
+];
+
+/// All load/store widths.
+const WIDTHS: [RvWidth; 5] = [
+    RvWidth::Byte, RvWidth::Half, RvWidth::Word,
+    RvWidth::ByteUnsigned, RvWidth::HalfUnsigned,
+];
+
+/// All branch operations.
+const BRN_OPS: [RvBranchOp; 6] = [
+    RvBranchOp::Eq, RvBranchOp::Ne, RvBranchOp::Lt,
+    RvBranchOp::Ge, RvBranchOp::Ltu, RvBranchOp::Geu,
+];
+
+/// All RV32A operations.
+const AMO_OPS: [RvAmoOp; 11] = [
+    RvAmoOp::Lr, RvAmoOp::Sc, RvAmoOp::Swap, RvAmoOp::Add, RvAmoOp::Xor,
+    RvAmoOp::And, RvAmoOp::Or, RvAmoOp::Min, RvAmoOp::Max, RvAmoOp::Minu,
+    RvAmoOp::Maxu,
+];
+
+/// All CSR operations.
+const CSR_OPS: [RvCsrOp; 3] = [RvCsrOp::Rw, RvCsrOp::Rs, RvCsrOp::Rc];
+
+impl Stmt<'_> {
+    fn err(&self, kind: AsmErrorKind) -> AsmError {
+        AsmError { line: self.line, kind }
+    }
+
+    /// Check the number of operands.
+    fn expect(&self, n: usize) -> Result<(), AsmError> {
+        if self.ops.len() != n {
+            return Err(self.err(AsmErrorKind::OperandCount {
+                expected: n, found: self.ops.len()
+            }));
+        }
+        Ok(())
+    }
+
+    fn invalid(&self, s: &str) -> AsmError {
+        self.err(AsmErrorKind::InvalidOperand(s.into()))
+    }
+
+    /// Parse operand 'idx' as a register.
+    fn reg(&self, idx: usize) -> Result<Reg, AsmError> {
+        Reg::from_name(self.ops[idx]).ok_or(self.invalid(self.ops[idx]))
+    }
+
+    /// Parse operand 'idx' as an integer.
+    fn int(&self, idx: usize) -> Result<i64, AsmError> {
+        parse_int(self.ops[idx]).ok_or(self.invalid(self.ops[idx]))
+    }
+
+    /// Parse operand 'idx' as a signed 32-bit immediate.
+    fn simm(&self, idx: usize) -> Result<i32, AsmError> {
+        i32::try_from(self.int(idx)?).map_err(|_| self.invalid(self.ops[idx]))
+    }
+
+    /// Parse operand 'idx' as a 32-bit value (either signed or unsigned).
+    fn word(&self, idx: usize) -> Result<u32, AsmError> {
+        let val = self.int(idx)?;
+        if val < i32::MIN as i64 || val > u32::MAX as i64 {
+            return Err(self.invalid(self.ops[idx]));
+        }
+        Ok(val as u32)
+    }
+
+    /// Parse operand 'idx' as a memory operand (`imm(reg)` or `(reg)`).
+    fn mem(&self, idx: usize) -> Result<(i32, Reg), AsmError> {
+        let op = self.ops[idx];
+        let (imm, rest) = op.split_once('(').ok_or(self.invalid(op))?;
+        let reg = rest.strip_suffix(')').ok_or(self.invalid(op))?;
+        let reg = Reg::from_name(reg.trim()).ok_or(self.invalid(op))?;
+        let imm = imm.trim();
+        let imm = if imm.is_empty() {
+            0
+        } else {
+            parse_int(imm).and_then(|x| i32::try_from(x).ok())
+                .ok_or(self.invalid(op))?
+        };
+        Ok((imm, reg))
+    }
+
+    /// Parse operand 'idx' as a CSR.
+    fn csr(&self, idx: usize) -> Result<Csr, AsmError> {
+        let op = self.ops[idx];
+        if let Some(csr) = Csr::from_name(op) {
+            return Ok(csr);
+        }
+        match parse_int(op) {
+            Some(num) if (0..4096).contains(&num) => Ok(Csr::new(num as u32)),
+            _ => Err(self.invalid(op)),
+        }
+    }
+
+    /// Parse operand 'idx' as a fence set (`iorw`, `rw`, `0`, ...).
+    fn fence_set(&self, idx: usize) -> Result<RvFenceSet, AsmError> {
+        let op = self.ops[idx];
+        if op == "0" {
+            return Ok(RvFenceSet::new(0));
+        }
+        let mut bits = 0;
+        for c in op.chars() {
+            let bit = match c {
+                'i' => RvFenceSet::I,
+                'o' => RvFenceSet::O,
+                'r' => RvFenceSet::R,
+                'w' => RvFenceSet::W,
+                _ => return Err(self.invalid(op)),
+            };
+            bits |= bit;
+        }
+        Ok(RvFenceSet::new(bits))
+    }
+
+    /// Parse operand 'idx' as a branch/jump target, returning an offset
+    /// relative to 'pc'.
+    ///
+    /// When 'labels' is not provided, labels are assumed to resolve to
+    /// the current location.
+    fn target(&self, idx: usize, pc: u32, labels: Option<&HashMap<&str, u32>>)
+        -> Result<i32, AsmError>
+    {
+        let op = self.ops[idx];
+        if let Some(val) = parse_int(op) {
+            return i32::try_from(val).map_err(|_| self.invalid(op));
+        }
+        if !is_ident(op) {
+            return Err(self.invalid(op));
+        }
+        match labels {
+            None => Ok(0),
+            Some(labels) => match labels.get(op) {
+                Some(addr) => Ok(addr.wrapping_sub(pc) as i32),
+                None => Err(self.err(AsmErrorKind::UndefinedLabel(op.into()))),
+            },
+        }
+    }
+
+    /// Parse the operand to an '.org' directive.
+    fn org(&self) -> Result<u32, AsmError> {
+        self.expect(1)?;
+        self.word(0)
+    }
+
+    /// Lower this statement into a list of encoded words.
+    fn lower(&self, labels: Option<&HashMap<&str, u32>>)
+        -> Result<Vec<u32>, AsmError>
+    {
+        if self.mnemonic == ".word" {
+            self.expect(1)?;
+            if let Ok(val) = self.word(0) {
+                return Ok(vec![val]);
+            }
+            // A label evaluates to its absolute location
+            let off = self.target(0, self.pc, labels)?;
+            return Ok(vec![self.pc.wrapping_add(off as u32)]);
+        }
+
+        self.lower_instrs(labels)?.into_iter()
+            .map(|inst| {
+                Rv32::encode(inst).map_err(|e| self.err(AsmErrorKind::Encode(e)))
+            })
+            .collect()
+    }
+
+    /// Lower this statement into a list of instructions.
+    fn lower_instrs(&self, labels: Option<&HashMap<&str, u32>>)
+        -> Result<Vec<Instr>, AsmError>
+    {
+        let m = self.mnemonic;
+        let pc = self.pc;
+        let zero = Reg::new(0);
+        let ra = Reg::new(1);
+
+        // R-type ALU operations
+        if let Some(alu_op) = ALU_OPS.iter().find(|op| op.to_string() == m) {
+            self.expect(3)?;
+            let (rd, rs1, rs2) = (self.reg(0)?, self.reg(1)?, self.reg(2)?);
+            return Ok(vec![Instr::Op { rd, rs1, rs2, alu_op: *alu_op }]);
+        }
+
+        // RV32M operations
+        if let Some(mul_op) = MUL_OPS.iter().find(|op| op.to_string() == m) {
+            self.expect(3)?;
+            let (rd, rs1, rs2) = (self.reg(0)?, self.reg(1)?, self.reg(2)?);
+            return Ok(vec![Instr::MulDiv { rd, rs1, rs2, mul_op: *mul_op }]);
+        }
+
+        // RV32A operations
+        if let Some(inst) = self.lower_amo(m)? {
+            return Ok(vec![inst]);
+        }
+
+        // I-type ALU operations
+        if let Some(alu_op) = ALU_IMM_OPS.iter().find(|op| op.to_string() == m) {
+            self.expect(3)?;
+            let (rd, rs1, simm) = (self.reg(0)?, self.reg(1)?, self.simm(2)?);
+            return Ok(vec![Instr::OpImm { rd, rs1, simm, alu_op: *alu_op }]);
+        }
+
+        // Loads and stores
+        if let Some(width) = WIDTHS.iter().find(|w| format!("l{}", w) == m) {
+            self.expect(2)?;
+            let rd = self.reg(0)?;
+            let (simm, rs1) = self.mem(1)?;
+            return Ok(vec![Instr::Load { rd, rs1, simm, width: *width }]);
+        }
+        if let Some(width) = WIDTHS[..3].iter().find(|w| format!("s{}", w) == m) {
+            self.expect(2)?;
+            let rs2 = self.reg(0)?;
+            let (simm, rs1) = self.mem(1)?;
+            return Ok(vec![Instr::Store { rs1, rs2, simm, width: *width }]);
+        }
+
+        // Branches
+        if let Some(brn_op) = BRN_OPS.iter().find(|op| format!("b{}", op) == m) {
+            self.expect(3)?;
+            let (rs1, rs2) = (self.reg(0)?, self.reg(1)?);
+            let simm = self.target(2, pc, labels)?;
+            return Ok(vec![Instr::Branch { rs1, rs2, simm, brn_op: *brn_op }]);
+        }
+
+        // CSR accesses
+        if let Some(csr_op) = CSR_OPS.iter().find(|op| format!("csr{}", op) == m) {
+            self.expect(3)?;
+            let (rd, csr, rs1) = (self.reg(0)?, self.csr(1)?, self.reg(2)?);
+            return Ok(vec![Instr::Csr { rd, rs1, csr, csr_op: *csr_op }]);
+        }
+        if let Some(csr_op) = CSR_OPS.iter().find(|op| format!("csr{}i", op) == m) {
+            self.expect(3)?;
+            let (rd, csr, uimm) = (self.reg(0)?, self.csr(1)?, self.word(2)?);
+            return Ok(vec![Instr::CsrImm { rd, uimm, csr, csr_op: *csr_op }]);
+        }
+
+        let res = match m {
+            "lui" | "auipc" => {
+                self.expect(2)?;
+                let (rd, uimm) = (self.reg(0)?, self.word(1)?);
+                if m == "lui" {
+                    Instr::Lui { rd, uimm }
+                } else {
+                    Instr::AuiPc { rd, uimm }
+                }
+            },
+            "jal" => {
+                if self.ops.len() == 1 {
+                    Instr::Jal { rd: ra, simm: self.target(0, pc, labels)? }
+                } else {
+                    self.expect(2)?;
+                    let rd = self.reg(0)?;
+                    Instr::Jal { rd, simm: self.target(1, pc, labels)? }
+                }
+            },
+            "jalr" => {
+                match self.ops.len() {
+                    // jalr rs1
+                    1 => Instr::Jalr { rd: ra, rs1: self.reg(0)?, simm: 0 },
+                    // jalr rd, imm(rs1)
+                    2 => {
+                        let rd = self.reg(0)?;
+                        let (simm, rs1) = self.mem(1)?;
+                        Instr::Jalr { rd, rs1, simm }
+                    },
+                    // jalr rd, rs1, imm
+                    _ => {
+                        self.expect(3)?;
+                        let (rd, rs1) = (self.reg(0)?, self.reg(1)?);
+                        Instr::Jalr { rd, rs1, simm: self.simm(2)? }
+                    },
+                }
+            },
+            "fence" => {
+                if self.ops.is_empty() {
+                    let all = RvFenceSet::new(0b1111);
+                    Instr::Fence { pred: all, succ: all }
+                } else {
+                    self.expect(2)?;
+                    let (pred, succ) = (self.fence_set(0)?, self.fence_set(1)?);
+                    Instr::Fence { pred, succ }
+                }
+            },
+            "fence.tso" | "fence.i" | "ecall" | "ebreak" | "mret" | "sret" |
+            "wfi" | "nop" | "ret" => {
+                self.expect(0)?;
+                match m {
+                    "fence.tso" => Instr::FenceTso,
+                    "fence.i"   => Instr::FenceI,
+                    "ecall"     => Instr::Ecall,
+                    "ebreak"    => Instr::Ebreak,
+                    "mret"      => Instr::Mret,
+                    "sret"      => Instr::Sret,
+                    "wfi"       => Instr::Wfi,
+                    "nop"       => Instr::OpImm {
+                        rd: zero, rs1: zero, simm: 0, alu_op: RvALUOpImm::Addi
+                    },
+                    _ => Instr::Jalr { rd: zero, rs1: ra, simm: 0 },
+                }
+            },
+
+            // Pseudo-instructions
+            "li" => {
+                self.expect(2)?;
+                let (rd, val) = (self.reg(0)?, self.word(1)? as i32);
+                return Ok(Self::lower_li(rd, val));
+            },
+            "la" => {
+                self.expect(2)?;
+                let rd = self.reg(0)?;
+                let off = self.target(1, pc, labels)?;
+                let (hi, lo) = split_hi_lo(off);
+                return Ok(vec![
+                    Instr::AuiPc { rd, uimm: hi },
+                    Instr::OpImm { rd, rs1: rd, simm: lo, alu_op: RvALUOpImm::Addi },
+                ]);
+            },
+            "mv" => {
+                self.expect(2)?;
+                let (rd, rs1) = (self.reg(0)?, self.reg(1)?);
+                Instr::OpImm { rd, rs1, simm: 0, alu_op: RvALUOpImm::Addi }
+            },
+            "not" => {
+                self.expect(2)?;
+                let (rd, rs1) = (self.reg(0)?, self.reg(1)?);
+                Instr::OpImm { rd, rs1, simm: -1, alu_op: RvALUOpImm::Xori }
+            },
+            "neg" => {
+                self.expect(2)?;
+                let (rd, rs2) = (self.reg(0)?, self.reg(1)?);
+                Instr::Op { rd, rs1: zero, rs2, alu_op: RvALUOp::Sub }
+            },
+            "j" => {
+                self.expect(1)?;
+                Instr::Jal { rd: zero, simm: self.target(0, pc, labels)? }
+            },
+            "jr" => {
+                self.expect(1)?;
+                Instr::Jalr { rd: zero, rs1: self.reg(0)?, simm: 0 }
+            },
+            "beqz" | "bnez" => {
+                self.expect(2)?;
+                let rs1 = self.reg(0)?;
+                let simm = self.target(1, pc, labels)?;
+                let brn_op = if m == "beqz" { RvBranchOp::Eq }
+                    else { RvBranchOp::Ne };
+                Instr::Branch { rs1, rs2: zero, simm, brn_op }
+            },
+            "bgt" | "ble" | "bgtu" | "bleu" => {
+                // These are the same as the normal branches with the source
+                // operands swapped.
+                self.expect(3)?;
+                let (rs2, rs1) = (self.reg(0)?, self.reg(1)?);
+                let simm = self.target(2, pc, labels)?;
+                let brn_op = match m {
+                    "bgt"  => RvBranchOp::Lt,
+                    "ble"  => RvBranchOp::Ge,
+                    "bgtu" => RvBranchOp::Ltu,
+                    _      => RvBranchOp::Geu,
+                };
+                Instr::Branch { rs1, rs2, simm, brn_op }
+            },
+            "csrr" => {
+                self.expect(2)?;
+                let (rd, csr) = (self.reg(0)?, self.csr(1)?);
+                Instr::Csr { rd, rs1: zero, csr, csr_op: RvCsrOp::Rs }
+            },
+            "csrw" => {
+                self.expect(2)?;
+                let (csr, rs1) = (self.csr(0)?, self.reg(1)?);
+                Instr::Csr { rd: zero, rs1, csr, csr_op: RvCsrOp::Rw }
+            },
+            _ => return Err(self.err(AsmErrorKind::UnknownMnemonic(m.into()))),
+        };
+        Ok(vec![res])
+    }
+
+    /// Lower an RV32A operation (ie. `amoadd.w.aq rd, rs2, (rs1)`). 
+    fn lower_amo(&self, m: &str) -> Result<Option<Instr>, AsmError> {
+        let (m, aq, rl) = if let Some(m) = m.strip_suffix(".aqrl") {
+            (m, true, true)
+        } else if let Some(m) = m.strip_suffix(".aq") {
+            (m, true, false)
+        } else if let Some(m) = m.strip_suffix(".rl") {
+            (m, false, true)
+        } else {
+            (m, false, false)
+        };
+        let amo_op = match m.strip_suffix(".w")
+            .and_then(|m| AMO_OPS.iter().find(|op| op.to_string() == m)) 
+        {
+            Some(op) => *op,
+            None => return Ok(None),
+        };
+
+        self.expect(if amo_op == RvAmoOp::Lr { 2 } else { 3 })?;
+        let rd = self.reg(0)?;
+        let rs2 = if amo_op == RvAmoOp::Lr { Reg::new(0) } else { self.reg(1)? };
+        let idx = self.ops.len() - 1;
+        let (off, rs1) = self.mem(idx)?;
+        if off != 0 {
+            return Err(self.invalid(self.ops[idx]));
+        }
+        Ok(Some(Instr::Amo { rd, rs1, rs2, aq, rl, amo_op }))
+    }
+
+    /// Lower the 'li' pseudo-instruction.
+    fn lower_li(rd: Reg, val: i32) -> Vec<Instr> {
+        if (-2048..2048).contains(&val) {
+            let rs1 = Reg::new(0);
+            return vec![
+                Instr::OpImm { rd, rs1, simm: val, alu_op: RvALUOpImm::Addi }
+            ];
+        }
+        let (hi, lo) = split_hi_lo(val);
+        let mut res = vec![Instr::Lui { rd, uimm: hi }];
+        if lo != 0 {
+            res.push(Instr::OpImm { rd, rs1: rd, simm: lo, alu_op: RvALUOpImm::Addi });
+        }
+        res
+    }
+}
+
+/// Split a 32-bit value into a U-type immediate and a (sign-extended)
+/// 12-bit immediate, such that 'hi + lo == val'.
+fn split_hi_lo(val: i32) -> (u32, i32) {
+    let lo = ((val as u32) << 20) as i32 >> 20;
+    let hi = (val as u32).wrapping_sub(lo as u32);
+    (hi, lo)
+}
+
+
+#[cfg(test)]
+mod test {
+    use crate::isa::*;
+    use crate::isa::rv32i::*;
+    use crate::isa::rv32i::asm::*;
+
+    fn words(img: &[u8]) -> Vec<u32> {
+        img.chunks(4).map(|c| u32::from_le_bytes(c.try_into().unwrap()))
+            .collect()
+    }
+
+    #[test]
+    fn assemble_display_syntax() {
+        let src = "
+            addi   x1, x2, -4
+            lw     x5, 8(x2)
+            sw     x5, -12(x2)
+            jalr   x0, 0(x1)
+            lui    x3, 0x12345000
+            csrrs  x5, mstatus, x0
+            fence  iorw, iorw
+            fence.tso
+            beq    x1, x2, -8
+            mulhsu x1, x2, x3
+            lr.w.aq x5, (x10)
+            amoadd.w x5, x7, (x6)
+        ";
+        let img = assemble(src).unwrap();
+        let text: Vec<String> = words(&img).into_iter()
+            .map(|w| Rv32::decode(w).unwrap().to_string())
+            .collect();
+        let expected: Vec<String> = src.lines().map(str::trim)
+            .filter(|l| !l.is_empty()).map(String::from).collect();
+        assert_eq!(text, expected);
+    }
+
+    #[test]
+    fn assemble_labels_and_pseudo() {
+        let src = "
+        start:
+            li   a0, 0x12345678     # lui + addi
+            li   a1, 10
+            mv   s0, sp
+        loop:
+            addi a1, a1, -1
+            bnez a1, loop
+            la   t0, data
+            j    end
+            nop
+        end:
+            ret
+            .org 0x40
+        data:
+            .word 0xdeadbeef
+            .word start
+        ";
+        let w = words(&assemble(src).unwrap());
+        let d = |i: usize| Rv32::decode(w[i]).unwrap();
+        let r = Reg::from_name;
+        assert_eq!(w.len(), 0x48 / 4);
+        assert_eq!(d(0), Instr::Lui { rd: r("a0").unwrap(), uimm: 0x1234_5000 });
+        assert_eq!(d(1), Instr::OpImm {
+            rd: r("a0").unwrap(), rs1: r("a0").unwrap(), simm: 0x678,
+            alu_op: RvALUOpImm::Addi
+        });
+        assert_eq!(d(5), Instr::Branch {
+            rs1: r("a1").unwrap(), rs2: r("zero").unwrap(), simm: -4,
+            brn_op: RvBranchOp::Ne
+        });
+        // 'la t0, data' is at 0x18, and 'data' is at 0x40
+        assert_eq!(d(6), Instr::AuiPc { rd: r("t0").unwrap(), uimm: 0 });
+        assert_eq!(d(7), Instr::OpImm {
+            rd: r("t0").unwrap(), rs1: r("t0").unwrap(), simm: 0x28,
+            alu_op: RvALUOpImm::Addi
+        });
+        assert_eq!(d(8), Instr::Jal { rd: r("zero").unwrap(), simm: 8 });
+        assert_eq!(d(10), Instr::Jalr {
+            rd: r("zero").unwrap(), rs1: r("ra").unwrap(), simm: 0
+        });
+        assert!(w[11..16].iter().all(|x| *x == 0));
+        assert_eq!(w[16], 0xdead_beef);
+        assert_eq!(w[17], 0);
+    }
+
+    #[test]
+    fn assemble_errors() {
+        let cases = [
+            ("foo x1, x2", AsmErrorKind::UnknownMnemonic("foo".into())),
+            ("addi x1, x2",
+             AsmErrorKind::OperandCount { expected: 3, found: 2 }),
+            ("addi x1, x32, 0", AsmErrorKind::InvalidOperand("x32".into())),
+            ("j nowhere", AsmErrorKind::UndefinedLabel("nowhere".into())),
+            ("a:\na:", AsmErrorKind::DuplicateLabel("a".into())),
+            ("nop\n.org 0", AsmErrorKind::OrgBackwards(0)),
+            (".org 0x1000001", AsmErrorKind::ImageTooLarge),
+            (".org 0xfffffffc\nnop\nnop", AsmErrorKind::ImageTooLarge),
+            (".org 0xfffffffc", AsmErrorKind::ImageTooLarge),
+            (".org 0xfffffff8\n.word 0", AsmErrorKind::ImageTooLarge),
+            ("addi x1, x1, 4096", AsmErrorKind::Encode(
+                EncodeError::new(InstFormat::I, 4096, EncodeReason::OutOfRange)
+            )),
+        ];
+        for (src, kind) in cases {
+            assert_eq!(assemble(src).unwrap_err().kind, kind, "{}", src);
+        }
+    }
+}
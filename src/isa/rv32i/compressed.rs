@@ -0,0 +1,440 @@
+//! Decoding for 16-bit encodings from the RV32C extension.
+//!
+//! Compressed instructions are expanded into their equivalent [Instr].
+
+use crate::isa::rv32i::*;
+
+/// A decoded instruction, along with the length of its encoding.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct DecodedInstr {
+    /// The decoded (or expanded) instruction.
+    pub inst: Instr,
+    /// The length of the original encoding in bytes.
+    pub len: usize,
+}
+impl DecodedInstr {
+    /// Returns true if this instruction was expanded from a compressed
+    /// (16-bit) encoding.
+    pub fn is_compressed(&self) -> bool {
+        self.len == 2
+    }
+}
+impl std::fmt::Display for DecodedInstr {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.inst)
+    }
+}
+
+/// An iterator over instructions in a stream of little-endian bytes.
+///
+/// Each item is the offset of an instruction in the stream, along with the
+/// result of decoding it. Undecodable encodings are skipped over using the
+/// length given by the low bits of the encoding.
+pub struct DecodeIter<'a> {
+    bytes: &'a [u8],
+    off: usize,
+    ext: RvExtensions,
+}
+impl Iterator for DecodeIter<'_> {
+    type Item = (usize, Result<DecodedInstr, DecodeError>);
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.off >= self.bytes.len() {
+            return None;
+        }
+        let off = self.off;
+        let rest = &self.bytes[off..];
+        let res = Rv32::decode_bytes(rest, self.ext);
+        self.off += match res {
+            Ok(ref inst) => inst.len,
+            Err(_) if rest.len() < 2 => rest.len(),
+            Err(_) => {
+                let len = Rv32::encoding_len(u16::from_le_bytes([rest[0], rest[1]]));
+                len.min(rest.len())
+            },
+        };
+        Some((off, res))
+    }
+}
+
+impl Rv32 {
+    /// Returns the length (in bytes) of an encoding, given the first
+    /// 16-bit parcel.
+    pub fn encoding_len(parcel: u16) -> usize {
+        if (parcel & 0b11) != 0b11 {
+            2
+        } else if (parcel & 0b11100) != 0b11100 {
+            4
+        } else if (parcel & 0b111111) == 0b011111 {
+            6
+        } else if (parcel & 0b1111111) == 0b0111111 {
+            8
+        } else {
+            // Encodings longer than 64 bits are not defined yet, so just
+            // skip over the first parcel.
+            2
+        }
+    }
+
+    /// Decode the instruction at the start of a slice of little-endian
+    /// bytes, which may be either a 16-bit or 32-bit encoding.
+    pub fn decode_bytes(bytes: &[u8], ext: RvExtensions)
+        -> Result<DecodedInstr, DecodeError>
+    {
+        let truncated = |enc| Err(DecodeError::new(enc, DecodeField::Opcode,
+            DecodeReason::Truncated));
+        if bytes.len() < 2 {
+            return truncated(bytes.first().copied().unwrap_or(0) as u32);
+        }
+        let parcel = u16::from_le_bytes([bytes[0], bytes[1]]);
+        match Self::encoding_len(parcel) {
+            2 => {
+                let inst = Self::decode_compressed(parcel, ext)?;
+                Ok(DecodedInstr { inst, len: 2 })
+            },
+            4 => {
+                if bytes.len() < 4 {
+                    return truncated(parcel as u32);
+                }
+                let enc = u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+                let inst = Self::decode_with(enc, ext)?;
+                Ok(DecodedInstr { inst, len: 4 })
+            },
+            _ => Err(DecodeError::new(parcel as u32, DecodeField::Opcode,
+                DecodeReason::Unsupported)),
+        }
+    }
+
+    /// Returns an iterator over a stream of mixed 16-bit and 32-bit
+    /// encodings in little-endian bytes.
+    pub fn decode_stream(bytes: &[u8], ext: RvExtensions) -> DecodeIter<'_> {
+        DecodeIter { bytes, off: 0, ext }
+    }
+
+    /// Decode a 16-bit compressed encoding into the equivalent instruction.
+    pub fn decode_compressed(enc: u16, ext: RvExtensions)
+        -> Result<Instr, DecodeError>
+    {
+        let enc = enc as u32;
+        let err = |field, reason| Err(DecodeError::new(enc, field, reason));
+        if !ext.contains(RvExtensions::C) {
+            return err(DecodeField::Opcode, DecodeReason::Disabled);
+        }
+
+        // Extract bits [hi:lo] from the encoding.
+        let bits = |hi: u32, lo: u32| (enc >> lo) & ((1 << (hi - lo + 1)) - 1);
+
+        let quadrant = enc & 0b11;
+        let f3 = bits(15, 13);
+
+        // Full register fields
+        let rd  = Reg::new(bits(11, 7));
+        let rs2 = Reg::new(bits(6, 2));
+        // Register fields limited to x8-x15
+        let rd_p  = Reg::new(bits(4, 2) + 8);
+        let rs1_p = Reg::new(bits(9, 7) + 8);
+
+        let x0 = Reg::new(0);
+        let ra = Reg::new(1);
+        let sp = Reg::new(2);
+
+        // Common immediate formats
+        let imm6 = Self::sext32((bits(12, 12) << 5) | bits(6, 2), 6);
+        let shamt = (bits(12, 12) << 5) | bits(6, 2);
+        let lw_uimm = (bits(12, 10) << 3) | (bits(6, 6) << 2) | (bits(5, 5) << 6);
+        let j_imm = Self::sext32(
+              (bits(12, 12) << 11) | (bits(11, 11) << 4) | (bits(10, 9) << 8)
+            | (bits(8, 8) << 10)   | (bits(7, 7) << 6)   | (bits(6, 6) << 7)
+            | (bits(5, 3) << 1)    | (bits(2, 2) << 5),
+            12
+        );
+        let b_imm = Self::sext32(
+              (bits(12, 12) << 8) | (bits(11, 10) << 3) | (bits(6, 5) << 6)
+            | (bits(4, 3) << 1)   | (bits(2, 2) << 5),
+            9
+        );
+
+        let addi = |rd, rs1, simm| Instr::OpImm {
+            rd, rs1, simm, alu_op: RvALUOpImm::Addi
+        };
+
+        // Floating-point loads and stores use bit 14 to select between 
+        // single and double-precision.
+        let fmt = if (f3 & 0b010) != 0 { RvFpFmt::S } else { RvFpFmt::D };
+        if matches!(f3, 0b001 | 0b011 | 0b101 | 0b111) && quadrant != 0b01 
+            && !ext.contains(fmt.extension()) 
+        {
+            return err(DecodeField::Funct3, DecodeReason::Disabled);
+        }
+        let frd   = FReg::new(bits(11, 7));
+        let frs2  = FReg::new(bits(6, 2));
+        let frd_p = FReg::new(bits(4, 2) + 8);
+        let ld_uimm = (bits(12, 10) << 3) | (bits(6, 5) << 6);
+
+        let res = match (quadrant, f3) {
+            // C.ADDI4SPN
+            (0b00, 0b000) => {
+                let uimm = (bits(12, 11) << 4) | (bits(10, 7) << 6)
+                    | (bits(6, 6) << 2) | (bits(5, 5) << 3);
+                if uimm == 0 {
+                    return err(DecodeField::Operand, DecodeReason::Reserved);
+                }
+                addi(rd_p, sp, uimm as i32)
+            },
+            // C.LW
+            (0b00, 0b010) => Instr::Load {
+                rd: rd_p, rs1: rs1_p, simm: lw_uimm as i32, width: RvWidth::Word
+            },
+            // C.SW
+            (0b00, 0b110) => Instr::Store {
+                rs1: rs1_p, rs2: rd_p, simm: lw_uimm as i32, width: RvWidth::Word
+            },
+            (0b00, 0b100) => {
+                return err(DecodeField::Funct3, DecodeReason::Reserved);
+            },
+            // C.FLD/C.FLW
+            (0b00, 0b001) | (0b00, 0b011) => {
+                let uimm = if fmt == RvFpFmt::D { ld_uimm } else { lw_uimm };
+                Instr::FpLoad { rd: frd_p, rs1: rs1_p, simm: uimm as i32, fmt }
+            },
+            // C.FSD/C.FSW
+            (0b00, 0b101) | (0b00, 0b111) => {
+                let uimm = if fmt == RvFpFmt::D { ld_uimm } else { lw_uimm };
+                Instr::FpStore { rs1: rs1_p, rs2: frd_p, simm: uimm as i32, fmt }
+            },
+
+            // C.NOP/C.ADDI
+            (0b01, 0b000) => addi(rd, rd, imm6),
+            // C.JAL
+            (0b01, 0b001) => Instr::Jal { rd: ra, simm: j_imm },
+            // C.LI
+            (0b01, 0b010) => addi(rd, x0, imm6),
+            (0b01, 0b011) => {
+                // C.ADDI16SP
+                if rd == sp {
+                    let simm = Self::sext32(
+                          (bits(12, 12) << 9) | (bits(6, 6) << 4)
+                        | (bits(5, 5) << 6) | (bits(4, 3) << 7)
+                        | (bits(2, 2) << 5),
+                        10
+                    );
+                    if simm == 0 {
+                        return err(DecodeField::Operand, DecodeReason::Reserved);
+                    }
+                    addi(sp, sp, simm)
+                }
+                // C.LUI
+                else {
+                    if imm6 == 0 {
+                        return err(DecodeField::Operand, DecodeReason::Reserved);
+                    }
+                    Instr::Lui { rd, uimm: (imm6 << 12) as u32 }
+                }
+            },
+            (0b01, 0b100) => {
+                let rd = rs1_p;
+                match bits(11, 10) {
+                    // C.SRLI/C.SRAI
+                    0b00 | 0b01 => {
+                        // shamt[5] must be zero for RV32C
+                        if shamt >= 32 {
+                            return err(DecodeField::Operand, DecodeReason::Reserved);
+                        }
+                        let alu_op = if bits(11, 10) == 0b00 {
+                            RvALUOpImm::Srli
+                        } else {
+                            RvALUOpImm::Srai
+                        };
+                        Instr::OpImm { rd, rs1: rd, simm: shamt as i32, alu_op }
+                    },
+                    // C.ANDI
+                    0b10 => Instr::OpImm {
+                        rd, rs1: rd, simm: imm6, alu_op: RvALUOpImm::Andi
+                    },
+                    // C.SUB/C.XOR/C.OR/C.AND
+                    _ => {
+                        // These are C.SUBW/C.ADDW in RV64C
+                        if bits(12, 12) != 0 {
+                            return err(DecodeField::Funct3, DecodeReason::Reserved);
+                        }
+                        let alu_op = match bits(6, 5) {
+                            0b00 => RvALUOp::Sub,
+                            0b01 => RvALUOp::Xor,
+                            0b10 => RvALUOp::Or,
+                            _    => RvALUOp::And,
+                        };
+                        Instr::Op { rd, rs1: rd, rs2: rd_p, alu_op }
+                    },
+                }
+            },
+            // C.J
+            (0b01, 0b101) => Instr::Jal { rd: x0, simm: j_imm },
+            // C.BEQZ/C.BNEZ
+            (0b01, 0b110) | (0b01, 0b111) => {
+                let brn_op = if f3 == 0b110 {
+                    RvBranchOp::Eq
+                } else {
+                    RvBranchOp::Ne
+                };
+                Instr::Branch { rs1: rs1_p, rs2: x0, simm: b_imm, brn_op }
+            },
+
+            // C.SLLI
+            (0b10, 0b000) => {
+                if shamt >= 32 {
+                    return err(DecodeField::Operand, DecodeReason::Reserved);
+                }
+                Instr::OpImm {
+                    rd, rs1: rd, simm: shamt as i32, alu_op: RvALUOpImm::Slli
+                }
+            },
+            // C.LWSP
+            (0b10, 0b010) => {
+                if rd == x0 {
+                    return err(DecodeField::Operand, DecodeReason::Reserved);
+                }
+                let uimm = (bits(12, 12) << 5) | (bits(6, 4) << 2)
+                    | (bits(3, 2) << 6);
+                Instr::Load { rd, rs1: sp, simm: uimm as i32, width: RvWidth::Word }
+            },
+            (0b10, 0b100) => {
+                match (bits(12, 12), rd.val(), rs2.val()) {
+                    // C.JR
+                    (0, 0, 0) => {
+                        return err(DecodeField::Operand, DecodeReason::Reserved);
+                    },
+                    (0, _, 0) => Instr::Jalr { rd: x0, rs1: rd, simm: 0 },
+                    // C.MV
+                    (0, _, _) => Instr::Op {
+                        rd, rs1: x0, rs2, alu_op: RvALUOp::Add
+                    },
+                    // C.EBREAK
+                    (_, 0, 0) => Instr::Ebreak,
+                    // C.JALR
+                    (_, _, 0) => Instr::Jalr { rd: ra, rs1: rd, simm: 0 },
+                    // C.ADD
+                    (_, _, _) => Instr::Op {
+                        rd, rs1: rd, rs2, alu_op: RvALUOp::Add
+                    },
+                }
+            },
+            // C.SWSP
+            (0b10, 0b110) => {
+                let uimm = (bits(12, 9) << 2) | (bits(8, 7) << 6);
+                Instr::Store { rs1: sp, rs2, simm: uimm as i32, width: RvWidth::Word }
+            },
+
+            // C.FLDSP/C.FLWSP
+            (0b10, 0b001) | (0b10, 0b011) => {
+                let uimm = if fmt == RvFpFmt::D {
+                    (bits(12, 12) << 5) | (bits(6, 5) << 3) | (bits(4, 2) << 6)
+                } else {
+                    (bits(12, 12) << 5) | (bits(6, 4) << 2) | (bits(3, 2) << 6)
+                };
+                Instr::FpLoad { rd: frd, rs1: sp, simm: uimm as i32, fmt }
+            },
+            // C.FSDSP/C.FSWSP
+            (0b10, 0b101) | (0b10, 0b111) => {
+                let uimm = if fmt == RvFpFmt::D {
+                    (bits(12, 10) << 3) | (bits(9, 7) << 6)
+                } else {
+                    (bits(12, 9) << 2) | (bits(8, 7) << 6)
+                };
+                Instr::FpStore { rs1: sp, rs2: frs2, simm: uimm as i32, fmt }
+            },
+
+            // Not a 16-bit encoding
+            _ => return err(DecodeField::Opcode, DecodeReason::Unsupported),
+        };
+        Ok(res)
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use crate::isa::*;
+    use crate::isa::rv32i::*;
+
+    #[test]
+    fn decode_rv32c() {
+        let cases = [
+            (0x0001, "addi   x0, x0, 0"),
+            (0x0800, "addi   x8, x2, 16"),
+            (0x4108, "lw     x10, 0(x10)"),
+            (0xc11c, "sw     x15, 0(x10)"),
+            (0x1141, "addi   x2, x2, -16"),
+            (0x2009, "jal    x1, 2"),
+            (0x4505, "addi   x10, x0, 1"),
+            (0x6105, "addi   x2, x2, 32"),
+            (0x6785, "lui    x15, 0x00001000"),
+            (0x8105, "srli   x10, x10, 1"),
+            (0x8505, "srai   x10, x10, 1"),
+            (0x997d, "andi   x10, x10, -1"),
+            (0x8d0d, "sub    x10, x10, x11"),
+            (0x8d6d, "and    x10, x10, x11"),
+            (0xa009, "jal    x0, 2"),
+            (0xbffd, "jal    x0, -2"),
+            (0xc501, "beq    x10, x0, 8"),
+            (0x050a, "slli   x10, x10, 2"),
+            (0x40b2, "lw     x1, 12(x2)"),
+            (0x8082, "jalr   x0, 0(x1)"),
+            (0x852e, "add    x10, x0, x11"),
+            (0x9002, "ebreak"),
+            (0x9502, "jalr   x1, 0(x10)"),
+            (0x952e, "add    x10, x10, x11"),
+            (0xc606, "sw     x1, 12(x2)"),
+        ];
+        for (enc, text) in cases {
+            let inst = Rv32::decode_compressed(enc, RvExtensions::ALL).unwrap();
+            assert_eq!(format!("{}", inst), text, "{:04x}", enc);
+            // Compressed encodings are rejected by the 32-bit decoder
+            assert!(Rv32::decode(enc as u32).is_err(), "{:04x}", enc);
+            let res = Rv32::decode_bytes(&enc.to_le_bytes(), RvExtensions::ALL)
+                .unwrap();
+            assert_eq!((res.inst, res.len), (inst, 2));
+        }
+
+        let invalid = [
+            (0x0000, DecodeReason::Reserved),
+            (0x6101, DecodeReason::Reserved),
+            (0x6781, DecodeReason::Reserved),
+            (0x4002, DecodeReason::Reserved),
+            (0x8002, DecodeReason::Reserved),
+            (0x8000, DecodeReason::Reserved),
+            (0x9105, DecodeReason::Reserved),
+            (0x9d0d, DecodeReason::Reserved),
+        ];
+        for (enc, reason) in invalid {
+            let err = Rv32::decode_compressed(enc, RvExtensions::ALL);
+            assert_eq!(err.unwrap_err().reason, reason, "{:04x}", enc);
+        }
+        assert_eq!(Rv32::decode_compressed(0x0001, RvExtensions::M).unwrap_err(),
+            DecodeError::new(0x0001, DecodeField::Opcode, DecodeReason::Disabled)
+        );
+    }
+
+    #[test]
+    fn decode_stream() {
+        let bytes = [
+            0x41, 0x11,             // addi x2, x2, -16
+            0x93, 0x00, 0xc1, 0xff, // addi x1, x2, -4
+            0x00, 0x00,             // illegal
+            0x82, 0x80,             // jalr x0, 0(x1)
+            0x83, 0x22,             // truncated
+        ];
+        let res: Vec<_> = Rv32::decode_stream(&bytes, RvExtensions::ALL)
+            .collect();
+        assert_eq!(res.len(), 5);
+        assert_eq!(res[0].0, 0);
+        assert!(res[0].1.unwrap().is_compressed());
+        assert_eq!(res[1].0, 2);
+        assert_eq!(res[1].1.unwrap().len, 4);
+        assert_eq!(res[1].1.unwrap().inst, Rv32::decode(0xffc1_0093).unwrap());
+        assert_eq!(res[2].0, 6);
+        assert!(res[2].1.is_err());
+        assert_eq!(res[3].0, 8);
+        assert_eq!(format!("{}", res[3].1.unwrap()), "jalr   x0, 0(x1)");
+        assert_eq!(res[4].0, 10);
+        assert_eq!(res[4].1.unwrap_err().reason, DecodeReason::Truncated);
+    }
+}
@@ -0,0 +1,554 @@
+//! Configurable disassembly syntax for [Instr].
+
+use crate::isa::rv32i::*;
+use crate::isa::rv32i::compressed::*;
+use std::collections::BTreeMap;
+
+/// Options controlling the syntax used to format an [Instr].
+///
+/// The default options match the [std::fmt::Display] implementation for
+/// [Instr]: numeric register names, decimal immediates, and no
+/// pseudo-instructions.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct DisasmOptions {
+    /// Use calling convention register names ('sp', 'a0') instead of
+    /// numeric names ('x2', 'x10').
+    pub abi_names: bool,
+    /// Print immediates and offsets in hexadecimal. Shift amounts and
+    /// CSR immediates are always printed in decimal.
+    pub hex_imm: bool,
+    /// Fold instructions into the equivalent pseudo-instruction where
+    /// possible (`addi x0, x0, 0` becomes `nop`).
+    pub pseudo: bool,
+}
+
+/// A table mapping addresses to symbol names.
+#[derive(Clone, Debug, Default)]
+pub struct SymbolTable {
+    map: BTreeMap<u32, String>,
+}
+impl SymbolTable {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a symbol, replacing any existing symbol at the same address.
+    pub fn insert(&mut self, addr: u32, name: impl Into<String>) {
+        self.map.insert(addr, name.into());
+    }
+
+    /// Returns the name of the symbol at exactly 'addr'.
+    pub fn get(&self, addr: u32) -> Option<&str> {
+        self.map.get(&addr).map(|s| s.as_str())
+    }
+
+    /// Returns the nearest symbol at or below 'addr', along with the
+    /// offset of 'addr' from the symbol.
+    pub fn lookup(&self, addr: u32) -> Option<(&str, u32)> {
+        self.map.range(..=addr).next_back()
+            .map(|(base, name)| (name.as_str(), addr - base))
+    }
+}
+
+/// Format an absolute address, along with the nearest symbol (if any).
+fn fmt_addr(addr: u32, symbols: Option<&SymbolTable>) -> String {
+    match symbols.and_then(|s| s.lookup(addr)) {
+        Some((name, 0)) => format!("0x{:08x} <{}>", addr, name),
+        Some((name, off)) => format!("0x{:08x} <{}+0x{:x}>", addr, name, off),
+        None => format!("0x{:08x}", addr),
+    }
+}
+
+/// An [Instr] paired with the [DisasmOptions] used to format it.
+///
+/// When the address of the instruction is known, branch and jump targets
+/// are printed as absolute addresses.
+pub struct InstrDisplay<'a> {
+    inst: Instr,
+    opts: DisasmOptions,
+    pc: Option<u32>,
+    symbols: Option<&'a SymbolTable>,
+}
+
+impl Instr {
+    /// Returns an object which formats this instruction with the given
+    /// options.
+    pub fn display(&self, opts: DisasmOptions) -> InstrDisplay<'static> {
+        InstrDisplay { inst: *self, opts, pc: None, symbols: None }
+    }
+
+    /// Returns an object which formats this instruction at address 'pc',
+    /// resolving branch and jump targets with an optional symbol table.
+    pub fn display_at<'a>(&self, pc: u32, opts: DisasmOptions,
+        symbols: Option<&'a SymbolTable>) -> InstrDisplay<'a>
+    {
+        InstrDisplay { inst: *self, opts, pc: Some(pc), symbols }
+    }
+}
+
+impl InstrDisplay<'_> {
+    /// Format a general-purpose register.
+    fn x(&self, r: Reg) -> String {
+        if self.opts.abi_names {
+            r.abi_name().to_string()
+        } else {
+            format!("{}", r)
+        }
+    }
+
+    /// Format a floating-point register.
+    fn f(&self, r: FReg) -> String {
+        if self.opts.abi_names {
+            r.abi_name().to_string()
+        } else {
+            format!("{}", r)
+        }
+    }
+
+    /// Format a signed immediate or offset.
+    fn imm(&self, imm: i32) -> String {
+        match (self.opts.hex_imm, imm < 0) {
+            (true, true)  => format!("-0x{:x}", imm.unsigned_abs()),
+            (true, false) => format!("0x{:x}", imm),
+            (false, _)    => format!("{}", imm),
+        }
+    }
+
+    /// Format a branch or jump target.
+    fn target(&self, simm: i32) -> String {
+        match self.pc {
+            Some(pc) => fmt_addr(pc.wrapping_add(simm as u32), self.symbols),
+            None => self.imm(simm),
+        }
+    }
+
+    /// Format the instruction as a pseudo-instruction, if it has one.
+    fn fmt_pseudo(&self, f: &mut std::fmt::Formatter<'_>)
+        -> Option<std::fmt::Result>
+    {
+        let x0 = Reg::new(0);
+        let ra = Reg::new(1);
+        let res = match self.inst {
+            Instr::OpImm { rd, rs1, simm: 0, alu_op: RvALUOpImm::Addi }
+                if rd == x0 && rs1 == x0 => write!(f, "nop"),
+            Instr::OpImm { rd, rs1, simm, alu_op: RvALUOpImm::Addi }
+                if rs1 == x0 => {
+                write!(f, "{:6} {}, {}", "li", self.x(rd), self.imm(simm))
+            },
+            Instr::OpImm { rd, rs1, simm: 0, alu_op: RvALUOpImm::Addi } => {
+                write!(f, "{:6} {}, {}", "mv", self.x(rd), self.x(rs1))
+            },
+            Instr::OpImm { rd, rs1, simm: -1, alu_op: RvALUOpImm::Xori } => {
+                write!(f, "{:6} {}, {}", "not", self.x(rd), self.x(rs1))
+            },
+            Instr::Op { rd, rs1, rs2, alu_op: RvALUOp::Sub } if rs1 == x0 => {
+                write!(f, "{:6} {}, {}", "neg", self.x(rd), self.x(rs2))
+            },
+            Instr::Jal { rd, simm } if rd == x0 => {
+                write!(f, "{:6} {}", "j", self.target(simm))
+            },
+            Instr::Jal { rd, simm } if rd == ra => {
+                write!(f, "{:6} {}", "jal", self.target(simm))
+            },
+            Instr::Jalr { rd, rs1, simm: 0 } if rd == x0 && rs1 == ra => {
+                write!(f, "ret")
+            },
+            Instr::Jalr { rd, rs1, simm: 0 } if rd == x0 => {
+                write!(f, "{:6} {}", "jr", self.x(rs1))
+            },
+            Instr::Jalr { rd, rs1, simm: 0 } if rd == ra => {
+                write!(f, "{:6} {}", "jalr", self.x(rs1))
+            },
+            Instr::Branch { rs1, rs2, simm, brn_op }
+                if rs2 == x0 && matches!(brn_op, RvBranchOp::Eq | RvBranchOp::Ne) => {
+                let inst = if brn_op == RvBranchOp::Eq { "beqz" } else { "bnez" };
+                write!(f, "{:6} {}, {}", inst, self.x(rs1), self.target(simm))
+            },
+            Instr::Csr { rd, rs1, csr, csr_op: RvCsrOp::Rs } if rs1 == x0 => {
+                write!(f, "{:6} {}, {}", "csrr", self.x(rd), csr)
+            },
+            Instr::Csr { rd, rs1, csr, csr_op: RvCsrOp::Rw } if rd == x0 => {
+                write!(f, "{:6} {}, {}", "csrw", csr, self.x(rs1))
+            },
+            Instr::Fence { pred, succ }
+                if pred.val() == 0b1111 && succ.val() == 0b1111 => {
+                write!(f, "fence")
+            },
+            _ => return None,
+        };
+        Some(res)
+    }
+}
+
+impl std::fmt::Display for InstrDisplay<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.opts.pseudo {
+            if let Some(res) = self.fmt_pseudo(f) {
+                return res;
+            }
+        }
+        let x = |r| self.x(r);
+        let fr = |r| self.f(r);
+        let imm = |i| self.imm(i);
+        let target = |i| self.target(i);
+        match self.inst {
+            Instr::Op { rd, rs1, rs2, alu_op } => {
+                let alu_op = format!("{}", alu_op);
+                write!(f, "{:6} {}, {}, {}", alu_op, x(rd), x(rs1), x(rs2))
+            },
+            Instr::Op32 { rd, rs1, rs2, alu_op } => {
+                let inst = format!("{}w", alu_op);
+                write!(f, "{:6} {}, {}, {}", inst, x(rd), x(rs1), x(rs2))
+            },
+            Instr::MulDiv { rd, rs1, rs2, mul_op } => {
+                let mul_op = format!("{}", mul_op);
+                write!(f, "{:6} {}, {}, {}", mul_op, x(rd), x(rs1), x(rs2))
+            },
+            Instr::Amo { rd, rs1, rs2, aq, rl, amo_op } => {
+                let ord = match (aq, rl) {
+                    (false, false) => "",
+                    (true,  false) => ".aq",
+                    (false, true)  => ".rl",
+                    (true,  true)  => ".aqrl",
+                };
+                let inst = format!("{}.w{}", amo_op, ord);
+                if let RvAmoOp::Lr = amo_op {
+                    write!(f, "{:6} {}, ({})", inst, x(rd), x(rs1))
+                } else {
+                    write!(f, "{:6} {}, {}, ({})", inst, x(rd), x(rs2), x(rs1))
+                }
+            },
+            Instr::OpImm { rd, rs1, simm, alu_op } => {
+                let simm = if alu_op.is_shift() { simm.to_string() } else { imm(simm) };
+                let alu_op = format!("{}", alu_op);
+                write!(f, "{:6} {}, {}, {}", alu_op, x(rd), x(rs1), simm)
+            },
+            Instr::OpImm32 { rd, rs1, simm, alu_op } => {
+                let simm = if alu_op.is_shift() { simm.to_string() } else { imm(simm) };
+                let inst = format!("{}w", alu_op);
+                write!(f, "{:6} {}, {}, {}", inst, x(rd), x(rs1), simm)
+            },
+            Instr::Load { rd, rs1, simm, width } => {
+                let inst = format!("l{}", width);
+                write!(f, "{:6} {}, {}({})", inst, x(rd), imm(simm), x(rs1))
+            },
+            Instr::Jalr { rd, rs1, simm } => {
+                write!(f, "{:6} {}, {}({})", "jalr", x(rd), imm(simm), x(rs1))
+            },
+            Instr::AuiPc { rd, uimm } => {
+                write!(f, "{:6} {}, 0x{:08x}", "auipc", x(rd), uimm)
+            },
+            Instr::Lui { rd, uimm } => {
+                write!(f, "{:6} {}, 0x{:08x}", "lui", x(rd), uimm)
+            },
+            Instr::Store { rs1, rs2, simm, width } => {
+                let inst = format!("s{}", width);
+                write!(f, "{:6} {}, {}({})", inst, x(rs2), imm(simm), x(rs1))
+            },
+            Instr::Jal { rd, simm } => {
+                write!(f, "{:6} {}, {}", "jal", x(rd), target(simm))
+            },
+            Instr::Branch { rs1, rs2, simm, brn_op } => {
+                let inst = format!("b{}", brn_op);
+                write!(f, "{:6} {}, {}, {}", inst, x(rs1), x(rs2), target(simm))
+            },
+            Instr::Fence { pred, succ } => {
+                write!(f, "{:6} {}, {}", "fence", pred, succ)
+            },
+            Instr::FenceTso => write!(f, "fence.tso"),
+            Instr::FenceI   => write!(f, "fence.i"),
+            Instr::Ecall    => write!(f, "ecall"),
+            Instr::Ebreak   => write!(f, "ebreak"),
+            Instr::Mret     => write!(f, "mret"),
+            Instr::Sret     => write!(f, "sret"),
+            Instr::Wfi      => write!(f, "wfi"),
+            Instr::Csr { rd, rs1, csr, csr_op } => {
+                let inst = format!("csr{}", csr_op);
+                write!(f, "{:6} {}, {}, {}", inst, x(rd), csr, x(rs1))
+            },
+            Instr::CsrImm { rd, uimm, csr, csr_op } => {
+                let inst = format!("csr{}i", csr_op);
+                write!(f, "{:6} {}, {}, {}", inst, x(rd), csr, uimm)
+            },
+            Instr::FpLoad { rd, rs1, simm, fmt } => {
+                let inst = format!("fl{}", fmt.mem_suffix());
+                write!(f, "{:6} {}, {}({})", inst, fr(rd), imm(simm), x(rs1))
+            },
+            Instr::FpStore { rs1, rs2, simm, fmt } => {
+                let inst = format!("fs{}", fmt.mem_suffix());
+                write!(f, "{:6} {}, {}({})", inst, fr(rs2), imm(simm), x(rs1))
+            },
+            Instr::FpFma { rd, rs1, rs2, rs3, fmt, rm, fma_op } => {
+                let inst = format!("{}.{}", fma_op, fmt);
+                write!(f, "{:6} {}, {}, {}, {}, {}", inst,
+                    fr(rd), fr(rs1), fr(rs2), fr(rs3), rm)
+            },
+            Instr::FpArith { rd, rs1, rs2, fmt, rm, fp_op } => {
+                let inst = format!("{}.{}", fp_op, fmt);
+                write!(f, "{:6} {}, {}, {}, {}", inst, fr(rd), fr(rs1), fr(rs2), rm)
+            },
+            Instr::FpSqrt { rd, rs1, fmt, rm } => {
+                let inst = format!("fsqrt.{}", fmt);
+                write!(f, "{:6} {}, {}, {}", inst, fr(rd), fr(rs1), rm)
+            },
+            Instr::FpOp { rd, rs1, rs2, fmt, fp_op } => {
+                let inst = format!("{}.{}", fp_op, fmt);
+                write!(f, "{:6} {}, {}, {}", inst, fr(rd), fr(rs1), fr(rs2))
+            },
+            Instr::FpCmp { rd, rs1, rs2, fmt, cmp_op } => {
+                let inst = format!("{}.{}", cmp_op, fmt);
+                write!(f, "{:6} {}, {}, {}", inst, x(rd), fr(rs1), fr(rs2))
+            },
+            Instr::FpClass { rd, rs1, fmt } => {
+                let inst = format!("fclass.{}", fmt);
+                write!(f, "{:6} {}, {}", inst, x(rd), fr(rs1))
+            },
+            Instr::FpCvtToInt { rd, rs1, fmt, rm, unsigned } => {
+                let w = if unsigned { "wu" } else { "w" };
+                let inst = format!("fcvt.{}.{}", w, fmt);
+                write!(f, "{:6} {}, {}, {}", inst, x(rd), fr(rs1), rm)
+            },
+            Instr::FpCvtFromInt { rd, rs1, fmt, rm, unsigned } => {
+                let w = if unsigned { "wu" } else { "w" };
+                let inst = format!("fcvt.{}.{}", fmt, w);
+                write!(f, "{:6} {}, {}, {}", inst, fr(rd), x(rs1), rm)
+            },
+            Instr::FpCvt { rd, rs1, fmt, src_fmt, rm } => {
+                let inst = format!("fcvt.{}.{}", fmt, src_fmt);
+                write!(f, "{:6} {}, {}, {}", inst, fr(rd), fr(rs1), rm)
+            },
+            Instr::FpMvToInt { rd, rs1 } => {
+                write!(f, "{:6} {}, {}", "fmv.x.w", x(rd), fr(rs1))
+            },
+            Instr::FpMvFromInt { rd, rs1 } => {
+                write!(f, "{:6} {}, {}", "fmv.w.x", fr(rd), x(rs1))
+            },
+        }
+    }
+}
+
+/// A single line of output from a [Disassembler].
+pub struct DisasmLine<'a> {
+    /// The address of the instruction.
+    pub addr: u32,
+    /// The result of decoding the instruction.
+    pub res: Result<DecodedInstr, DecodeError>,
+    /// An absolute address computed from a preceding 'auipc'.
+    pub target: Option<u32>,
+    opts: DisasmOptions,
+    symbols: Option<&'a SymbolTable>,
+}
+impl std::fmt::Display for DisasmLine<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "0x{:08x}: ", self.addr)?;
+        match self.res {
+            Ok(ref inst) => {
+                let inst = inst.inst.display_at(self.addr, self.opts, self.symbols);
+                write!(f, "{}", inst)?;
+            },
+            Err(ref e) => {
+                let dir = match Rv32::encoding_len(e.enc as u16) {
+                    2 => ".half",
+                    _ => ".word",
+                };
+                write!(f, "{:6} 0x{:x} # {}", dir, e.enc, e)?;
+            },
+        }
+        if let Some(target) = self.target {
+            write!(f, " # {}", fmt_addr(target, self.symbols))?;
+        }
+        Ok(())
+    }
+}
+
+/// Disassembles a stream of instructions loaded at some base address.
+pub struct Disassembler<'a> {
+    base: u32,
+    ext: RvExtensions,
+    opts: DisasmOptions,
+    symbols: Option<&'a SymbolTable>,
+}
+impl<'a> Disassembler<'a> {
+    pub fn new(base: u32) -> Self {
+        Self { base, ext: RvExtensions::ALL, opts: DisasmOptions::default(),
+            symbols: None }
+    }
+
+    /// Set the extensions accepted by the decoder.
+    pub fn with_extensions(mut self, ext: RvExtensions) -> Self {
+        self.ext = ext;
+        self
+    }
+
+    /// Set the syntax used for each instruction.
+    pub fn with_options(mut self, opts: DisasmOptions) -> Self {
+        self.opts = opts;
+        self
+    }
+
+    /// Set the table used to annotate addresses with symbol names.
+    pub fn with_symbols(mut self, symbols: &'a SymbolTable) -> Self {
+        self.symbols = Some(symbols);
+        self
+    }
+
+    /// Disassemble some little-endian bytes.
+    ///
+    /// An 'auipc' followed immediately by an 'addi', 'jalr', load, or store
+    /// using the same register is resolved into an absolute address.
+    pub fn disassemble(&self, bytes: &[u8]) -> Vec<DisasmLine<'a>> {
+        let mut res = Vec::new();
+        // The destination and value of the most recent 'auipc'
+        let mut auipc: Option<(Reg, u32)> = None;
+        for (off, inst) in Rv32::decode_stream(bytes, self.ext) {
+            let addr = self.base.wrapping_add(off as u32);
+            let base = |rs1: Reg| match auipc {
+                Some((rd, val)) if rd == rs1 && rd.val() != 0 => Some(val),
+                _ => None,
+            };
+            let target = match inst.as_ref().map(|i| i.inst) {
+                Ok(Instr::OpImm { rs1, simm, alu_op: RvALUOpImm::Addi, .. }) |
+                Ok(Instr::Jalr { rs1, simm, .. }) |
+                Ok(Instr::Load { rs1, simm, .. }) |
+                Ok(Instr::Store { rs1, simm, .. }) => {
+                    base(rs1).map(|val| val.wrapping_add(simm as u32))
+                },
+                _ => None,
+            };
+            auipc = match inst {
+                Ok(DecodedInstr { inst: Instr::AuiPc { rd, uimm }, .. }) => {
+                    Some((rd, addr.wrapping_add(uimm)))
+                },
+                _ => None,
+            };
+            res.push(DisasmLine {
+                addr, res: inst, target, opts: self.opts, symbols: self.symbols
+            });
+        }
+        res
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use crate::isa::*;
+    use crate::isa::rv32i::*;
+    use crate::isa::rv32i::disasm::*;
+    use crate::isa::rv32i::asm::assemble;
+
+    #[test]
+    fn disasm_options() {
+        let abi = DisasmOptions { abi_names: true, ..Default::default() };
+        let hex = DisasmOptions { hex_imm: true, ..Default::default() };
+        let pseudo = DisasmOptions { pseudo: true, ..Default::default() };
+        let all = DisasmOptions { abi_names: true, hex_imm: true, pseudo: true };
+        let cases = [
+            // addi x2, x2, -16
+            (0xff01_0113, abi,    "addi   sp, sp, -16"),
+            (0xff01_0113, hex,    "addi   x2, x2, -0x10"),
+            (0xff01_0113, pseudo, "addi   x2, x2, -16"),
+            // addi x0, x0, 0
+            (0x0000_0013, pseudo, "nop"),
+            // addi x10, x0, 100
+            (0x0640_0513, all,    "li     a0, 0x64"),
+            // addi x10, x11, 0
+            (0x0005_8513, all,    "mv     a0, a1"),
+            // xori x10, x11, -1
+            (0xfff5_c513, all,    "not    a0, a1"),
+            // sub x10, x0, x11
+            (0x40b0_0533, all,    "neg    a0, a1"),
+            // slli x10, x10, 16
+            (0x0105_1513, hex,    "slli   x10, x10, 16"),
+            // jal x0, -8
+            (0xff9f_f06f, pseudo, "j      -8"),
+            // jal x1, 16
+            (0x0100_00ef, all,    "jal    0x10"),
+            // jalr x0, 0(x1)
+            (0x0000_8067, all,    "ret"),
+            (0x0000_8067, abi,    "jalr   zero, 0(ra)"),
+            // jalr x0, 0(x5)
+            (0x0002_8067, all,    "jr     t0"),
+            // jalr x1, 0(x5)
+            (0x0002_80e7, all,    "jalr   t0"),
+            // beq x10, x0, 8
+            (0x0005_0463, all,    "beqz   a0, 0x8"),
+            // bne x10, x11, 8
+            (0x00b5_1463, all,    "bne    a0, a1, 0x8"),
+            // csrrs x10, mstatus, x0
+            (0x3000_2573, all,    "csrr   a0, mstatus"),
+            // csrrw x0, mtvec, x10
+            (0x3055_1073, all,    "csrw   mtvec, a0"),
+            // fence iorw, iorw
+            (0x0ff0_000f, pseudo, "fence"),
+            // flw f10, 8(x2)
+            (0x0081_2507, all,    "flw    fa0, 0x8(sp)"),
+        ];
+        for (enc, opts, text) in cases {
+            let inst = Rv32::decode(enc).unwrap();
+            assert_eq!(format!("{}", inst.display(opts)), text, "{:08x}", enc);
+            assert_eq!(format!("{}", inst.display(DisasmOptions::default())),
+                format!("{}", inst));
+        }
+    }
+
+    #[test]
+    fn disasm_pseudo_roundtrip() {
+        // The assembler should accept any output with pseudo-instructions
+        let all = DisasmOptions { abi_names: true, hex_imm: true, pseudo: true };
+        let encs = [
+            0x0000_0013, 0x0640_0513, 0x0005_8513, 0xfff5_c513, 0x40b0_0533,
+            0xff9f_f06f, 0x0100_00ef, 0x0000_8067, 0x0002_8067, 0x0002_80e7,
+            0x0005_0463, 0x3000_2573, 0x3055_1073, 0x0ff0_000f,
+        ];
+        for enc in encs {
+            let inst = Rv32::decode(enc).unwrap();
+            let text = format!("{}", inst.display(all));
+            let bytes = assemble(&text).unwrap();
+            assert_eq!(bytes, enc.to_le_bytes(), "{}", text);
+        }
+    }
+
+    #[test]
+    fn disasm_with_symbols() {
+        let bytes = assemble("
+        loop:
+            addi  x1, x1, 1
+            beq   x1, x2, loop
+            la    x10, data
+            auipc x5, 0
+            lw    x6, 16(x5)
+            jal   x0, loop
+        data:
+            .word 0
+        ").unwrap();
+        let text = &bytes[..0x1c];
+
+        let mut symbols = SymbolTable::new();
+        symbols.insert(0x8000_0000, "loop");
+        symbols.insert(0x8000_001c, "data");
+        let lines: Vec<String> = Disassembler::new(0x8000_0000)
+            .with_symbols(&symbols)
+            .disassemble(text)
+            .iter().map(|l| l.to_string()).collect();
+        assert_eq!(lines, [
+            "0x80000000: addi   x1, x1, 1",
+            "0x80000004: beq    x1, x2, 0x80000000 <loop>",
+            "0x80000008: auipc  x10, 0x00000000",
+            "0x8000000c: addi   x10, x10, 20 # 0x8000001c <data>",
+            "0x80000010: auipc  x5, 0x00000000",
+            "0x80000014: lw     x6, 16(x5) # 0x80000020 <data+0x4>",
+            "0x80000018: jal    x0, 0x80000000 <loop>",
+        ]);
+
+        // Without symbols, targets are still resolved
+        let opts = DisasmOptions { abi_names: true, pseudo: true, ..Default::default() };
+        let lines: Vec<String> = Disassembler::new(0x8000_0000)
+            .with_options(opts)
+            .disassemble(text)
+            .iter().map(|l| l.to_string()).collect();
+        assert_eq!(lines[1], "0x80000004: beq    ra, sp, 0x80000000");
+        assert_eq!(lines[3], "0x8000000c: addi   a0, a0, 20 # 0x8000001c");
+        assert_eq!(lines[6], "0x80000018: j      0x80000000");
+    }
+}
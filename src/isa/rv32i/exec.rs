@@ -0,0 +1,544 @@
+//! An instruction-level interpreter for RV32I.
+
+use crate::isa::rv32i::*;
+use crate::memory::{AccessFault, AtomicKind, Bus};
+
+/// Reasons why an instruction could not be executed.
+///
+/// When a trap occurs, the state of the hart is left unchanged and the
+/// program counter still points to the trapping instruction. Memory may
+/// still have been accessed: an AMO whose store faults has already read
+/// from the bus.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Rv32Trap {
+    /// The instruction could not be decoded.
+    IllegalInstruction(DecodeError),
+    /// The instruction is valid, but is not supported by the interpreter.
+    Unimplemented(Instr),
+    /// A jump or branch to a misaligned address.
+    InstructionMisaligned(u32),
+    /// A misaligned 'lr'.
+    LoadMisaligned(u32),
+    /// A misaligned 'sc' or AMO.
+    StoreMisaligned(u32),
+    /// An instruction fetch from an address which cannot be read.
+    InstructionAccessFault(u32),
+    /// A load from an address which cannot be read.
+    LoadAccessFault(u32),
+    /// A store or AMO to an address which cannot be written.
+    StoreAccessFault(u32),
+    /// An 'ecall' instruction.
+    EnvironmentCall,
+    /// An 'ebreak' instruction.
+    Breakpoint,
+}
+impl std::fmt::Display for Rv32Trap {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::IllegalInstruction(e) => write!(f, "illegal instruction: {}", e),
+            Self::Unimplemented(inst) => write!(f, "unimplemented: {}", inst),
+            Self::InstructionMisaligned(addr) => {
+                write!(f, "misaligned instruction address 0x{:08x}", addr)
+            },
+            Self::LoadMisaligned(addr) => {
+                write!(f, "misaligned load address 0x{:08x}", addr)
+            },
+            Self::StoreMisaligned(addr) => {
+                write!(f, "misaligned store address 0x{:08x}", addr)
+            },
+            Self::InstructionAccessFault(addr) => {
+                write!(f, "instruction access fault at 0x{:08x}", addr)
+            },
+            Self::LoadAccessFault(addr) => {
+                write!(f, "load access fault at 0x{:08x}", addr)
+            },
+            Self::StoreAccessFault(addr) => {
+                write!(f, "store access fault at 0x{:08x}", addr)
+            },
+            Self::EnvironmentCall => write!(f, "environment call"),
+            Self::Breakpoint => write!(f, "breakpoint"),
+        }
+    }
+}
+impl std::error::Error for Rv32Trap {}
+
+impl Rv32Trap {
+    fn fetch_fault(f: AccessFault) -> Self {
+        Self::InstructionAccessFault(f.addr as u32)
+    }
+    fn load_fault(f: AccessFault) -> Self {
+        Self::LoadAccessFault(f.addr as u32)
+    }
+    fn store_fault(f: AccessFault) -> Self {
+        Self::StoreAccessFault(f.addr as u32)
+    }
+}
+
+impl Rv32State {
+    /// Fetch, decode, and execute a single instruction.
+    ///
+    /// Instructions are always fetched in little-endian byte order, while
+    /// loads and stores use the byte order of the bus.
+    ///
+    /// Instructions are fetched in naturally-aligned 32-bit words, so each
+    /// instruction is a single fetch unless it is a 32-bit encoding which
+    /// starts halfway through a word.
+    pub fn step(&mut self, mem: &mut impl Bus) -> Result<(), Rv32Trap> {
+        let pc = self.pc;
+        let mut buf = [0u8; 4];
+        // A 16-bit encoding may be the last halfword in memory, so only
+        // fetch the first half if the whole word cannot be read
+        let whole = pc.is_multiple_of(4)
+            && mem.fetch(pc as usize, &mut buf).is_ok();
+        if !whole {
+            mem.fetch(pc as usize, &mut buf[..2])
+                .map_err(Rv32Trap::fetch_fault)?;
+        }
+        let len = Rv32::encoding_len(u16::from_le_bytes([buf[0], buf[1]]));
+        if len == 4 && !whole {
+            mem.fetch(pc.wrapping_add(2) as usize, &mut buf[2..])
+                .map_err(Rv32Trap::fetch_fault)?;
+        }
+        let inst = Rv32::decode_bytes(&buf[..len.min(4)], self.ext)
+            .map_err(Rv32Trap::IllegalInstruction)?;
+        let next_pc = pc.wrapping_add(inst.len as u32);
+        let next_pc = self.execute(inst.inst, next_pc, mem)?;
+        self.pc = next_pc;
+        Ok(())
+    }
+
+    /// Check the alignment of a jump or branch target.
+    fn check_target(&self, target: u32) -> Result<u32, Rv32Trap> {
+        let align = if self.ext.contains(RvExtensions::C) { 2 } else { 4 };
+        if !target.is_multiple_of(align) {
+            return Err(Rv32Trap::InstructionMisaligned(target));
+        }
+        Ok(target)
+    }
+
+    /// Execute an instruction, returning the address of the next
+    /// instruction.
+    fn execute(&mut self, inst: Instr, next_pc: u32, mem: &mut impl Bus)
+        -> Result<u32, Rv32Trap>
+    {
+        let pc = self.pc;
+        match inst {
+            Instr::Op { rd, rs1, rs2, alu_op } => {
+                let res = alu_op.eval(self.reg(rs1), self.reg(rs2));
+                self.set_reg(rd, res);
+            },
+            Instr::MulDiv { rd, rs1, rs2, mul_op } => {
+                let res = mul_op.eval(self.reg(rs1), self.reg(rs2));
+                self.set_reg(rd, res);
+            },
+            Instr::OpImm { rd, rs1, simm, alu_op } => {
+                let res = alu_op.eval(self.reg(rs1), simm);
+                self.set_reg(rd, res);
+            },
+            Instr::Amo { rd, rs1, rs2, amo_op, .. } => {
+                let addr = self.reg(rs1);
+                if !addr.is_multiple_of(4) {
+                    return Err(if amo_op == RvAmoOp::Lr {
+                        Rv32Trap::LoadMisaligned(addr)
+                    } else {
+                        Rv32Trap::StoreMisaligned(addr)
+                    });
+                }
+                let src = self.reg(rs2);
+                let (res, kind) = match amo_op {
+                    RvAmoOp::Lr => {
+                        let val = mem.read_u32(addr as usize)
+                            .map_err(Rv32Trap::load_fault)?;
+                        self.reservation.acquire(addr);
+                        (val, AtomicKind::LoadReserved)
+                    },
+                    RvAmoOp::Sc => {
+                        // Keep the reservation if the store faults
+                        let success = self.reservation.matches(addr);
+                        if success {
+                            mem.write_u32(addr as usize, src)
+                                .map_err(Rv32Trap::store_fault)?;
+                        }
+                        self.reservation.clear();
+                        let kind = AtomicKind::StoreConditional { success };
+                        (if success { 0 } else { 1 }, kind)
+                    },
+                    // An AMO which cannot be read is still reported as a
+                    // store fault
+                    _ => {
+                        let old = mem.read_u32(addr as usize)
+                            .map_err(Rv32Trap::store_fault)?;
+                        mem.write_u32(addr as usize, amo_op.eval(old, src))
+                            .map_err(Rv32Trap::store_fault)?;
+                        (old, AtomicKind::ReadModifyWrite)
+                    },
+                };
+                mem.observe_atomic(addr as usize, kind);
+                self.set_reg(rd, res);
+            },
+            Instr::Load { rd, rs1, simm, width } => {
+                let addr = self.reg(rs1).wrapping_add(simm as u32) as usize;
+                let res = match width {
+                    RvWidth::Byte => mem.read_u8(addr).map(|v| v as i8 as u32),
+                    RvWidth::Half => mem.read_u16(addr).map(|v| v as i16 as u32),
+                    RvWidth::Word => mem.read_u32(addr),
+                    RvWidth::ByteUnsigned => mem.read_u8(addr).map(u32::from),
+                    RvWidth::HalfUnsigned => mem.read_u16(addr).map(u32::from),
+                    // Only valid for RV64I
+                    RvWidth::Double | RvWidth::WordUnsigned => {
+                        return Err(Rv32Trap::Unimplemented(inst));
+                    },
+                };
+                let res = res.map_err(Rv32Trap::load_fault)?;
+                self.set_reg(rd, res);
+            },
+            Instr::Store { rs1, rs2, simm, width } => {
+                let addr = self.reg(rs1).wrapping_add(simm as u32) as usize;
+                let val = self.reg(rs2);
+                let res = match width {
+                    RvWidth::Byte => mem.write_u8(addr, val as u8),
+                    RvWidth::Half => mem.write_u16(addr, val as u16),
+                    RvWidth::Word => mem.write_u32(addr, val),
+                    // Only valid for RV64I
+                    _ => return Err(Rv32Trap::Unimplemented(inst)),
+                };
+                res.map_err(Rv32Trap::store_fault)?;
+            },
+            Instr::Jal { rd, simm } => {
+                let target = self.check_target(pc.wrapping_add(simm as u32))?;
+                self.set_reg(rd, next_pc);
+                return Ok(target);
+            },
+            Instr::Jalr { rd, rs1, simm } => {
+                let target = self.reg(rs1).wrapping_add(simm as u32) & !1;
+                let target = self.check_target(target)?;
+                self.set_reg(rd, next_pc);
+                return Ok(target);
+            },
+            Instr::Branch { rs1, rs2, simm, brn_op } => {
+                if brn_op.eval(self.reg(rs1), self.reg(rs2)) {
+                    return self.check_target(pc.wrapping_add(simm as u32));
+                }
+            },
+            Instr::AuiPc { rd, uimm } => self.set_reg(rd, pc.wrapping_add(uimm)),
+            Instr::Lui { rd, uimm } => self.set_reg(rd, uimm),
+
+            // There is no cache or pipeline to synchronize with
+            Instr::Fence { .. } | Instr::FenceTso | Instr::FenceI |
+            Instr::Wfi => {},
+
+            Instr::Ecall => return Err(Rv32Trap::EnvironmentCall),
+            Instr::Ebreak => return Err(Rv32Trap::Breakpoint),
+
+            Instr::Op32 { .. } | Instr::OpImm32 { .. } |
+            Instr::Mret | Instr::Sret |
+            Instr::Csr { .. } | Instr::CsrImm { .. } |
+            Instr::FpLoad { .. } | Instr::FpStore { .. } |
+            Instr::FpFma { .. } | Instr::FpArith { .. } |
+            Instr::FpSqrt { .. } | Instr::FpOp { .. } |
+            Instr::FpCmp { .. } | Instr::FpClass { .. } |
+            Instr::FpCvtToInt { .. } | Instr::FpCvtFromInt { .. } |
+            Instr::FpCvt { .. } | Instr::FpMvToInt { .. } |
+            Instr::FpMvFromInt { .. } => {
+                return Err(Rv32Trap::Unimplemented(inst));
+            },
+        }
+        Ok(next_pc)
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use crate::isa::rv32i::*;
+    use crate::isa::rv32i::asm::assemble;
+    use crate::isa::rv32i::exec::*;
+    use crate::memory::{AccessKind, Endian, NaiveRAM};
+
+    /// Assemble a program at address zero and run it until it traps.
+    fn run(src: &str) -> (Rv32State, NaiveRAM<0x1000>, Rv32Trap) {
+        let mut ram = NaiveRAM::<0x1000>::new();
+        ram.write_bytes(0, &assemble(src).unwrap());
+        let mut state = Rv32State::new(0);
+        for _ in 0..10_000 {
+            if let Err(trap) = state.step(&mut ram) {
+                return (state, ram, trap);
+            }
+        }
+        panic!("program did not terminate");
+    }
+
+    fn r(name: &str) -> Reg {
+        Reg::from_name(name).unwrap()
+    }
+
+    #[test]
+    fn exec_arith() {
+        let (s, _, trap) = run("
+            li   a0, -5
+            li   a1, 3
+            add  a2, a0, a1
+            sub  a3, a1, a0
+            slt  a4, a0, a1
+            sltu a5, a0, a1
+            srai a6, a0, 1
+            srli a7, a0, 28
+            li   t0, 0x7fffffff
+            addi t1, t0, 1
+            addi zero, zero, 5
+            mul  t2, a0, a1
+            div  t3, a0, a1
+            lui  t4, 0x12345000
+            auipc t5, 0
+            ecall
+        ");
+        assert_eq!(trap, Rv32Trap::EnvironmentCall);
+        assert_eq!(s.reg(r("a2")), -2i32 as u32);
+        assert_eq!(s.reg(r("a3")), 8);
+        assert_eq!(s.reg(r("a4")), 1);
+        assert_eq!(s.reg(r("a5")), 0);
+        assert_eq!(s.reg(r("a6")), -3i32 as u32);
+        assert_eq!(s.reg(r("a7")), 0xf);
+        // Wraparound on overflow
+        assert_eq!(s.reg(r("t1")), 0x8000_0000);
+        assert_eq!(s.reg(r("zero")), 0);
+        assert_eq!(s.reg(r("t2")), -15i32 as u32);
+        assert_eq!(s.reg(r("t3")), -1i32 as u32);
+        assert_eq!(s.reg(r("t4")), 0x1234_5000);
+        // 'li t0, 0x7fffffff' is two instructions
+        assert_eq!(s.reg(r("t5")), 0x3c);
+        // The trapping instruction is not retired
+        assert_eq!(s.pc, 0x40);
+    }
+
+    #[test]
+    fn exec_loads_stores() {
+        let (s, ram, _) = run("
+            li   a0, 0x800
+            li   a1, 0x8081f2f3
+            sw   a1, 0(a0)
+            lb   t0, 0(a0)
+            lbu  t1, 0(a0)
+            lh   t2, 2(a0)
+            lhu  t3, 2(a0)
+            lw   t4, 0(a0)
+            sb   a1, 4(a0)
+            sh   a1, 6(a0)
+            lw   t5, 4(a0)
+            ebreak
+        ");
+        assert_eq!(s.reg(r("t0")), 0xffff_fff3);
+        assert_eq!(s.reg(r("t1")), 0x0000_00f3);
+        assert_eq!(s.reg(r("t2")), 0xffff_8081);
+        assert_eq!(s.reg(r("t3")), 0x0000_8081);
+        assert_eq!(s.reg(r("t4")), 0x8081_f2f3);
+        assert_eq!(s.reg(r("t5")), 0xf2f3_00f3);
+        let mut buf = [0u8; 4];
+        ram.read_bytes(0x800, &mut buf);
+        assert_eq!(buf, [0xf3, 0xf2, 0x81, 0x80]);
+    }
+
+    #[test]
+    fn exec_control_flow() {
+        // Sum 1..=10 in a loop, with a call and return
+        let (s, _, trap) = run("
+                li   a0, 0
+                li   t0, 10
+            loop:
+                add  a0, a0, t0
+                addi t0, t0, -1
+                bnez t0, loop
+                jal  double
+                ecall
+            double:
+                add  a0, a0, a0
+                ret
+        ");
+        assert_eq!(trap, Rv32Trap::EnvironmentCall);
+        assert_eq!(s.reg(r("a0")), 110);
+        assert_eq!(s.reg(r("ra")), 0x18);
+    }
+
+    #[test]
+    fn exec_atomics() {
+        let (s, ram, _) = run("
+            li      a0, 0x800
+            li      t0, 5
+            sw      t0, 0(a0)
+            li      t1, 3
+            amoadd.w t2, t1, (a0)
+            lr.w    t3, (a0)
+            sc.w    t4, t1, (a0)
+            sc.w    t5, t1, (a0)
+            ecall
+        ");
+        assert_eq!(s.reg(r("t2")), 5);
+        assert_eq!(s.reg(r("t3")), 8);
+        assert_eq!(s.reg(r("t4")), 0);
+        assert_eq!(s.reg(r("t5")), 1);
+        let mut buf = [0u8; 4];
+        ram.read_bytes(0x800, &mut buf);
+        assert_eq!(u32::from_le_bytes(buf), 3);
+    }
+
+    /// A bus which faults on every write.
+    struct ReadOnly<'a>(&'a mut NaiveRAM<0x1000>);
+    impl Bus for ReadOnly<'_> {
+        fn read(&mut self, addr: usize, dst: &mut [u8])
+            -> Result<(), AccessFault>
+        {
+            self.0.read(addr, dst)
+        }
+        fn write(&mut self, addr: usize, src: &[u8])
+            -> Result<(), AccessFault>
+        {
+            Err(AccessFault::new(addr, src.len(), AccessKind::Write))
+        }
+    }
+
+    #[test]
+    fn exec_sc_fault() {
+        let mut ram = NaiveRAM::<0x1000>::new();
+        ram.write_bytes(0, &assemble("
+            lr.w t0, (a0)
+            sc.w t1, a0, (a0)
+        ").unwrap());
+        let mut s = Rv32State::new(0);
+        s.set_reg(r("a0"), 0x800);
+        s.set_reg(r("t1"), 5);
+        s.step(&mut ram).unwrap();
+
+        // A faulting 'sc' keeps the reservation, so it can be retried
+        assert_eq!(s.step(&mut ReadOnly(&mut ram)),
+            Err(Rv32Trap::StoreAccessFault(0x800)));
+        assert_eq!(s.pc, 4);
+        assert_eq!(s.reg(r("t1")), 5);
+        assert_eq!(s.reservation().addr(), Some(0x800));
+        s.step(&mut ram).unwrap();
+        assert_eq!(s.reg(r("t1")), 0);
+        assert_eq!(s.reservation().addr(), None);
+        let mut buf = [0u8; 4];
+        ram.read_bytes(0x800, &mut buf);
+        assert_eq!(u32::from_le_bytes(buf), 0x800);
+    }
+
+    /// A bus which counts instruction fetches.
+    struct CountFetches<'a, B: Bus>(&'a mut B, usize);
+    impl <B: Bus> Bus for CountFetches<'_, B> {
+        fn read(&mut self, addr: usize, dst: &mut [u8])
+            -> Result<(), AccessFault>
+        {
+            self.0.read(addr, dst)
+        }
+        fn write(&mut self, addr: usize, src: &[u8])
+            -> Result<(), AccessFault>
+        {
+            self.0.write(addr, src)
+        }
+        fn fetch(&mut self, addr: usize, dst: &mut [u8])
+            -> Result<(), AccessFault>
+        {
+            self.1 += 1;
+            self.0.fetch(addr, dst)
+        }
+    }
+
+    #[test]
+    fn exec_fetch() {
+        let mut ram = NaiveRAM::<0x10e>::new();
+        // c.li a0, 1; addi a0, a0, 1; c.addi a0, 1; addi a0, a0, 1
+        ram.write_bytes(0x100, &[0x05, 0x45, 0x13, 0x05, 0x15, 0x00,
+            0x05, 0x05, 0x13, 0x05, 0x15, 0x00]);
+        let mut bus = CountFetches(&mut ram, 0);
+        let mut s = Rv32State::new(0x100);
+        // Only a 32-bit encoding which starts halfway through a word
+        // takes more than one fetch
+        for fetches in [1, 2, 1, 1] {
+            bus.1 = 0;
+            s.step(&mut bus).unwrap();
+            assert_eq!(bus.1, fetches, "{:x}", s.pc);
+        }
+        assert_eq!(s.reg(r("a0")), 4);
+
+        // The last halfword in memory can be fetched
+        ram.write_bytes(0x10c, &[0x02, 0x90]);
+        let mut s = Rv32State::new(0x10c);
+        assert_eq!(s.step(&mut ram), Err(Rv32Trap::Breakpoint));
+        ram.write_bytes(0x10c, &[0x13, 0x05]);
+        assert_eq!(s.step(&mut ram),
+            Err(Rv32Trap::InstructionAccessFault(0x10e)));
+    }
+
+    #[test]
+    fn exec_compressed_and_traps() {
+        let mut ram = NaiveRAM::<0x1000>::new();
+        // c.li a0, 1; c.addi a0, 1; c.jr ra
+        ram.write_bytes(0, &[0x05, 0x45, 0x05, 0x05, 0x82, 0x80]);
+        let mut s = Rv32State::new(0);
+        s.set_reg(r("ra"), 0x102);
+        for _ in 0..3 {
+            s.step(&mut ram).unwrap();
+        }
+        assert_eq!(s.reg(r("a0")), 2);
+        assert_eq!(s.pc, 0x102);
+
+        // Jumping to a halfword boundary requires the C extension
+        let mut s = Rv32State::new(4);
+        s.ext = RvExtensions::NONE;
+        s.set_reg(r("ra"), 0x102);
+        ram.write_bytes(4, &0x0000_8067u32.to_le_bytes());
+        assert_eq!(s.step(&mut ram), Err(Rv32Trap::InstructionMisaligned(0x102)));
+        assert_eq!(s.pc, 4);
+
+        // Illegal and unimplemented instructions
+        ram.write_bytes(8, &[0; 4]);
+        let mut s = Rv32State::new(8);
+        assert!(matches!(s.step(&mut ram), Err(Rv32Trap::IllegalInstruction(_))));
+        ram.write_bytes(8, &0x3000_2573u32.to_le_bytes());
+        assert!(matches!(s.step(&mut ram), Err(Rv32Trap::Unimplemented(_))));
+    }
+
+    #[test]
+    fn exec_access_faults() {
+        let (s, _, trap) = run("
+            li   a0, 0x2000
+            lw   t0, 0(a0)
+        ");
+        assert_eq!(trap, Rv32Trap::LoadAccessFault(0x2000));
+        assert_eq!(s.pc, 4);
+        let (_, _, trap) = run("
+            li   a0, 0xffe
+            sw   a0, 0(a0)
+        ");
+        assert_eq!(trap, Rv32Trap::StoreAccessFault(0xffe));
+        let (_, _, trap) = run("
+            li   a0, 0x2000
+            amoswap.w t0, t0, (a0)
+        ");
+        assert_eq!(trap, Rv32Trap::StoreAccessFault(0x2000));
+        let (s, _, trap) = run("
+            li   t0, 0x2000
+            jr   t0
+        ");
+        assert_eq!(trap, Rv32Trap::InstructionAccessFault(0x2000));
+        assert_eq!(s.pc, 0x2000);
+    }
+
+    #[test]
+    fn exec_big_endian() {
+        let mut ram = NaiveRAM::<0x1000>::with_endian(Endian::Big);
+        // Instruction fetch is always little-endian
+        ram.write_bytes(0, &assemble("
+            li   a0, 0x800
+            li   a1, 0x11223344
+            sw   a1, 0(a0)
+            lbu  t0, 0(a0)
+            lh   t1, 2(a0)
+            ecall
+        ").unwrap());
+        let mut s = Rv32State::new(0);
+        while s.step(&mut ram).is_ok() {}
+        assert_eq!(s.reg(r("t0")), 0x11);
+        assert_eq!(s.reg(r("t1")), 0x3344);
+    }
+}
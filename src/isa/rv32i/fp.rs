@@ -0,0 +1,598 @@
+//! Types and decoding for the RV32F and RV32D floating-point extensions.
+
+use crate::isa::rv32i::*;
+
+/// A floating-point register.
+#[repr(transparent)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct FReg(u32);
+impl FReg {
+    /// Names for each register in the standard calling convention.
+    pub const ABI_NAMES: [&'static str; 32] = [
+        "ft0", "ft1", "ft2",  "ft3",  "ft4", "ft5", "ft6",  "ft7",
+        "fs0", "fs1", "fa0",  "fa1",  "fa2", "fa3", "fa4",  "fa5",
+        "fa6", "fa7", "fs2",  "fs3",  "fs4", "fs5", "fs6",  "fs7",
+        "fs8", "fs9", "fs10", "fs11", "ft8", "ft9", "ft10", "ft11",
+    ];
+
+    pub fn new(idx: u32) -> Self {
+        assert!(idx < 32);
+        Self(idx)
+    }
+    pub fn val(&self) -> u32 {
+        self.0
+    }
+
+    /// Returns the calling convention name for this register.
+    pub fn abi_name(&self) -> &'static str {
+        Self::ABI_NAMES[self.0 as usize]
+    }
+
+    /// Look up a register by numeric ('f2') or calling convention ('fa0')
+    /// name.
+    pub fn from_name(name: &str) -> Option<Self> {
+        if let Some(idx) = name.strip_prefix('f') {
+            if !idx.is_empty() && idx.bytes().all(|c| c.is_ascii_digit()) {
+                return match idx.parse::<u32>() {
+                    Ok(idx) if idx < 32 => Some(Self(idx)),
+                    _ => None,
+                };
+            }
+        }
+        Self::ABI_NAMES.iter().position(|n| *n == name)
+            .map(|idx| Self(idx as u32))
+    }
+}
+impl std::fmt::Display for FReg {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "f{}", self.0)
+    }
+}
+
+/// Floating-point formats.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RvFpFmt { S, D }
+impl TryFrom<u32> for RvFpFmt {
+    type Error = DecodeReason;
+    fn try_from(x: u32) -> Result<Self, Self::Error> {
+        match x {
+            0b00 => Ok(Self::S),
+            0b01 => Ok(Self::D),
+            // Half-precision and quad-precision
+            _ => Err(DecodeReason::Unsupported),
+        }
+    }
+}
+impl RvFpFmt {
+    /// Returns the 'fmt' field for this format.
+    pub fn bits(&self) -> u32 {
+        match self {
+            Self::S => 0b00,
+            Self::D => 0b01,
+        }
+    }
+
+    /// Returns the 'width' field used by loads and stores of this format.
+    pub fn width(&self) -> u32 {
+        match self {
+            Self::S => 0b010,
+            Self::D => 0b011,
+        }
+    }
+
+    /// Returns the extension which provides this format.
+    pub fn extension(&self) -> RvExtensions {
+        match self {
+            Self::S => RvExtensions::F,
+            Self::D => RvExtensions::D,
+        }
+    }
+
+    /// Returns the suffix used for load/store mnemonics (`flw`, `fld`).
+    pub fn mem_suffix(&self) -> &'static str {
+        match self {
+            Self::S => "w",
+            Self::D => "d",
+        }
+    }
+}
+impl std::fmt::Display for RvFpFmt {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let s = match self {
+            Self::S => "s",
+            Self::D => "d",
+        };
+        write!(f, "{}", s)
+    }
+}
+
+/// Floating-point rounding modes.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RvRoundingMode {
+    /// Round to nearest, ties to even
+    Rne,
+    /// Round towards zero
+    Rtz,
+    /// Round down (towards negative infinity)
+    Rdn,
+    /// Round up (towards positive infinity)
+    Rup,
+    /// Round to nearest, ties to max magnitude
+    Rmm,
+    /// Use the dynamic rounding mode in 'frm'
+    Dyn,
+}
+impl TryFrom<u32> for RvRoundingMode {
+    type Error = DecodeReason;
+    fn try_from(x: u32) -> Result<Self, Self::Error> {
+        match x {
+            0b000 => Ok(Self::Rne),
+            0b001 => Ok(Self::Rtz),
+            0b010 => Ok(Self::Rdn),
+            0b011 => Ok(Self::Rup),
+            0b100 => Ok(Self::Rmm),
+            0b111 => Ok(Self::Dyn),
+            _ => Err(DecodeReason::Reserved),
+        }
+    }
+}
+impl RvRoundingMode {
+    /// Returns the 'rm' field for this rounding mode.
+    pub fn bits(&self) -> u32 {
+        match self {
+            Self::Rne => 0b000,
+            Self::Rtz => 0b001,
+            Self::Rdn => 0b010,
+            Self::Rup => 0b011,
+            Self::Rmm => 0b100,
+            Self::Dyn => 0b111,
+        }
+    }
+}
+impl std::fmt::Display for RvRoundingMode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let s = match self {
+            Self::Rne => "rne",
+            Self::Rtz => "rtz",
+            Self::Rdn => "rdn",
+            Self::Rup => "rup",
+            Self::Rmm => "rmm",
+            Self::Dyn => "dyn",
+        };
+        write!(f, "{}", s)
+    }
+}
+
+/// Fused multiply-add opcodes.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RvFmaOp { Madd, Msub, Nmsub, Nmadd }
+impl RvFmaOp {
+    /// Returns the major opcode for this operation.
+    pub fn opcode(&self) -> Opcode {
+        match self {
+            Self::Madd  => Opcode::MADD,
+            Self::Msub  => Opcode::MSUB,
+            Self::Nmsub => Opcode::NMSUB,
+            Self::Nmadd => Opcode::NMADD,
+        }
+    }
+}
+impl std::fmt::Display for RvFmaOp {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let s = match self {
+            Self::Madd  => "fmadd",
+            Self::Msub  => "fmsub",
+            Self::Nmsub => "fnmsub",
+            Self::Nmadd => "fnmadd",
+        };
+        write!(f, "{}", s)
+    }
+}
+
+/// Floating-point arithmetic opcodes (which use a rounding mode).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RvFpArithOp { Add, Sub, Mul, Div }
+impl RvFpArithOp {
+    /// Returns the 'funct5' field for this operation.
+    pub fn funct5(&self) -> u32 {
+        match self {
+            Self::Add => 0b00000,
+            Self::Sub => 0b00001,
+            Self::Mul => 0b00010,
+            Self::Div => 0b00011,
+        }
+    }
+}
+impl std::fmt::Display for RvFpArithOp {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let s = match self {
+            Self::Add => "fadd",
+            Self::Sub => "fsub",
+            Self::Mul => "fmul",
+            Self::Div => "fdiv",
+        };
+        write!(f, "{}", s)
+    }
+}
+
+/// Floating-point sign-injection and min/max opcodes.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RvFpOp { Sgnj, Sgnjn, Sgnjx, Min, Max }
+impl RvFpOp {
+    /// Returns the 'funct5' and 'funct3' fields for this operation.
+    pub fn funct(&self) -> (u32, u32) {
+        match self {
+            Self::Sgnj  => (0b00100, 0b000),
+            Self::Sgnjn => (0b00100, 0b001),
+            Self::Sgnjx => (0b00100, 0b010),
+            Self::Min   => (0b00101, 0b000),
+            Self::Max   => (0b00101, 0b001),
+        }
+    }
+}
+impl std::fmt::Display for RvFpOp {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let s = match self {
+            Self::Sgnj  => "fsgnj",
+            Self::Sgnjn => "fsgnjn",
+            Self::Sgnjx => "fsgnjx",
+            Self::Min   => "fmin",
+            Self::Max   => "fmax",
+        };
+        write!(f, "{}", s)
+    }
+}
+
+/// Floating-point comparison opcodes.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RvFpCmpOp { Eq, Lt, Le }
+impl RvFpCmpOp {
+    /// Returns the 'funct3' field for this operation.
+    pub fn funct3(&self) -> u32 {
+        match self {
+            Self::Eq => 0b010,
+            Self::Lt => 0b001,
+            Self::Le => 0b000,
+        }
+    }
+}
+impl std::fmt::Display for RvFpCmpOp {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let s = match self {
+            Self::Eq => "feq",
+            Self::Lt => "flt",
+            Self::Le => "fle",
+        };
+        write!(f, "{}", s)
+    }
+}
+
+// Values of the 'funct5' field for OP-FP encodings
+const F5_SQRT:     u32 = 0b01011;
+const F5_SGNJ:     u32 = 0b00100;
+const F5_MINMAX:   u32 = 0b00101;
+const F5_CVT_FMT:  u32 = 0b01000;
+const F5_CMP:      u32 = 0b10100;
+const F5_CVT_TO_W: u32 = 0b11000;
+const F5_CVT_W_TO: u32 = 0b11010;
+const F5_MV_X_CLS: u32 = 0b11100;
+const F5_MV_TO_F:  u32 = 0b11110;
+
+/// Use a floating-point register index in one of the integer register
+/// fields of an encoding.
+fn fr(r: FReg) -> Reg {
+    Reg::new(r.val())
+}
+
+impl Rv32 {
+    /// Decode an instruction from the LOAD_FP, STORE_FP, MADD, MSUB,
+    /// NMSUB, NMADD, or OP_FP opcodes.
+    pub(super) fn decode_fp(enc: u32, opcode: Opcode, ext: RvExtensions)
+        -> Result<Instr, DecodeError>
+    {
+        let err = |field, reason| DecodeError::new(enc, field, reason);
+        let f3  = (enc & Self::MASK_F3_12)  >> 12;
+        let f7  = (enc & Self::MASK_F7_25)  >> 25;
+        let rd  = (enc & Self::MASK_RD_7)   >>  7;
+        let rs1 = (enc & Self::MASK_RS1_15) >> 15;
+        let rs2 = (enc & Self::MASK_RS2_20) >> 20;
+        let rs3 = f7 >> 2;
+
+        // Check that the extension for some format is enabled.
+        let check = |fmt: RvFpFmt, field| {
+            if ext.contains(fmt.extension()) {
+                Ok(fmt)
+            } else {
+                Err(err(field, DecodeReason::Disabled))
+            }
+        };
+
+        // Loads and stores encode the format in the 'width' field.
+        if let Opcode::LOAD_FP | Opcode::STORE_FP = opcode {
+            let fmt = match f3 {
+                0b010 => RvFpFmt::S,
+                0b011 => RvFpFmt::D,
+                // Half/quad-precision, and vector loads/stores
+                _ => return Err(err(DecodeField::Funct3, DecodeReason::Unsupported)),
+            };
+            let fmt = check(fmt, DecodeField::Funct3)?;
+            let rs1 = Reg::new(rs1);
+            return Ok(if let Opcode::LOAD_FP = opcode {
+                let simm = Self::build_i_imm(enc);
+                Instr::FpLoad { rd: FReg::new(rd), rs1, simm, fmt }
+            } else {
+                let simm = Self::build_s_imm(enc);
+                Instr::FpStore { rs1, rs2: FReg::new(rs2), simm, fmt }
+            });
+        }
+
+        let fmt = RvFpFmt::try_from(f7 & 0b11)
+            .map_err(|r| err(DecodeField::Funct7, r))?;
+        let fmt = check(fmt, DecodeField::Funct7)?;
+        let rm = || RvRoundingMode::try_from(f3)
+            .map_err(|r| err(DecodeField::Funct3, r));
+
+        let fma_op = match opcode {
+            Opcode::MADD  => Some(RvFmaOp::Madd),
+            Opcode::MSUB  => Some(RvFmaOp::Msub),
+            Opcode::NMSUB => Some(RvFmaOp::Nmsub),
+            Opcode::NMADD => Some(RvFmaOp::Nmadd),
+            _ => None,
+        };
+        if let Some(fma_op) = fma_op {
+            return Ok(Instr::FpFma {
+                rd: FReg::new(rd), rs1: FReg::new(rs1), rs2: FReg::new(rs2),
+                rs3: FReg::new(rs3), fmt, rm: rm()?, fma_op
+            });
+        }
+
+        let reserved = |field| Err(err(field, DecodeReason::Reserved));
+        let (frd, frs1, frs2) = (FReg::new(rd), FReg::new(rs1), FReg::new(rs2));
+        let (xrd, xrs1) = (Reg::new(rd), Reg::new(rs1));
+        let res = match f7 >> 2 {
+            0b00000 => Instr::FpArith {
+                rd: frd, rs1: frs1, rs2: frs2, fmt, rm: rm()?,
+                fp_op: RvFpArithOp::Add
+            },
+            0b00001 => Instr::FpArith {
+                rd: frd, rs1: frs1, rs2: frs2, fmt, rm: rm()?,
+                fp_op: RvFpArithOp::Sub
+            },
+            0b00010 => Instr::FpArith {
+                rd: frd, rs1: frs1, rs2: frs2, fmt, rm: rm()?,
+                fp_op: RvFpArithOp::Mul
+            },
+            0b00011 => Instr::FpArith {
+                rd: frd, rs1: frs1, rs2: frs2, fmt, rm: rm()?,
+                fp_op: RvFpArithOp::Div
+            },
+            F5_SQRT => {
+                if rs2 != 0 {
+                    return reserved(DecodeField::Operand);
+                }
+                Instr::FpSqrt { rd: frd, rs1: frs1, fmt, rm: rm()? }
+            },
+            F5_SGNJ | F5_MINMAX => {
+                let fp_op = match (f7 >> 2, f3) {
+                    (F5_SGNJ, 0b000)   => RvFpOp::Sgnj,
+                    (F5_SGNJ, 0b001)   => RvFpOp::Sgnjn,
+                    (F5_SGNJ, 0b010)   => RvFpOp::Sgnjx,
+                    (F5_MINMAX, 0b000) => RvFpOp::Min,
+                    (F5_MINMAX, 0b001) => RvFpOp::Max,
+                    _ => return reserved(DecodeField::Funct3),
+                };
+                Instr::FpOp { rd: frd, rs1: frs1, rs2: frs2, fmt, fp_op }
+            },
+            F5_CVT_FMT => {
+                // The 'rs2' field encodes the source format
+                let src_fmt = match RvFpFmt::try_from(rs2) {
+                    Ok(src_fmt) if src_fmt != fmt => src_fmt,
+                    Ok(_) => return reserved(DecodeField::Operand),
+                    Err(r) => return Err(err(DecodeField::Operand, r)),
+                };
+                let src_fmt = check(src_fmt, DecodeField::Operand)?;
+                Instr::FpCvt { rd: frd, rs1: frs1, fmt, src_fmt, rm: rm()? }
+            },
+            F5_CMP => {
+                let cmp_op = match f3 {
+                    0b010 => RvFpCmpOp::Eq,
+                    0b001 => RvFpCmpOp::Lt,
+                    0b000 => RvFpCmpOp::Le,
+                    _ => return reserved(DecodeField::Funct3),
+                };
+                Instr::FpCmp { rd: xrd, rs1: frs1, rs2: frs2, fmt, cmp_op }
+            },
+            F5_CVT_TO_W | F5_CVT_W_TO => {
+                let unsigned = match rs2 {
+                    0b00000 => false,
+                    0b00001 => true,
+                    // Conversions to/from 64-bit integers
+                    0b00010 | 0b00011 => return Err(
+                        err(DecodeField::Operand, DecodeReason::Unsupported)
+                    ),
+                    _ => return reserved(DecodeField::Operand),
+                };
+                if f7 >> 2 == F5_CVT_TO_W {
+                    Instr::FpCvtToInt { rd: xrd, rs1: frs1, fmt, rm: rm()?, unsigned }
+                } else {
+                    Instr::FpCvtFromInt { rd: frd, rs1: xrs1, fmt, rm: rm()?, unsigned }
+                }
+            },
+            F5_MV_X_CLS | F5_MV_TO_F => {
+                if rs2 != 0 {
+                    return reserved(DecodeField::Operand);
+                }
+                match (f7 >> 2, f3, fmt) {
+                    (F5_MV_X_CLS, 0b001, _) => {
+                        Instr::FpClass { rd: xrd, rs1: frs1, fmt }
+                    },
+                    (F5_MV_X_CLS, 0b000, RvFpFmt::S) => {
+                        Instr::FpMvToInt { rd: xrd, rs1: frs1 }
+                    },
+                    (F5_MV_TO_F, 0b000, RvFpFmt::S) => {
+                        Instr::FpMvFromInt { rd: frd, rs1: xrs1 }
+                    },
+                    // 'fmv.x.d' and 'fmv.d.x' only exist on RV64
+                    (_, 0b000, RvFpFmt::D) => return Err(
+                        err(DecodeField::Funct7, DecodeReason::Unsupported)
+                    ),
+                    _ => return reserved(DecodeField::Funct3),
+                }
+            },
+            _ => return reserved(DecodeField::Funct7),
+        };
+        Ok(res)
+    }
+
+    /// Encode a floating-point instruction.
+    pub(super) fn encode_fp(inst: Instr) -> Result<u32, EncodeError> {
+        use InstFormat::*;
+        let res = match inst {
+            Instr::FpLoad { rd, rs1, simm, fmt } => {
+                Self::check_simm(I, simm, 12, 1)?;
+                Self::enc_i(Opcode::LOAD_FP, fr(rd), fmt.width(), rs1, simm as u32)
+            },
+            Instr::FpStore { rs1, rs2, simm, fmt } => {
+                Self::check_simm(S, simm, 12, 1)?;
+                Self::enc_s(Opcode::STORE_FP, fmt.width(), rs1, fr(rs2), simm)
+            },
+            Instr::FpFma { rd, rs1, rs2, rs3, fmt, rm, fma_op } => {
+                let f7 = (rs3.val() << 2) | fmt.bits();
+                Self::enc_r(fma_op.opcode(), fr(rd), rm.bits(), fr(rs1), fr(rs2), f7)
+            },
+            Instr::FpArith { rd, rs1, rs2, fmt, rm, fp_op } => {
+                let f7 = (fp_op.funct5() << 2) | fmt.bits();
+                Self::enc_r(Opcode::OP_FP, fr(rd), rm.bits(), fr(rs1), fr(rs2), f7)
+            },
+            Instr::FpSqrt { rd, rs1, fmt, rm } => {
+                let f7 = (F5_SQRT << 2) | fmt.bits();
+                let x0 = Reg::new(0);
+                Self::enc_r(Opcode::OP_FP, fr(rd), rm.bits(), fr(rs1), x0, f7)
+            },
+            Instr::FpOp { rd, rs1, rs2, fmt, fp_op } => {
+                let (f5, f3) = fp_op.funct();
+                let f7 = (f5 << 2) | fmt.bits();
+                Self::enc_r(Opcode::OP_FP, fr(rd), f3, fr(rs1), fr(rs2), f7)
+            },
+            Instr::FpCvt { rd, rs1, fmt, src_fmt, rm } => {
+                let f7 = (F5_CVT_FMT << 2) | fmt.bits();
+                let rs2 = Reg::new(src_fmt.bits());
+                Self::enc_r(Opcode::OP_FP, fr(rd), rm.bits(), fr(rs1), rs2, f7)
+            },
+            Instr::FpCmp { rd, rs1, rs2, fmt, cmp_op } => {
+                let f7 = (F5_CMP << 2) | fmt.bits();
+                Self::enc_r(Opcode::OP_FP, rd, cmp_op.funct3(), fr(rs1), fr(rs2), f7)
+            },
+            Instr::FpCvtToInt { rd, rs1, fmt, rm, unsigned } => {
+                let f7 = (F5_CVT_TO_W << 2) | fmt.bits();
+                let rs2 = Reg::new(unsigned as u32);
+                Self::enc_r(Opcode::OP_FP, rd, rm.bits(), fr(rs1), rs2, f7)
+            },
+            Instr::FpCvtFromInt { rd, rs1, fmt, rm, unsigned } => {
+                let f7 = (F5_CVT_W_TO << 2) | fmt.bits();
+                let rs2 = Reg::new(unsigned as u32);
+                Self::enc_r(Opcode::OP_FP, fr(rd), rm.bits(), rs1, rs2, f7)
+            },
+            Instr::FpClass { rd, rs1, fmt } => {
+                let f7 = (F5_MV_X_CLS << 2) | fmt.bits();
+                let x0 = Reg::new(0);
+                Self::enc_r(Opcode::OP_FP, rd, 0b001, fr(rs1), x0, f7)
+            },
+            Instr::FpMvToInt { rd, rs1 } => {
+                let f7 = F5_MV_X_CLS << 2;
+                let x0 = Reg::new(0);
+                Self::enc_r(Opcode::OP_FP, rd, 0b000, fr(rs1), x0, f7)
+            },
+            Instr::FpMvFromInt { rd, rs1 } => {
+                let f7 = F5_MV_TO_F << 2;
+                let x0 = Reg::new(0);
+                Self::enc_r(Opcode::OP_FP, fr(rd), 0b000, rs1, x0, f7)
+            },
+            _ => unreachable!("not a floating-point instruction"),
+        };
+        Ok(res)
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use crate::isa::*;
+    use crate::isa::rv32i::*;
+
+    #[test]
+    fn decode_fp() {
+        let cases = [
+            (0x0081_2087, "flw    f1, 8(x2)"),
+            (0xfe11_3c27, "fsd    f1, -8(x2)"),
+            (0x0031_00d3, "fadd.s f1, f2, f3, rne"),
+            (0x0a31_70d3, "fsub.d f1, f2, f3, dyn"),
+            (0x2031_10c3, "fmadd.s f1, f2, f3, f4, rtz"),
+            (0x2231_00cf, "fnmadd.d f1, f2, f3, f4, rne"),
+            (0x5801_70d3, "fsqrt.s f1, f2, dyn"),
+            (0x2231_10d3, "fsgnjn.d f1, f2, f3"),
+            (0x2831_10d3, "fmax.s f1, f2, f3"),
+            (0x4011_70d3, "fcvt.s.d f1, f2, dyn"),
+            (0x4201_00d3, "fcvt.d.s f1, f2, rne"),
+            (0xa231_20d3, "feq.d  x1, f2, f3"),
+            (0xa031_00d3, "fle.s  x1, f2, f3"),
+            (0xc011_10d3, "fcvt.wu.s x1, f2, rtz"),
+            (0xd201_70d3, "fcvt.d.w f1, x2, dyn"),
+            (0xe201_10d3, "fclass.d x1, f2"),
+            (0xe001_00d3, "fmv.x.w x1, f2"),
+            (0xf001_00d3, "fmv.w.x f1, x2"),
+        ];
+        for (enc, text) in cases {
+            let inst = Rv32::decode(enc).unwrap();
+            assert_eq!(format!("{}", inst), text, "{:08x}", enc);
+        }
+
+        let invalid = [
+            // Reserved rounding mode
+            (0x0031_50d3, DecodeField::Funct3, DecodeReason::Reserved),
+            // fadd.h
+            (0x0431_00d3, DecodeField::Funct7, DecodeReason::Unsupported),
+            // fcvt.l.s
+            (0xc021_00d3, DecodeField::Operand, DecodeReason::Unsupported),
+            // fmv.x.d
+            (0xe201_00d3, DecodeField::Funct7, DecodeReason::Unsupported),
+            // fcvt.s.s
+            (0x4001_00d3, DecodeField::Operand, DecodeReason::Reserved),
+        ];
+        for (enc, field, reason) in invalid {
+            assert_eq!(Rv32::decode(enc),
+                Err(DecodeError::new(enc, field, reason)), "{:08x}", enc);
+        }
+
+        // Double-precision requires the D extension
+        let ext = RvExtensions::F;
+        assert!(Rv32::decode_with(0x0031_00d3, ext).is_ok());
+        assert_eq!(Rv32::decode_with(0x0a31_70d3, ext).unwrap_err().reason,
+            DecodeReason::Disabled);
+    }
+
+    #[test]
+    fn decode_fp_compressed() {
+        let cases = [
+            (0x2008, "fld    f10, 0(x8)"),
+            (0x6008, "flw    f10, 0(x8)"),
+            (0xa008, "fsd    f10, 0(x8)"),
+            (0xe008, "fsw    f10, 0(x8)"),
+            (0x2522, "fld    f10, 8(x2)"),
+            (0x6522, "flw    f10, 8(x2)"),
+            (0xa42a, "fsd    f10, 8(x2)"),
+            (0xe42a, "fsw    f10, 8(x2)"),
+        ];
+        for (enc, text) in cases {
+            let inst = Rv32::decode_compressed(enc, RvExtensions::ALL).unwrap();
+            assert_eq!(format!("{}", inst), text, "{:04x}", enc);
+        }
+        assert_eq!(Rv32::decode_compressed(0x2008, RvExtensions::C)
+            .unwrap_err().reason, DecodeReason::Disabled);
+        assert_eq!(Rv32::decode_compressed(0x2008, 
+            RvExtensions::C | RvExtensions::F).unwrap_err().reason, 
+            DecodeReason::Disabled);
+        assert!(Rv32::decode_compressed(0x6008, 
+            RvExtensions::C | RvExtensions::F).is_ok());
+    }
+}
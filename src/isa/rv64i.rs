@@ -0,0 +1,357 @@
+//! Support for the RV64I base integer instruction set.
+//!
+//! RV64I shares almost all of its encodings with RV32I, so this reuses
+//! [Instr] and the decoder for [Rv32], handling only the encodings which
+//! differ between the two.
+//!
+//! Only the base integer ISA has 64-bit encodings here: the RV64M and RV64A
+//! instructions (eg. 'mulw' and 'amoadd.d') are out of scope, and are
+//! reported as [DecodeReason::Unsupported].
+
+use crate::isa::*;
+use crate::isa::rv32i::*;
+
+pub mod exec;
+
+/// Representing the RV64I instruction set.
+pub struct Rv64;
+impl Rv64 {
+    /// Decode an instruction, accepting only the base integer ISA and the
+    /// given set of extensions.
+    ///
+    /// Compressed encodings are not supported (RV64C reassigns several of
+    /// the RV32C encodings).
+    pub fn decode_with(enc: u32, ext: RvExtensions)
+        -> Result<Instr, DecodeError>
+    {
+        let err = |field, reason| DecodeError::new(enc, field, reason);
+        let (opcode, rd, f3, rs1, rs2, f7) = Rv32::decode_fields(enc)?;
+
+        let res = match opcode {
+            // Shift amounts are six bits wide, using the low bit of 'funct7'
+            Opcode::OP_IMM if f3 == 0b001 || f3 == 0b101 => {
+                let alu_op = RvALUOpImm::try_from((f3, f7 & !1))
+                    .map_err(|r| err(DecodeField::Funct7, r))?;
+                let simm   = ((enc >> 20) & 0b111111) as i32;
+                Instr::OpImm { rd, rs1, simm, alu_op }
+            },
+            Opcode::OP_IMM_32 => {
+                let alu_op = match RvALUOpImm::try_from((f3, f7)) {
+                    Ok(alu_op @ (RvALUOpImm::Addi | RvALUOpImm::Slli |
+                        RvALUOpImm::Srli | RvALUOpImm::Srai)) => alu_op,
+                    Ok(_) => return Err(
+                        err(DecodeField::Funct3, DecodeReason::Reserved)
+                    ),
+                    Err(r) => return Err(err(DecodeField::Funct7, r)),
+                };
+                let simm   = if alu_op.is_shift() {
+                    rs2.val() as i32
+                } else {
+                    Rv32::build_i_imm(enc)
+                };
+                Instr::OpImm32 { rd, rs1, simm, alu_op }
+            },
+            Opcode::OP_32 => {
+                // 'mulw', 'divw', and friends (RV64M)
+                if f7 == RvMulOp::FUNCT7 {
+                    return Err(
+                        err(DecodeField::Funct7, DecodeReason::Unsupported)
+                    );
+                }
+                let alu_op = match RvALUOp::try_from((f3, f7)) {
+                    Ok(alu_op @ (RvALUOp::Add | RvALUOp::Sub | RvALUOp::Sll |
+                        RvALUOp::Srl | RvALUOp::Sra)) => alu_op,
+                    Ok(_) => return Err(
+                        err(DecodeField::Funct3, DecodeReason::Reserved)
+                    ),
+                    Err(r) => return Err(err(DecodeField::Funct7, r)),
+                };
+                Instr::Op32 { rd, rs1, rs2, alu_op }
+            },
+            Opcode::LOAD => {
+                let simm   = Rv32::build_i_imm(enc);
+                let width  = RvWidth::try_from(f3)
+                    .map_err(|r| err(DecodeField::Funct3, r))?;
+                Instr::Load { rd, rs1, simm, width }
+            },
+            Opcode::STORE if f3 == RvWidth::Double.funct3() => {
+                let simm   = Rv32::build_s_imm(enc);
+                Instr::Store { rs1, rs2, simm, width: RvWidth::Double }
+            },
+            _ => Rv32::decode_with(enc, ext)?,
+        };
+        Ok(res)
+    }
+}
+
+impl InstructionSet for Rv64 {
+    type Encoding = u32;
+    type Inst = Instr;
+    type DecodeError = DecodeError;
+
+    fn decode(enc: Self::Encoding) -> Result<Self::Inst, Self::DecodeError> {
+        Self::decode_with(enc, RvExtensions::ALL)
+    }
+}
+
+impl Assembler for Rv64 {
+    type EncodeError = EncodeError;
+
+    /// Encode an RV64I instruction.
+    fn encode(inst: Self::Inst) -> Result<Self::Encoding, Self::EncodeError> {
+        use InstFormat::*;
+        let res = match inst {
+            Instr::Op32 { rd, rs1, rs2, alu_op } => {
+                let (f3, f7) = alu_op.funct();
+                Rv32::enc_r(Opcode::OP_32, rd, f3, rs1, rs2, f7)
+            },
+            Instr::OpImm { rd, rs1, simm, alu_op } if alu_op.is_shift() => {
+                Rv32::check_uimm(I, simm as u32, 6)?;
+                let (f3, f7) = alu_op.funct();
+                let imm = (f7 << 5) | simm as u32;
+                Rv32::enc_i(Opcode::OP_IMM, rd, f3, rs1, imm)
+            },
+            Instr::OpImm32 { rd, rs1, simm, alu_op } => {
+                let (f3, f7) = alu_op.funct();
+                if alu_op.is_shift() {
+                    Rv32::check_uimm(I, simm as u32, 5)?;
+                    let imm = (f7 << 5) | simm as u32;
+                    Rv32::enc_i(Opcode::OP_IMM_32, rd, f3, rs1, imm)
+                } else {
+                    Rv32::check_simm(I, simm, 12, 1)?;
+                    Rv32::enc_i(Opcode::OP_IMM_32, rd, f3, rs1, simm as u32)
+                }
+            },
+            Instr::Load { rd, rs1, simm, width } => {
+                Rv32::check_simm(I, simm, 12, 1)?;
+                Rv32::enc_i(Opcode::LOAD, rd, width.funct3(), rs1, simm as u32)
+            },
+            Instr::Store { rs1, rs2, simm, width: RvWidth::Double } => {
+                Rv32::check_simm(S, simm, 12, 1)?;
+                let f3 = RvWidth::Double.funct3();
+                Rv32::enc_s(Opcode::STORE, f3, rs1, rs2, simm)
+            },
+            _ => Rv32::encode(inst)?,
+        };
+        Ok(res)
+    }
+}
+impl RvALUOpImm {
+    /// Compute the result of this operation on a 64-bit register, where
+    /// shift amounts are six bits wide.
+    pub fn eval64(&self, a: u64, imm: i32) -> u64 {
+        let b = imm as i64 as u64;
+        match self {
+            Self::Addi  => a.wrapping_add(b),
+            Self::Slti  => ((a as i64) < imm as i64) as u64,
+            Self::Sltiu => (a < b) as u64,
+            Self::Xori  => a ^ b,
+            Self::Ori   => a | b,
+            Self::Andi  => a & b,
+            Self::Slli  => a << (b & 0x3f),
+            Self::Srli  => a >> (b & 0x3f),
+            Self::Srai  => ((a as i64) >> (b & 0x3f)) as u64,
+        }
+    }
+
+    /// Compute the result of the word form of this operation (ie. 'addiw'),
+    /// which operates on the low 32 bits and sign-extends the result.
+    pub fn eval_word(&self, a: u64, imm: i32) -> u64 {
+        self.eval(a as u32, imm) as i32 as i64 as u64
+    }
+}
+
+impl RvALUOp {
+    /// Compute the result of this operation on 64-bit registers, where
+    /// shift amounts are six bits wide.
+    pub fn eval64(&self, a: u64, b: u64) -> u64 {
+        match self {
+            Self::Add  => a.wrapping_add(b),
+            Self::Sub  => a.wrapping_sub(b),
+            Self::Sll  => a << (b & 0x3f),
+            Self::Slt  => ((a as i64) < (b as i64)) as u64,
+            Self::Sltu => (a < b) as u64,
+            Self::Xor  => a ^ b,
+            Self::Srl  => a >> (b & 0x3f),
+            Self::Sra  => ((a as i64) >> (b & 0x3f)) as u64,
+            Self::Or   => a | b,
+            Self::And  => a & b,
+        }
+    }
+
+    /// Compute the result of the word form of this operation (ie. 'addw'),
+    /// which operates on the low 32 bits and sign-extends the result.
+    pub fn eval_word(&self, a: u64, b: u64) -> u64 {
+        self.eval(a as u32, b as u32) as i32 as i64 as u64
+    }
+}
+
+impl RvBranchOp {
+    /// Returns true if the branch is taken, comparing 64-bit registers.
+    pub fn eval64(&self, a: u64, b: u64) -> bool {
+        match self {
+            Self::Eq  => a == b,
+            Self::Ne  => a != b,
+            Self::Lt  => (a as i64) < (b as i64),
+            Self::Ge  => (a as i64) >= (b as i64),
+            Self::Ltu => a < b,
+            Self::Geu => a >= b,
+        }
+    }
+}
+
+/// Architectural state for a single RV64I hart.
+///
+/// See [Rv64State::step] for executing instructions.
+pub struct Rv64State {
+    /// The program counter
+    pub pc: u64,
+    /// Extensions accepted when decoding instructions
+    pub ext: RvExtensions,
+    gpr: [u64; 32],
+}
+impl Rv64State {
+    /// Create a new hart with all registers cleared, starting at 'pc'.
+    pub fn new(pc: u64) -> Self {
+        Self {
+            pc,
+            ext: RvExtensions::ALL,
+            gpr: [0; 32],
+        }
+    }
+
+    /// Read a general-purpose register ('x0' always reads as zero).
+    pub fn reg(&self, r: Reg) -> u64 {
+        if r.val() == 0 { 0 } else { self.gpr[r.val() as usize] }
+    }
+
+    /// Write a general-purpose register (writes to 'x0' are ignored).
+    pub fn set_reg(&mut self, r: Reg, val: u64) {
+        if r.val() != 0 {
+            self.gpr[r.val() as usize] = val;
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use crate::isa::*;
+    use crate::isa::rv32i::*;
+    use crate::isa::rv64i::*;
+
+    #[test]
+    fn decode_rv64() {
+        let cases = [
+            (0xfff1_009b, "addiw  x1, x2, -1"),
+            (0x01f1_109b, "slliw  x1, x2, 31"),
+            (0x4031_509b, "sraiw  x1, x2, 3"),
+            (0x0031_00bb, "addw   x1, x2, x3"),
+            (0x4031_00bb, "subw   x1, x2, x3"),
+            (0x4031_50bb, "sraw   x1, x2, x3"),
+            (0x0081_3083, "ld     x1, 8(x2)"),
+            (0x0081_6083, "lwu    x1, 8(x2)"),
+            (0xfe11_3c23, "sd     x1, -8(x2)"),
+            (0x03f1_1093, "slli   x1, x2, 63"),
+            (0x4201_5093, "srai   x1, x2, 32"),
+            // Encodings shared with RV32I
+            (0x0081_2083, "lw     x1, 8(x2)"),
+            (0x4031_00b3, "sub    x1, x2, x3"),
+        ];
+        for (enc, text) in cases {
+            let inst = Rv64::decode(enc).unwrap();
+            assert_eq!(format!("{}", inst), text, "{:08x}", enc);
+            assert_eq!(Rv64::encode(inst), Ok(enc), "{}", inst);
+        }
+
+        let invalid = [
+            // slliw with shamt[5] set
+            (0x0201_109b, DecodeField::Funct7, DecodeReason::Reserved),
+            // mulw
+            (0x0231_00bb, DecodeField::Funct7, DecodeReason::Unsupported),
+            // sltw
+            (0x0031_20bb, DecodeField::Funct3, DecodeReason::Reserved),
+            // c.nop
+            (0x0000_0001, DecodeField::Opcode, DecodeReason::Unsupported),
+        ];
+        for (enc, field, reason) in invalid {
+            assert_eq!(Rv64::decode(enc),
+                Err(DecodeError::new(enc, field, reason)), "{:08x}", enc);
+        }
+
+        // None of the RV64I-only encodings are valid RV32I instructions
+        for enc in [0xfff1_009b, 0x0031_00bb, 0x0081_3083, 0x0081_6083,
+            0xfe11_3c23, 0x03f1_1093, 0x4201_5093]
+        {
+            assert!(Rv32::decode(enc).is_err(), "{:08x}", enc);
+        }
+    }
+
+    #[test]
+    fn encode_invalid_rv64() {
+        let r = Reg::new(1);
+        let inst = Instr::OpImm {
+            rd: r, rs1: r, simm: 64, alu_op: RvALUOpImm::Slli
+        };
+        assert_eq!(Rv64::encode(inst),
+            Err(EncodeError::new(InstFormat::I, 64, EncodeReason::OutOfRange)));
+        let inst = Instr::OpImm32 {
+            rd: r, rs1: r, simm: 32, alu_op: RvALUOpImm::Slli
+        };
+        assert_eq!(Rv64::encode(inst),
+            Err(EncodeError::new(InstFormat::I, 32, EncodeReason::OutOfRange)));
+    }
+
+    #[test]
+    fn eval_rv64() {
+        // Word operations use the low 32 bits and sign-extend the result
+        assert_eq!(RvALUOpImm::Addi.eval_word(0x1_7fff_ffff, 1),
+            0xffff_ffff_8000_0000);
+        assert_eq!(RvALUOpImm::Addi.eval_word(0, -1), u64::MAX);
+        assert_eq!(RvALUOpImm::Slli.eval_word(1, 31), 0xffff_ffff_8000_0000);
+        assert_eq!(RvALUOp::Sll.eval_word(1, 31), 0xffff_ffff_8000_0000);
+        assert_eq!(RvALUOp::Sll.eval_word(3, 0x21), 6);
+        assert_eq!(RvALUOp::Sra.eval_word(0x8000_0000, 4),
+            0xffff_ffff_f800_0000);
+        assert_eq!(RvALUOp::Sra.eval_word(0x8000_0000_0000_0100, 4), 0x10);
+        assert_eq!(RvALUOp::Srl.eval_word(0x8000_0000, 4), 0x0800_0000);
+        assert_eq!(RvALUOp::Sub.eval_word(0, 1), u64::MAX);
+
+        // Shift amounts are six bits wide
+        assert_eq!(RvALUOpImm::Slli.eval64(1, 63), 0x8000_0000_0000_0000);
+        assert_eq!(RvALUOpImm::Srai.eval64(0x8000_0000_0000_0000, 32),
+            0xffff_ffff_8000_0000);
+        assert_eq!(RvALUOpImm::Srli.eval64(0x8000_0000_0000_0000, 32),
+            0x8000_0000);
+        assert_eq!(RvALUOp::Sll.eval64(1, 0x43), 8);
+        assert_eq!(RvALUOp::Sra.eval64(u64::MAX << 40, 40), u64::MAX);
+
+        // Immediates are sign-extended to 64 bits
+        assert_eq!(RvALUOpImm::Addi.eval64(0, -1), u64::MAX);
+        assert_eq!(RvALUOpImm::Sltiu.eval64(5, -1), 1);
+        assert_eq!(RvALUOp::Slt.eval64(u64::MAX, 0), 1);
+        assert!(RvBranchOp::Lt.eval64(u64::MAX, 0));
+        assert!(!RvBranchOp::Ltu.eval64(u64::MAX, 0));
+    }
+
+    #[test]
+    fn decode_roundtrip_rv64() {
+        let mut x: u32 = 0x8765_4321;
+        for _ in 0..(1 << 18) {
+            x ^= x << 13;
+            x ^= x >> 17;
+            x ^= x << 5;
+            let enc = x | 0b11;
+            if let Ok(inst) = Rv64::decode(enc) {
+                let res = Rv64::encode(inst).unwrap();
+                if let Instr::Fence { .. } | Instr::FenceTso | Instr::FenceI
+                    = inst
+                {
+                    assert_eq!(Rv64::decode(res), Ok(inst));
+                } else {
+                    assert_eq!(res, enc, "{}", inst);
+                }
+            }
+        }
+    }
+}
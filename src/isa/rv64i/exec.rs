@@ -0,0 +1,322 @@
+//! An instruction-level interpreter for RV64I.
+
+use crate::isa::rv32i::*;
+use crate::isa::rv64i::{Rv64, Rv64State};
+use crate::memory::{AccessFault, Bus};
+
+/// Reasons why an instruction could not be executed.
+///
+/// When a trap occurs, the state of the hart is left unchanged and the
+/// program counter still points to the trapping instruction.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Rv64Trap {
+    /// The instruction could not be decoded.
+    IllegalInstruction(DecodeError),
+    /// The instruction is valid, but is not supported by the interpreter.
+    Unimplemented(Instr),
+    /// A jump or branch to a misaligned address.
+    InstructionMisaligned(u64),
+    /// An instruction fetch from an address which cannot be read.
+    InstructionAccessFault(u64),
+    /// A load from an address which cannot be read.
+    LoadAccessFault(u64),
+    /// A store to an address which cannot be written.
+    StoreAccessFault(u64),
+    /// An 'ecall' instruction.
+    EnvironmentCall,
+    /// An 'ebreak' instruction.
+    Breakpoint,
+}
+impl std::fmt::Display for Rv64Trap {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::IllegalInstruction(e) => write!(f, "illegal instruction: {}", e),
+            Self::Unimplemented(inst) => write!(f, "unimplemented: {}", inst),
+            Self::InstructionMisaligned(addr) => {
+                write!(f, "misaligned instruction address 0x{:016x}", addr)
+            },
+            Self::InstructionAccessFault(addr) => {
+                write!(f, "instruction access fault at 0x{:016x}", addr)
+            },
+            Self::LoadAccessFault(addr) => {
+                write!(f, "load access fault at 0x{:016x}", addr)
+            },
+            Self::StoreAccessFault(addr) => {
+                write!(f, "store access fault at 0x{:016x}", addr)
+            },
+            Self::EnvironmentCall => write!(f, "environment call"),
+            Self::Breakpoint => write!(f, "breakpoint"),
+        }
+    }
+}
+impl std::error::Error for Rv64Trap {}
+
+impl Rv64Trap {
+    fn fetch_fault(f: AccessFault) -> Self {
+        Self::InstructionAccessFault(f.addr as u64)
+    }
+    fn load_fault(f: AccessFault) -> Self {
+        Self::LoadAccessFault(f.addr as u64)
+    }
+    fn store_fault(f: AccessFault) -> Self {
+        Self::StoreAccessFault(f.addr as u64)
+    }
+}
+
+impl Rv64State {
+    /// Fetch, decode, and execute a single instruction.
+    ///
+    /// Instructions are always fetched in little-endian byte order, while
+    /// loads and stores use the byte order of the bus.
+    pub fn step(&mut self, mem: &mut impl Bus) -> Result<(), Rv64Trap> {
+        let pc = self.pc;
+        let mut buf = [0u8; 4];
+        mem.fetch(pc as usize, &mut buf).map_err(Rv64Trap::fetch_fault)?;
+        let inst = Rv64::decode_with(u32::from_le_bytes(buf), self.ext)
+            .map_err(Rv64Trap::IllegalInstruction)?;
+        let next_pc = self.execute(inst, pc.wrapping_add(4), mem)?;
+        self.pc = next_pc;
+        Ok(())
+    }
+
+    /// Check the alignment of a jump or branch target.
+    fn check_target(&self, target: u64) -> Result<u64, Rv64Trap> {
+        // Compressed encodings are not supported for RV64
+        if !target.is_multiple_of(4) {
+            return Err(Rv64Trap::InstructionMisaligned(target));
+        }
+        Ok(target)
+    }
+
+    /// Execute an instruction, returning the address of the next
+    /// instruction.
+    fn execute(&mut self, inst: Instr, next_pc: u64, mem: &mut impl Bus)
+        -> Result<u64, Rv64Trap>
+    {
+        let pc = self.pc;
+        match inst {
+            Instr::Op { rd, rs1, rs2, alu_op } => {
+                let res = alu_op.eval64(self.reg(rs1), self.reg(rs2));
+                self.set_reg(rd, res);
+            },
+            Instr::OpImm { rd, rs1, simm, alu_op } => {
+                let res = alu_op.eval64(self.reg(rs1), simm);
+                self.set_reg(rd, res);
+            },
+            Instr::Op32 { rd, rs1, rs2, alu_op } => {
+                let res = alu_op.eval_word(self.reg(rs1), self.reg(rs2));
+                self.set_reg(rd, res);
+            },
+            Instr::OpImm32 { rd, rs1, simm, alu_op } => {
+                let res = alu_op.eval_word(self.reg(rs1), simm);
+                self.set_reg(rd, res);
+            },
+            Instr::Load { rd, rs1, simm, width } => {
+                let addr = self.reg(rs1).wrapping_add(simm as i64 as u64);
+                let addr = addr as usize;
+                let res = match width {
+                    RvWidth::Byte => mem.read_u8(addr).map(|v| v as i8 as u64),
+                    RvWidth::Half => mem.read_u16(addr).map(|v| v as i16 as u64),
+                    RvWidth::Word => mem.read_u32(addr).map(|v| v as i32 as u64),
+                    RvWidth::Double => mem.read_u64(addr),
+                    RvWidth::ByteUnsigned => mem.read_u8(addr).map(u64::from),
+                    RvWidth::HalfUnsigned => mem.read_u16(addr).map(u64::from),
+                    RvWidth::WordUnsigned => mem.read_u32(addr).map(u64::from),
+                };
+                let res = res.map_err(Rv64Trap::load_fault)?;
+                self.set_reg(rd, res);
+            },
+            Instr::Store { rs1, rs2, simm, width } => {
+                let addr = self.reg(rs1).wrapping_add(simm as i64 as u64);
+                let addr = addr as usize;
+                let val = self.reg(rs2);
+                let res = match width {
+                    RvWidth::Byte => mem.write_u8(addr, val as u8),
+                    RvWidth::Half => mem.write_u16(addr, val as u16),
+                    RvWidth::Word => mem.write_u32(addr, val as u32),
+                    RvWidth::Double => mem.write_u64(addr, val),
+                    _ => return Err(Rv64Trap::Unimplemented(inst)),
+                };
+                res.map_err(Rv64Trap::store_fault)?;
+            },
+            Instr::Jal { rd, simm } => {
+                let target = pc.wrapping_add(simm as i64 as u64);
+                let target = self.check_target(target)?;
+                self.set_reg(rd, next_pc);
+                return Ok(target);
+            },
+            Instr::Jalr { rd, rs1, simm } => {
+                let target = self.reg(rs1).wrapping_add(simm as i64 as u64) & !1;
+                let target = self.check_target(target)?;
+                self.set_reg(rd, next_pc);
+                return Ok(target);
+            },
+            Instr::Branch { rs1, rs2, simm, brn_op } => {
+                if brn_op.eval64(self.reg(rs1), self.reg(rs2)) {
+                    let target = pc.wrapping_add(simm as i64 as u64);
+                    return self.check_target(target);
+                }
+            },
+            // The upper immediate is sign-extended to 64 bits
+            Instr::AuiPc { rd, uimm } => {
+                let res = pc.wrapping_add(uimm as i32 as i64 as u64);
+                self.set_reg(rd, res);
+            },
+            Instr::Lui { rd, uimm } => {
+                self.set_reg(rd, uimm as i32 as i64 as u64);
+            },
+
+            // There is no cache or pipeline to synchronize with
+            Instr::Fence { .. } | Instr::FenceTso | Instr::FenceI |
+            Instr::Wfi => {},
+
+            Instr::Ecall => return Err(Rv64Trap::EnvironmentCall),
+            Instr::Ebreak => return Err(Rv64Trap::Breakpoint),
+
+            Instr::MulDiv { .. } | Instr::Amo { .. } |
+            Instr::Mret | Instr::Sret |
+            Instr::Csr { .. } | Instr::CsrImm { .. } |
+            Instr::FpLoad { .. } | Instr::FpStore { .. } |
+            Instr::FpFma { .. } | Instr::FpArith { .. } |
+            Instr::FpSqrt { .. } | Instr::FpOp { .. } |
+            Instr::FpCmp { .. } | Instr::FpClass { .. } |
+            Instr::FpCvtToInt { .. } | Instr::FpCvtFromInt { .. } |
+            Instr::FpCvt { .. } | Instr::FpMvToInt { .. } |
+            Instr::FpMvFromInt { .. } => {
+                return Err(Rv64Trap::Unimplemented(inst));
+            },
+        }
+        Ok(next_pc)
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use crate::isa::*;
+    use crate::isa::rv64i::{Rv64, Rv64State};
+    use crate::isa::rv64i::exec::*;
+    use crate::memory::NaiveRAM;
+
+    fn r(name: &str) -> Reg {
+        Reg::from_name(name).unwrap()
+    }
+
+    /// Encode a program at address zero and run it until it traps.
+    fn run(prog: &[Instr]) -> (Rv64State, NaiveRAM<0x1000>, Rv64Trap) {
+        let mut ram = NaiveRAM::<0x1000>::new();
+        for (i, inst) in prog.iter().enumerate() {
+            let enc = Rv64::encode(*inst).unwrap();
+            ram.write_bytes(i * 4, &enc.to_le_bytes());
+        }
+        let mut state = Rv64State::new(0);
+        for _ in 0..10_000 {
+            if let Err(trap) = state.step(&mut ram) {
+                return (state, ram, trap);
+            }
+        }
+        panic!("program did not terminate");
+    }
+
+    fn imm(rd: &str, rs1: &str, simm: i32, alu_op: RvALUOpImm) -> Instr {
+        Instr::OpImm { rd: r(rd), rs1: r(rs1), simm, alu_op }
+    }
+    fn imm32(rd: &str, rs1: &str, simm: i32, alu_op: RvALUOpImm) -> Instr {
+        Instr::OpImm32 { rd: r(rd), rs1: r(rs1), simm, alu_op }
+    }
+    fn op32(rd: &str, rs1: &str, rs2: &str, alu_op: RvALUOp) -> Instr {
+        Instr::Op32 { rd: r(rd), rs1: r(rs1), rs2: r(rs2), alu_op }
+    }
+
+    #[test]
+    fn exec_rv64_arith() {
+        let (s, _, trap) = run(&[
+            imm("a0", "zero", -1, RvALUOpImm::Addi),
+            imm("a1", "a0", 63, RvALUOpImm::Slli),
+            imm("a2", "a1", 32, RvALUOpImm::Srai),
+            imm("a3", "a1", 63, RvALUOpImm::Srli),
+            imm32("a4", "a3", -2, RvALUOpImm::Addi),
+            Instr::Lui { rd: r("a5"), uimm: 0x8000_0000 },
+            imm32("a6", "a5", -1, RvALUOpImm::Addi),
+            imm("t0", "zero", 31, RvALUOpImm::Addi),
+            op32("a7", "a3", "t0", RvALUOp::Sll),
+            op32("t1", "a5", "a3", RvALUOp::Sra),
+            op32("t2", "a0", "a3", RvALUOp::Add),
+            Instr::Ecall,
+        ]);
+        assert_eq!(trap, Rv64Trap::EnvironmentCall);
+        assert_eq!(s.reg(r("a0")), u64::MAX);
+        assert_eq!(s.reg(r("a1")), 0x8000_0000_0000_0000);
+        assert_eq!(s.reg(r("a2")), 0xffff_ffff_8000_0000);
+        assert_eq!(s.reg(r("a3")), 1);
+        assert_eq!(s.reg(r("a4")), u64::MAX);
+        assert_eq!(s.reg(r("a5")), 0xffff_ffff_8000_0000);
+        assert_eq!(s.reg(r("a6")), 0x7fff_ffff);
+        assert_eq!(s.reg(r("a7")), 0xffff_ffff_8000_0000);
+        assert_eq!(s.reg(r("t1")), 0xffff_ffff_c000_0000);
+        assert_eq!(s.reg(r("t2")), 0);
+        assert_eq!(s.pc, 0x2c);
+    }
+
+    #[test]
+    fn exec_rv64_loads_stores() {
+        let load = |rd, simm, width| Instr::Load {
+            rd: r(rd), rs1: r("a0"), simm, width
+        };
+        let (s, ram, _) = run(&[
+            imm("a0", "zero", 0x400, RvALUOpImm::Addi),
+            imm("a1", "zero", -3, RvALUOpImm::Addi),
+            imm("a1", "a1", 62, RvALUOpImm::Srli),
+            imm("a1", "a1", 31, RvALUOpImm::Slli),
+            Instr::Store { rs1: r("a0"), rs2: r("a1"), simm: 8,
+                width: RvWidth::Double },
+            load("t0", 8, RvWidth::Double),
+            load("t1", 8, RvWidth::Word),
+            load("t2", 8, RvWidth::WordUnsigned),
+            load("t3", 12, RvWidth::Word),
+            Instr::Store { rs1: r("a0"), rs2: r("a1"), simm: 16,
+                width: RvWidth::Word },
+            load("t4", 16, RvWidth::Double),
+            Instr::Ebreak,
+        ]);
+        assert_eq!(s.reg(r("a1")), 0x1_8000_0000);
+        assert_eq!(s.reg(r("t0")), 0x1_8000_0000);
+        assert_eq!(s.reg(r("t1")), 0xffff_ffff_8000_0000);
+        assert_eq!(s.reg(r("t2")), 0x8000_0000);
+        assert_eq!(s.reg(r("t3")), 1);
+        assert_eq!(s.reg(r("t4")), 0x8000_0000);
+        let mut buf = [0u8; 8];
+        ram.read_bytes(0x408, &mut buf);
+        assert_eq!(u64::from_le_bytes(buf), 0x1_8000_0000);
+    }
+
+    #[test]
+    fn exec_rv64_traps() {
+        let (s, _, trap) = run(&[
+            imm("a0", "zero", 1, RvALUOpImm::Addi),
+            imm("a0", "a0", 40, RvALUOpImm::Slli),
+            Instr::Load { rd: r("t0"), rs1: r("a0"), simm: 0,
+                width: RvWidth::Double },
+        ]);
+        assert_eq!(trap, Rv64Trap::LoadAccessFault(1 << 40));
+        assert_eq!(s.pc, 8);
+
+        // Branches compare all 64 bits
+        let (s, _, trap) = run(&[
+            imm("a0", "zero", 1, RvALUOpImm::Addi),
+            imm("a0", "a0", 32, RvALUOpImm::Slli),
+            Instr::Branch { rs1: r("a0"), rs2: r("zero"), simm: 8,
+                brn_op: RvBranchOp::Eq },
+            Instr::Ecall,
+            Instr::Ebreak,
+        ]);
+        assert_eq!(trap, Rv64Trap::EnvironmentCall);
+        assert_eq!(s.pc, 0xc);
+
+        let (_, _, trap) = run(&[
+            Instr::MulDiv { rd: r("a0"), rs1: r("a0"), rs2: r("a0"),
+                mul_op: RvMulOp::Mul },
+        ]);
+        assert!(matches!(trap, Rv64Trap::Unimplemented(_)));
+    }
+}
@@ -1,9 +1,10 @@
-#![feature(int_log)]
 #![allow(unused_parens)]
+#![allow(clippy::unusual_byte_groupings)]
 
 pub mod isa;
 pub mod topology;
 pub mod memory;
+pub mod elf;
 
 
 
@@ -1,30 +1,318 @@
 
 pub mod cache;
+pub mod sparse;
+pub mod system;
+
+/// Different kinds of atomic memory accesses.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AtomicKind {
+    /// A load which acquires a reservation (ie. 'lr').
+    LoadReserved,
+    /// A store which depends on a reservation (ie. 'sc').
+    StoreConditional { success: bool },
+    /// An atomic read-modify-write (ie. 'amoadd').
+    ReadModifyWrite,
+}
+
+/// Interface to models which observe atomic memory accesses.
+pub trait AtomicObserver {
+    /// Observe an atomic access to the provided address.
+    fn observe_atomic(&mut self, addr: usize, kind: AtomicKind);
+}
+
+/// Byte order for multi-byte accesses.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Endian {
+    #[default]
+    Little,
+    Big,
+}
+
+/// Different kinds of memory accesses.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AccessKind { Read, Write }
+
+/// An access which cannot be completed by a memory or device.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct AccessFault {
+    /// The address of the access.
+    pub addr: usize,
+    /// The size of the access (in bytes).
+    pub len: usize,
+    pub kind: AccessKind,
+}
+impl AccessFault {
+    pub fn new(addr: usize, len: usize, kind: AccessKind) -> Self {
+        Self { addr, len, kind }
+    }
+}
+impl std::fmt::Display for AccessFault {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let kind = match self.kind {
+            AccessKind::Read => "read",
+            AccessKind::Write => "write",
+        };
+        write!(f, "access fault on {}-byte {} at 0x{:08x}", self.len, kind, 
+            self.addr)
+    }
+}
+impl std::error::Error for AccessFault {}
+
+/// Interface to a memory system which is accessed by a processor.
+///
+/// Implementors only need to provide [Bus::read] and [Bus::write]. Typed
+/// accesses are built on top of these, using the byte order given by
+/// [Bus::endian].
+pub trait Bus {
+    /// Copy data starting at address `addr` into a slice `dst`.
+    fn read(&mut self, addr: usize, dst: &mut [u8]) -> Result<(), AccessFault>;
+
+    /// Copy data from a slice `src` into memory starting at address `addr`.
+    fn write(&mut self, addr: usize, src: &[u8]) -> Result<(), AccessFault>;
+
+    /// The byte order used for typed accesses.
+    fn endian(&self) -> Endian {
+        Endian::Little
+    }
+
+    /// Copy data for an instruction fetch starting at address `addr` into a
+    /// slice `dst`. By default, this is the same as [Bus::read].
+    fn fetch(&mut self, addr: usize, dst: &mut [u8]) -> Result<(), AccessFault> {
+        self.read(addr, dst)
+    }
+
+    /// Observe an atomic access to the provided address (see
+    /// [AtomicObserver]). This is called after the access itself, and 
+    /// does nothing by default.
+    fn observe_atomic(&mut self, _addr: usize, _kind: AtomicKind) {}
+
+    fn read_u8(&mut self, addr: usize) -> Result<u8, AccessFault> {
+        let mut buf = [0u8; 1];
+        self.read(addr, &mut buf)?;
+        Ok(buf[0])
+    }
+    fn read_u16(&mut self, addr: usize) -> Result<u16, AccessFault> {
+        let mut buf = [0u8; 2];
+        self.read(addr, &mut buf)?;
+        Ok(match self.endian() {
+            Endian::Little => u16::from_le_bytes(buf),
+            Endian::Big => u16::from_be_bytes(buf),
+        })
+    }
+    fn read_u32(&mut self, addr: usize) -> Result<u32, AccessFault> {
+        let mut buf = [0u8; 4];
+        self.read(addr, &mut buf)?;
+        Ok(match self.endian() {
+            Endian::Little => u32::from_le_bytes(buf),
+            Endian::Big => u32::from_be_bytes(buf),
+        })
+    }
+    fn read_u64(&mut self, addr: usize) -> Result<u64, AccessFault> {
+        let mut buf = [0u8; 8];
+        self.read(addr, &mut buf)?;
+        Ok(match self.endian() {
+            Endian::Little => u64::from_le_bytes(buf),
+            Endian::Big => u64::from_be_bytes(buf),
+        })
+    }
+
+    fn write_u8(&mut self, addr: usize, val: u8) -> Result<(), AccessFault> {
+        self.write(addr, &[val])
+    }
+    fn write_u16(&mut self, addr: usize, val: u16) -> Result<(), AccessFault> {
+        match self.endian() {
+            Endian::Little => self.write(addr, &val.to_le_bytes()),
+            Endian::Big => self.write(addr, &val.to_be_bytes()),
+        }
+    }
+    fn write_u32(&mut self, addr: usize, val: u32) -> Result<(), AccessFault> {
+        match self.endian() {
+            Endian::Little => self.write(addr, &val.to_le_bytes()),
+            Endian::Big => self.write(addr, &val.to_be_bytes()),
+        }
+    }
+    fn write_u64(&mut self, addr: usize, val: u64) -> Result<(), AccessFault> {
+        match self.endian() {
+            Endian::Little => self.write(addr, &val.to_le_bytes()),
+            Endian::Big => self.write(addr, &val.to_be_bytes()),
+        }
+    }
+}
+impl <T: Bus + ?Sized> Bus for &mut T {
+    fn read(&mut self, addr: usize, dst: &mut [u8]) -> Result<(), AccessFault> {
+        (**self).read(addr, dst)
+    }
+    fn write(&mut self, addr: usize, src: &[u8]) -> Result<(), AccessFault> {
+        (**self).write(addr, src)
+    }
+    fn endian(&self) -> Endian {
+        (**self).endian()
+    }
+    fn fetch(&mut self, addr: usize, dst: &mut [u8]) -> Result<(), AccessFault> {
+        (**self).fetch(addr, dst)
+    }
+    fn observe_atomic(&mut self, addr: usize, kind: AtomicKind) {
+        (**self).observe_atomic(addr, kind)
+    }
+}
 
 /// A naive model of a simple random-access memory. 
+///
+/// Storage for all `SIZE` bytes is allocated up front. See 
+/// [sparse::SparseRAM] for modeling large address spaces.
 pub struct NaiveRAM<const SIZE: usize> {
     data: Box<[u8; SIZE]>,
+    endian: Endian,
+}
+impl <const SIZE: usize> Default for NaiveRAM<SIZE> {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 impl <const SIZE: usize> NaiveRAM<SIZE> {
     pub fn new() -> Self {
+        // Avoid building a temporary array on the stack
+        let data = vec![0u8; SIZE].into_boxed_slice().try_into().unwrap();
         Self {
-            data: Box::new([0; SIZE]),
+            data,
+            endian: Endian::Little,
         }
     }
 
+    /// Create a RAM which uses the given byte order for typed accesses.
+    pub fn with_endian(endian: Endian) -> Self {
+        Self { endian, ..Self::new() }
+    }
+
+    /// Returns a fault if an access is not within the bounds of RAM.
+    fn check(addr: usize, len: usize, kind: AccessKind) 
+        -> Result<(), AccessFault> 
+    {
+        match addr.checked_add(len) {
+            Some(end) if end <= SIZE => Ok(()),
+            _ => Err(AccessFault::new(addr, len, kind)),
+        }
+    }
+
+    /// Copy data from RAM at offset `off`, into a slice `dst`. 
+    /// Returns a fault if any part of the access is out of range.
+    pub fn try_read_bytes(&self, off: usize, dst: &mut [u8])
+        -> Result<(), AccessFault>
+    {
+        Self::check(off, dst.len(), AccessKind::Read)?;
+        dst.copy_from_slice(&self.data[off..(off + dst.len())]);
+        Ok(())
+    }
+
+    /// Copy data from a slice `src` into RAM, starting at offset `off`.
+    /// Returns a fault if any part of the access is out of range.
+    pub fn try_write_bytes(&mut self, off: usize, src: &[u8])
+        -> Result<(), AccessFault>
+    {
+        Self::check(off, src.len(), AccessKind::Write)?;
+        self.data[off..(off + src.len())].copy_from_slice(src);
+        Ok(())
+    }
+
     /// Copy data from RAM at offset `off`, into a slice `dst`.
+    /// Panics if any part of the access is out of range.
     pub fn read_bytes(&self, off: usize, dst: &mut [u8]) {
-        assert!(off + dst.len() < SIZE);
-        dst.copy_from_slice(&self.data[off..(off + dst.len())])
+        self.try_read_bytes(off, dst).unwrap_or_else(|f| panic!("{}", f))
     }
 
     /// Copy data from a slice `src` into RAM, starting at offset `off`.
+    /// Panics if any part of the access is out of range.
     pub fn write_bytes(&mut self, off: usize, src: &[u8]) {
-        assert!(off + src.len() < SIZE);
-        self.data[off..(off + src.len())].copy_from_slice(src)
+        self.try_write_bytes(off, src).unwrap_or_else(|f| panic!("{}", f))
+    }
+}
+impl <const SIZE: usize> Bus for NaiveRAM<SIZE> {
+    fn read(&mut self, addr: usize, dst: &mut [u8]) -> Result<(), AccessFault> {
+        self.try_read_bytes(addr, dst)
+    }
+    fn write(&mut self, addr: usize, src: &[u8]) -> Result<(), AccessFault> {
+        self.try_write_bytes(addr, src)
+    }
+    fn endian(&self) -> Endian {
+        self.endian
     }
 }
 
 
+#[cfg(test)]
+mod test {
+    use crate::memory::*;
+
+    #[test]
+    fn bus_typed_access() {
+        let mut ram = NaiveRAM::<0x100>::new();
+        ram.write_u32(0x10, 0x1122_3344).unwrap();
+        assert_eq!(ram.read_u8(0x10), Ok(0x44));
+        assert_eq!(ram.read_u16(0x12), Ok(0x1122));
+        ram.write_u64(0x20, 0x0102_0304_0506_0708).unwrap();
+        assert_eq!(ram.read_u32(0x24), Ok(0x0102_0304));
+        ram.write_u16(0x30, 0xbeef).unwrap();
+        ram.write_u8(0x32, 0x7f).unwrap();
+        assert_eq!(ram.read_u32(0x30), Ok(0x007f_beef));
+
+        let mut ram = NaiveRAM::<0x100>::with_endian(Endian::Big);
+        ram.write_u32(0x10, 0x1122_3344).unwrap();
+        assert_eq!(ram.read_u8(0x10), Ok(0x11));
+        assert_eq!(ram.read_u16(0x12), Ok(0x3344));
+        ram.write_u64(0x20, 0x0102_0304_0506_0708).unwrap();
+        assert_eq!(ram.read_u32(0x24), Ok(0x0506_0708));
+    }
+
+    #[test]
+    fn naive_ram_bounds() {
+        let mut ram = NaiveRAM::<0x100>::new();
+        let mut buf = [0u8; 4];
+        // Accesses which end exactly at the last byte
+        ram.write_bytes(0xfc, &[1, 2, 3, 4]);
+        ram.read_bytes(0xfc, &mut buf);
+        assert_eq!(buf, [1, 2, 3, 4]);
+        ram.write_bytes(0x100, &[]);
+
+        assert_eq!(ram.try_read_bytes(0xfd, &mut buf),
+            Err(AccessFault::new(0xfd, 4, AccessKind::Read)));
+        assert_eq!(ram.try_write_bytes(0x100, &[0]),
+            Err(AccessFault::new(0x100, 1, AccessKind::Write)));
+        // Offsets which would overflow
+        assert_eq!(ram.try_read_bytes(usize::MAX - 2, &mut buf),
+            Err(AccessFault::new(usize::MAX - 2, 4, AccessKind::Read)));
+        assert_eq!(ram.try_write_bytes(usize::MAX, &buf),
+            Err(AccessFault::new(usize::MAX, 4, AccessKind::Write)));
+        // Failed accesses have no effect
+        ram.read_bytes(0xfc, &mut buf);
+        assert_eq!(buf, [1, 2, 3, 4]);
+    }
+
+    #[test]
+    #[should_panic]
+    fn naive_ram_out_of_range() {
+        let ram = NaiveRAM::<0x100>::new();
+        ram.read_bytes(usize::MAX, &mut [0; 2]);
+    }
 
+    #[test]
+    fn naive_ram_large() {
+        // Larger than the stack of a test thread
+        let mut ram = NaiveRAM::<0x0400_0000>::new();
+        ram.write_u32(0x03ff_fff0, 1).unwrap();
+        assert_eq!(ram.read_u32(0x03ff_fff0), Ok(1));
+    }
 
+    #[test]
+    fn bus_access_fault() {
+        let mut ram = NaiveRAM::<0x100>::new();
+        // The last bytes of RAM are accessible
+        assert_eq!(ram.write_u32(0xfc, 1), Ok(()));
+        assert_eq!(ram.read_u32(0xfc), Ok(1));
+        assert_eq!(ram.read_u32(0xfe),
+            Err(AccessFault::new(0xfe, 4, AccessKind::Read)));
+        assert_eq!(ram.write_u8(0x100, 0),
+            Err(AccessFault::new(0x100, 1, AccessKind::Write)));
+        assert_eq!(ram.read_u64(usize::MAX - 1),
+            Err(AccessFault::new(usize::MAX - 1, 8, AccessKind::Read)));
+    }
+}
@@ -1,7 +1,14 @@
 
 
+use crate::memory::{AtomicKind, AtomicObserver};
+
+pub mod dynamic;
+pub mod hierarchy;
+pub mod stats;
+use stats::CacheStats;
+
 /// Representing a cache line, with some size given by `NBYTES`.
-#[derive(Clone, Copy)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub struct CacheLine<const NBYTES: usize> { 
     pub data: [u8; NBYTES]
 }
@@ -53,46 +60,705 @@ impl CacheTag {
 }
 
 /// Interface to some cache replacement state/policy.
+///
+/// Each set in a cache has its own instance of the policy, which is
+/// notified about accesses to each way in the set.
 pub trait ReplacementPolicy<const NWAY: usize>: Default + Copy { 
+    /// Create the state for a set, given a seed which is unique to the
+    /// set. Only policies which make random choices need to use the seed.
+    fn seeded(_seed: u64) -> Self {
+        Self::default()
+    }
+
     /// Select a tag to replace from this set, returning the way index.
     fn replace(&mut self, set: &[CacheTag; NWAY]) -> usize;
+
+    /// Notify the policy that an access hit in a way.
+    fn on_hit(&mut self, _way: usize) {}
+
+    /// Notify the policy that a new line was filled into a way.
+    fn on_fill(&mut self, _way: usize) {}
+
+    /// Notify the policy that a way was invalidated.
+    fn on_invalidate(&mut self, _way: usize) {}
+}
+
+/// Replacement state for a single set, where the number of ways is only
+/// known at runtime.
+///
+/// Each of the provided policies is implemented once in terms of this, 
+/// and shared by [SetAssocCache] and [dynamic::DynCache].
+trait SetPolicy {
+    /// Select a way to replace from a set with the provided tags.
+    fn victim(&mut self, tags: &[CacheTag]) -> usize;
+
+    /// An access hit in a way.
+    fn hit(&mut self, _way: usize, _ways: usize) {}
+
+    /// A new line was filled into a way.
+    fn filled(&mut self, way: usize, ways: usize) {
+        self.hit(way, ways)
+    }
+
+    /// A way was invalidated.
+    fn invalidated(&mut self, _way: usize) {}
+}
+impl <T: SetPolicy + ?Sized> SetPolicy for Box<T> {
+    fn victim(&mut self, tags: &[CacheTag]) -> usize {
+        (**self).victim(tags)
+    }
+    fn hit(&mut self, way: usize, ways: usize) {
+        (**self).hit(way, ways)
+    }
+    fn filled(&mut self, way: usize, ways: usize) {
+        (**self).filled(way, ways)
+    }
+    fn invalidated(&mut self, way: usize) {
+        (**self).invalidated(way)
+    }
 }
 
+/// Adapts a [ReplacementPolicy] for sets with `NWAY` ways to a 
+/// [SetPolicy].
+#[derive(Clone, Copy)]
+struct FixedWays<P, const NWAY: usize>(P);
+impl <P: ReplacementPolicy<NWAY>, const NWAY: usize> SetPolicy 
+    for FixedWays<P, NWAY> 
+{
+    fn victim(&mut self, tags: &[CacheTag]) -> usize {
+        self.0.replace(tags.try_into().unwrap())
+    }
+    fn hit(&mut self, way: usize, _ways: usize) {
+        self.0.on_hit(way)
+    }
+    fn filled(&mut self, way: usize, _ways: usize) {
+        self.0.on_fill(way)
+    }
+    fn invalidated(&mut self, way: usize) {
+        self.0.on_invalidate(way)
+    }
+}
+
+/// Advance a SplitMix64 generator, returning the next value.
+fn splitmix64(state: &mut u64) -> u64 {
+    *state = state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    let mut z = *state;
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+}
+
+/// Random replacement, using a pseudo-random generator with an explicit
+/// seed so that simulations are reproducible.
 #[derive(Clone, Copy)]
 pub struct RandomPolicy {
-    seed: usize,
-    off:  usize,
+    state: u64,
+}
+impl RandomPolicy {
+    /// The seed used by [RandomPolicy::default].
+    pub const DEFAULT_SEED: u64 = 0x5eed_cafe_f00d_d00d;
+
+    pub fn new(seed: u64) -> Self {
+        Self { state: seed }
+    }
 }
 impl Default for RandomPolicy {
     fn default() -> Self { 
-        Self {
-            seed: unsafe { core::arch::x86_64::_rdtsc() as usize },
-            off: 0,
-        }
+        Self::new(Self::DEFAULT_SEED)
+    }
+}
+impl SetPolicy for RandomPolicy {
+    /// Select a random way, scaling the high bits into the range.
+    fn victim(&mut self, tags: &[CacheTag]) -> usize {
+        let next = splitmix64(&mut self.state) >> 32;
+        ((next * tags.len() as u64) >> 32) as usize
     }
 }
 impl <const NWAY: usize> ReplacementPolicy<NWAY> for RandomPolicy {
+    fn seeded(seed: u64) -> Self {
+        Self::new(seed)
+    }
+    fn replace(&mut self, set: &[CacheTag; NWAY]) -> usize {
+        self.victim(set)
+    }
+}
+
+/// A timestamp for each way in a set, stored in an array or a [Vec].
+#[derive(Clone, Copy, Default)]
+struct Stamps<S> {
+    stamp: S,
+    clock: u64,
+}
+impl <S: AsRef<[u64]> + AsMut<[u64]>> Stamps<S> {
+    fn new(stamp: S) -> Self {
+        Self { stamp, clock: 0 }
+    }
+
+    fn touch(&mut self, way: usize) {
+        self.clock += 1;
+        self.stamp.as_mut()[way] = self.clock;
+    }
+
+    fn clear(&mut self, way: usize) {
+        self.stamp.as_mut()[way] = 0;
+    }
+
+    /// Returns the way with the oldest timestamp.
+    fn oldest(&self) -> usize {
+        let stamp = self.stamp.as_ref();
+        (0..stamp.len()).min_by_key(|way| stamp[*way]).unwrap()
+    }
+}
+
+/// The time of the most recent access to each way.
+#[derive(Clone, Copy)]
+struct LruState<S>(Stamps<S>);
+impl <S: AsRef<[u64]> + AsMut<[u64]>> SetPolicy for LruState<S> {
+    fn victim(&mut self, _tags: &[CacheTag]) -> usize { self.0.oldest() }
+    fn hit(&mut self, way: usize, _ways: usize) { self.0.touch(way) }
+    fn invalidated(&mut self, way: usize) { self.0.clear(way) }
+}
+
+/// The time when each way was filled.
+#[derive(Clone, Copy)]
+struct FifoState<S>(Stamps<S>);
+impl <S: AsRef<[u64]> + AsMut<[u64]>> SetPolicy for FifoState<S> {
+    fn victim(&mut self, _tags: &[CacheTag]) -> usize { self.0.oldest() }
+    fn filled(&mut self, way: usize, _ways: usize) { self.0.touch(way) }
+    fn invalidated(&mut self, way: usize) { self.0.clear(way) }
+}
+
+/// Tree pseudo-LRU node bits, where node 'n' has children '2n' and 
+/// '2n+1' and the root is node 1. A set bit points to the right child.
+///
+/// When the number of ways is not a power of two, the tree has empty 
+/// subtrees on the right, which are never selected.
+#[derive(Clone, Copy, Default)]
+struct TreePlruState(u64);
+impl SetPolicy for TreePlruState {
+    /// Follow the node bits to the victim way.
+    fn victim(&mut self, tags: &[CacheTag]) -> usize {
+        let nway = tags.len();
+        let mut node = 1;
+        let mut way = 0;
+        for level in (0..nway.next_power_of_two().ilog2()).rev() {
+            let mut dir = ((self.0 >> node) & 1) as usize;
+            if (way | (1 << level)) >= nway {
+                dir = 0;
+            }
+            way |= dir << level;
+            node = (node << 1) | dir;
+        }
+        way
+    }
+
+    /// Point each node on the path to a way away from it.
+    fn hit(&mut self, way: usize, ways: usize) {
+        let mut node = 1;
+        for level in (0..ways.next_power_of_two().ilog2()).rev() {
+            let dir = (way >> level) & 1;
+            if dir == 0 {
+                self.0 |= 1 << node;
+            } else {
+                self.0 &= !(1 << node);
+            }
+            node = (node << 1) | dir;
+        }
+    }
+}
+
+/// Not-recently-used reference bits for each way.
+#[derive(Clone, Copy, Default)]
+struct NruState(u64);
+impl SetPolicy for NruState {
+    /// Returns the first way whose reference bit is clear.
+    fn victim(&mut self, tags: &[CacheTag]) -> usize {
+        (self.0.trailing_ones() as usize).min(tags.len() - 1)
+    }
+    fn hit(&mut self, way: usize, ways: usize) {
+        self.0 |= 1 << way;
+        if self.0.count_ones() as usize == ways {
+            self.0 = 1 << way;
+        }
+    }
+    fn invalidated(&mut self, way: usize) {
+        self.0 &= !(1 << way);
+    }
+}
+
+/// True least-recently-used replacement.
+#[derive(Clone, Copy)]
+pub struct LruPolicy<const NWAY: usize>(LruState<[u64; NWAY]>);
+impl <const NWAY: usize> Default for LruPolicy<NWAY> {
+    fn default() -> Self {
+        Self(LruState(Stamps::new([0; NWAY])))
+    }
+}
+impl <const NWAY: usize> ReplacementPolicy<NWAY> for LruPolicy<NWAY> {
+    fn replace(&mut self, set: &[CacheTag; NWAY]) -> usize {
+        self.0.victim(set)
+    }
+    fn on_hit(&mut self, way: usize) { self.0.hit(way, NWAY) }
+    fn on_fill(&mut self, way: usize) { self.0.filled(way, NWAY) }
+    fn on_invalidate(&mut self, way: usize) { self.0.invalidated(way) }
+}
+
+/// Tree pseudo-LRU replacement.
+///
+/// Each node in a binary tree over the ways points towards the half of 
+/// the tree which was accessed least recently. `NWAY` must be at most 64.
+#[derive(Clone, Copy, Default)]
+pub struct TreePlruPolicy<const NWAY: usize>(TreePlruState);
+impl <const NWAY: usize> ReplacementPolicy<NWAY> for TreePlruPolicy<NWAY> {
+    fn replace(&mut self, set: &[CacheTag; NWAY]) -> usize {
+        const { assert!(NWAY <= 64) };
+        self.0.victim(set)
+    }
+    fn on_hit(&mut self, way: usize) { self.0.hit(way, NWAY) }
+    fn on_fill(&mut self, way: usize) { self.0.filled(way, NWAY) }
+    fn on_invalidate(&mut self, way: usize) { self.0.invalidated(way) }
+}
+
+/// First-in first-out replacement, where hits have no effect.
+#[derive(Clone, Copy)]
+pub struct FifoPolicy<const NWAY: usize>(FifoState<[u64; NWAY]>);
+impl <const NWAY: usize> Default for FifoPolicy<NWAY> {
+    fn default() -> Self {
+        Self(FifoState(Stamps::new([0; NWAY])))
+    }
+}
+impl <const NWAY: usize> ReplacementPolicy<NWAY> for FifoPolicy<NWAY> {
+    fn replace(&mut self, set: &[CacheTag; NWAY]) -> usize {
+        self.0.victim(set)
+    }
+    fn on_hit(&mut self, way: usize) { self.0.hit(way, NWAY) }
+    fn on_fill(&mut self, way: usize) { self.0.filled(way, NWAY) }
+    fn on_invalidate(&mut self, way: usize) { self.0.invalidated(way) }
+}
+
+/// Not-recently-used replacement.
+///
+/// Each way has a reference bit which is set when it is accessed. The
+/// victim is the first way whose bit is clear. When every bit would be 
+/// set, all bits except the one for the most recent access are cleared.
+/// `NWAY` must be at most 64.
+#[derive(Clone, Copy, Default)]
+pub struct NruPolicy<const NWAY: usize>(NruState);
+impl <const NWAY: usize> ReplacementPolicy<NWAY> for NruPolicy<NWAY> {
     fn replace(&mut self, set: &[CacheTag; NWAY]) -> usize {
-        let res = (self.seed & ((1 << NWAY.log2()) - 1));
-        if self.seed == 0 {
-            let mut next = self.seed;
-            next ^= next >> 12;
-            next ^= next << 25;
-            next ^= next >> 27;
-            next  = next.wrapping_mul(0x2545f4914f6cdd1d);
-            self.seed = next;
+        const { assert!(NWAY <= 64) };
+        self.0.victim(set)
+    }
+    fn on_hit(&mut self, way: usize) { self.0.hit(way, NWAY) }
+    fn on_fill(&mut self, way: usize) { self.0.filled(way, NWAY) }
+    fn on_invalidate(&mut self, way: usize) { self.0.invalidated(way) }
+}
+
+/// Policies for handling writes which hit in the cache.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum WritePolicy {
+    /// Modified lines are marked dirty, and must be written back to memory
+    /// when they are evicted.
+    #[default]
+    WriteBack,
+    /// Every write is also sent to memory, so lines are never dirty.
+    WriteThrough,
+}
+
+/// Policies for handling writes which miss in the cache.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum AllocatePolicy {
+    /// The line is filled from memory before it is written.
+    #[default]
+    WriteAllocate,
+    /// The write is only sent to memory.
+    NoWriteAllocate,
+}
+
+/// Reasons why a cache geometry is invalid.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum GeometryError {
+    /// The line size is not a power of two.
+    LineSize(usize),
+    /// The number of sets is zero.
+    Sets(usize),
+    /// The number of ways is zero, or is not supported by the replacement 
+    /// policy.
+    Ways(usize),
+}
+impl std::fmt::Display for GeometryError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::LineSize(n) => {
+                write!(f, "line size must be a power of two (got {})", n)
+            },
+            Self::Sets(n) => {
+                write!(f, "number of sets must be non-zero (got {})", n)
+            },
+            Self::Ways(n) => {
+                write!(f, "unsupported number of ways (got {})", n)
+            },
+        }
+    }
+}
+impl std::error::Error for GeometryError {}
+
+/// The shape of a set-associative cache, which determines how addresses
+/// are split into a tag, set index, and byte offset. 
+///
+/// The low bits of an address are the byte offset within a line, and the
+/// remaining bits are the line number. The set index and tag are the 
+/// remainder and quotient of dividing the line number by the number of 
+/// sets. When the number of sets is a power of two, this is equivalent to
+/// the following scheme for an address of N bits:
+/// 
+///   address bit N                                          address bit 0
+///   v                                                                  v
+///   [ remaining high bits (tag bits)     | set index   | byte offset   ]
+///   [(N+1 - ilog2(sets) - ilog2(line_bytes)) | ilog2(sets) | ilog2(line_bytes) ]
+///
+/// The line size must always be a power of two. Geometries can only be
+/// created with [CacheGeometry::new], which checks this.
+///
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct CacheGeometry {
+    line_bytes: usize,
+    sets: usize,
+    ways: usize,
+}
+impl CacheGeometry {
+    /// Create a geometry, checking that the line size is a power of two
+    /// and that there is at least one set and way.
+    pub fn new(line_bytes: usize, sets: usize, ways: usize) 
+        -> Result<Self, GeometryError>
+    {
+        if !line_bytes.is_power_of_two() {
+            return Err(GeometryError::LineSize(line_bytes));
+        }
+        if sets == 0 {
+            return Err(GeometryError::Sets(sets));
+        }
+        if ways == 0 {
+            return Err(GeometryError::Ways(ways));
+        }
+        Ok(Self { line_bytes, sets, ways })
+    }
+
+    /// The number of bytes in a cache line.
+    pub const fn line_bytes(&self) -> usize {
+        self.line_bytes
+    }
+    /// The number of sets.
+    pub const fn sets(&self) -> usize {
+        self.sets
+    }
+    /// The number of ways in each set.
+    pub const fn ways(&self) -> usize {
+        self.ways
+    }
+
+    /// The total number of lines in the cache.
+    pub const fn lines(&self) -> usize {
+        self.sets * self.ways
+    }
+    /// The total number of bytes in the cache.
+    pub const fn capacity(&self) -> usize {
+        self.lines() * self.line_bytes
+    }
+
+    /// Get the offset for the provided address.
+    pub const fn offset(&self, addr: usize) -> usize {
+        (addr & ((1 << self.line_bytes.ilog2()) - 1))
+    }
+    /// Get the set index for the provided address.
+    pub const fn set(&self, addr: usize) -> usize {
+        ( (addr >> self.line_bytes.ilog2()) % self.sets ) 
+    }
+    /// Get the address of the line containing the provided address.
+    pub const fn line_addr(&self, addr: usize) -> usize {
+        (addr & !((1 << self.line_bytes.ilog2()) - 1))
+    }
+    /// Get the tag bits for the provided address.
+    pub const fn tag(&self, addr: usize) -> usize {
+        ( (addr >> self.line_bytes.ilog2()) / self.sets )
+    }
+    /// Rebuild the address of a line from its set index and tag bits.
+    pub const fn addr(&self, set: usize, tag: usize) -> usize {
+        ((tag * self.sets) + set) << self.line_bytes.ilog2()
+    }
+}
+
+/// A valid line which was removed from the cache to make room for another.
+///
+/// `L` is the type holding the contents of the line.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Eviction<L> {
+    /// The address of the first byte in the evicted line.
+    pub addr: usize,
+    /// Whether or not the line was modified (and must be written back).
+    pub dirty: bool,
+    /// The contents of the evicted line.
+    pub line: L,
+}
+
+/// The result of a write to a cache.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct WriteResult<L> {
+    /// Whether or not the written line was present in the cache.
+    pub hit: bool,
+    /// Whether or not the write must also be sent to memory.
+    pub forward: bool,
+    /// A dirty line which was evicted to allocate the written line, and
+    /// must be written back to memory.
+    pub writeback: Option<Eviction<L>>,
+}
+
+/// Types which can hold a copy of the contents of a line.
+trait LineData {
+    fn from_slice(data: &[u8]) -> Self;
+}
+impl LineData for Vec<u8> {
+    fn from_slice(data: &[u8]) -> Self {
+        data.to_vec()
+    }
+}
+impl <const NBYTES: usize> LineData for CacheLine<NBYTES> {
+    fn from_slice(data: &[u8]) -> Self {
+        Self { data: data.try_into().unwrap() }
+    }
+}
+
+/// Tag, line, and replacement state shared by [SetAssocCache] and 
+/// [dynamic::DynCache]. 
+///
+/// The entry for way 'w' in set 's' is stored at index '(s * ways) + w'.
+struct CacheCore<P: SetPolicy> {
+    geometry: CacheGeometry,
+    /// Cache tag storage.
+    tags: Vec<CacheTag>,
+    /// Cache line storage, with the same indexing as 'tags'.
+    data: Vec<u8>,
+    /// State associated with the replacement policy for each set.
+    policy: Vec<P>,
+    /// The address of a line reserved by an atomic access.
+    reserved: Option<usize>,
+    write_policy: WritePolicy,
+    allocate_policy: AllocatePolicy,
+    stats: CacheStats,
+}
+impl <P: SetPolicy> CacheCore<P> {
+    fn new(geometry: CacheGeometry, policy: Vec<P>) -> Self {
+        Self {
+            geometry,
+            tags: vec![CacheTag::default(); geometry.lines()],
+            data: vec![0; geometry.capacity()],
+            policy,
+            reserved: None,
+            write_policy: WritePolicy::default(),
+            allocate_policy: AllocatePolicy::default(),
+            stats: CacheStats::new(geometry.sets, geometry.lines()),
+        }
+    }
+
+    fn with_miss_classification(self) -> Self {
+        Self { stats: self.stats.with_miss_classification(), ..self }
+    }
+
+    /// Get the contents of the line at some index.
+    fn line(&self, idx: usize) -> &[u8] {
+        let n = self.geometry.line_bytes;
+        &self.data[(idx * n)..((idx + 1) * n)]
+    }
+
+    /// Get a mutable reference to the contents of the line at some index.
+    fn line_mut(&mut self, idx: usize) -> &mut [u8] {
+        let n = self.geometry.line_bytes;
+        &mut self.data[(idx * n)..((idx + 1) * n)]
+    }
+
+    /// If the provided address has a valid entry in the cache, get the
+    /// index of the entry.
+    fn find(&self, addr: usize) -> Option<usize> {
+        let g = self.geometry;
+        let (set, tag) = (g.set(addr), g.tag(addr));
+        let base = set * g.ways;
+        self.tags[base..(base + g.ways)].iter()
+            .position(|t| t.valid && t.tag == tag)
+            .map(|way| base + way)
+    }
+
+    fn is_dirty(&self, addr: usize) -> bool {
+        self.find(addr).is_some_and(|idx| self.tags[idx].dirty)
+    }
+
+    fn is_reserved(&self, addr: usize) -> bool {
+        self.reserved == Some(self.geometry.line_addr(addr))
+    }
+
+    /// Track the line reserved by 'lr'. Any other atomic access releases
+    /// the reservation.
+    fn observe_atomic(&mut self, addr: usize, kind: AtomicKind) {
+        self.reserved = match kind {
+            AtomicKind::LoadReserved => Some(self.geometry.line_addr(addr)),
+            _ => None,
+        };
+    }
+
+    fn invalidate(&mut self, addr: usize) {
+        if let Some(idx) = self.find(addr) {
+            self.invalidate_entry(idx);
+        }
+        if self.is_reserved(addr) {
+            self.reserved = None;
+        }
+    }
+
+    fn invalidate_all(&mut self) {
+        for idx in 0..self.tags.len() {
+            self.invalidate_entry(idx);
+        }
+        self.reserved = None;
+    }
+
+    /// Invalidate the entry at some index.
+    fn invalidate_entry(&mut self, idx: usize) {
+        let ways = self.geometry.ways;
+        if self.tags[idx].valid {
+            self.stats.invalidate(idx / ways);
+        }
+        self.tags[idx].invalidate();
+        self.policy[idx / ways].invalidated(idx % ways);
+    }
+
+    fn clean<L: LineData>(&mut self) -> Vec<Eviction<L>> {
+        let g = self.geometry;
+        let mut res = Vec::new();
+        for idx in 0..self.tags.len() {
+            let tag = self.tags[idx];
+            if tag.valid && tag.dirty {
+                self.tags[idx].dirty = false;
+                let addr = g.addr(idx / g.ways, tag.tag);
+                let line = L::from_slice(self.line(idx));
+                res.push(Eviction { addr, dirty: true, line });
+            }
+        }
+        res
+    }
+
+    /// Read the entry for the provided address, returning its index if 
+    /// the access hits.
+    fn read(&mut self, addr: usize) -> Option<usize> {
+        let g = self.geometry;
+        let hit = self.find(addr);
+        self.stats.access(g.set(addr), g.line_addr(addr), false, hit.is_some());
+        let idx = hit?;
+        self.policy[idx / g.ways].hit(idx % g.ways, g.ways);
+        Some(idx)
+    }
+
+    fn fill<L: LineData>(&mut self, addr: usize, data: &[u8])
+        -> Option<Eviction<L>>
+    {
+        assert_eq!(data.len(), self.geometry.line_bytes);
+        if self.find(addr).is_none() {
+            self.allocate(addr, |line| line.copy_from_slice(data)).1
         } else {
-            self.seed = self.seed >> NWAY.log2();
+            None
+        }
+    }
+
+    /// Put back a line which was replaced by the line for 'addr' (ie.
+    /// because it could not be written back), dropping the line for
+    /// 'addr'. Statistics still count the replacement.
+    fn restore(&mut self, addr: usize, victim: &Eviction<Vec<u8>>) {
+        let g = self.geometry;
+        debug_assert_eq!(g.set(addr), g.set(victim.addr));
+        if let Some(idx) = self.find(addr) {
+            self.tags[idx] = CacheTag {
+                valid: true, dirty: victim.dirty, tag: g.tag(victim.addr)
+            };
+            self.line_mut(idx).copy_from_slice(&victim.line);
         }
+    }
+
+    fn write<L: LineData>(&mut self, addr: usize, data: &[u8], 
+        fetch: impl FnOnce(usize, &mut [u8])) -> WriteResult<L>
+    {
+        let g = self.geometry;
+        let off = g.offset(addr);
+        assert!(off + data.len() <= g.line_bytes);
+
+        let write_back = self.write_policy == WritePolicy::WriteBack;
+        let hit = self.find(addr);
+        self.stats.access(g.set(addr), g.line_addr(addr), true, hit.is_some());
+        let mut res = WriteResult {
+            hit: hit.is_some(), forward: !write_back, writeback: None
+        };
+        let idx = match hit {
+            Some(idx) => {
+                self.policy[idx / g.ways].hit(idx % g.ways, g.ways);
+                idx
+            },
+            None => {
+                if self.allocate_policy == AllocatePolicy::NoWriteAllocate {
+                    res.forward = true;
+                    return res;
+                }
+                let line_addr = g.line_addr(addr);
+                let (idx, evicted) = self.allocate(addr, |line| {
+                    fetch(line_addr, line)
+                });
+                res.writeback = evicted.filter(|e| e.dirty);
+                idx
+            },
+        };
+        self.line_mut(idx)[off..(off + data.len())].copy_from_slice(data);
+        self.tags[idx].dirty |= write_back;
         res
     }
+
+    /// Allocate a line for the provided address, initializing it with
+    /// 'init'. Returns the index of the line, and the victim if a valid
+    /// line was replaced.
+    fn allocate<L: LineData>(&mut self, addr: usize, 
+        init: impl FnOnce(&mut [u8])) -> (usize, Option<Eviction<L>>)
+    {
+        let g = self.geometry;
+        let set = g.set(addr);
+        let base = set * g.ways;
+
+        // If there's an invalid entry in this set, use it. Otherwise, we 
+        // have to invoke some replacement policy
+        let tags = &self.tags[base..(base + g.ways)];
+        let way = match tags.iter().position(|t| !t.valid) {
+            Some(way) => way,
+            None => self.policy[set].victim(tags),
+        };
+        let idx = base + way;
+
+        let old_tag = self.tags[idx];
+        let evicted = old_tag.valid.then(|| Eviction {
+            addr: g.addr(set, old_tag.tag),
+            dirty: old_tag.dirty,
+            line: L::from_slice(self.line(idx)),
+        });
+        if evicted.as_ref().is_some_and(|e| self.reserved == Some(e.addr)) {
+            self.reserved = None;
+        }
+
+        self.tags[idx] = CacheTag { valid: true, dirty: false, tag: g.tag(addr) };
+        init(self.line_mut(idx));
+        self.policy[set].filled(way, g.ways);
+        self.stats.fill(set, old_tag.valid, old_tag.valid && old_tag.dirty);
+        (idx, evicted)
+    }
 }
 
 /// A naive model of a set-associative cache.
 ///
 /// Instances of this type are parameterized by other types/constants:
 ///
-/// - `NBYTES` is the number of bytes in a cache line
+/// - `NBYTES` is the number of bytes in a cache line (a power of two)
 /// - `NSET` is the number of sets
 /// - `NWAY` is the number of ways
 /// - `P` is some cache replacement policy
@@ -105,163 +771,140 @@ pub struct SetAssocCache
     <const NBYTES: usize, const NSET: usize, const NWAY: usize, P> where
     P: ReplacementPolicy<NWAY>
 {
-    /// Cache tag storage.
-    tags: [ [ CacheTag; NWAY]; NSET ],
-    /// Cache line storage.
-    sets: [ [ CacheLine<NBYTES>; NWAY]; NSET ],
-    /// State associated with the replacement policy.
-    _policy: P,
+    core: CacheCore<FixedWays<P, NWAY>>,
+}
+impl <const NBYTES: usize, const NSET: usize, const NWAY: usize, P> Default
+    for SetAssocCache<NBYTES, NSET, NWAY, P> where P: ReplacementPolicy<NWAY>
+{
+    fn default() -> Self {
+        Self::new()
+    }
 }
 impl <const NBYTES: usize, const NSET: usize, const NWAY: usize, P>
     SetAssocCache<NBYTES, NSET, NWAY, P> where P: ReplacementPolicy<NWAY>
 {
+    const GEOMETRY: CacheGeometry = CacheGeometry {
+        line_bytes: NBYTES, sets: NSET, ways: NWAY
+    };
+
     pub fn new() -> Self {
-        Self {
-            tags: [ [  CacheTag::default(); NWAY]; NSET ],
-            sets: [ [ CacheLine::default(); NWAY]; NSET ],
-            _policy: P::default()
-        }
+        Self::with_seed(RandomPolicy::DEFAULT_SEED)
     }
 
-    /// Invalidate an entry in the cache.
-    pub fn invalidate(&mut self, addr: usize) {
-        if let Some((tag, line)) = self.snoop_mut_checked(addr) {
-            tag.invalidate();
-        }
+    /// Create a cache where the replacement policy for each set is seeded
+    /// from a single seed. Caches created with the same seed behave 
+    /// identically.
+    pub fn with_seed(seed: u64) -> Self {
+        const { 
+            assert!(NBYTES.is_power_of_two() && NSET > 0 && NWAY > 0) 
+        };
+        let mut state = seed;
+        let policy = (0..NSET).map(|_| {
+            FixedWays(P::seeded(splitmix64(&mut state)))
+        }).collect();
+        Self { core: CacheCore::new(Self::GEOMETRY, policy) }
     }
 
-    /// Read an entry from the cache.
-    pub fn read(&mut self, addr: usize) -> Option<CacheLine<NBYTES>> {
-        if let Some((tag, line)) = self.snoop_mut_checked(addr) {
-            Some(*line)
-        } else {
-            None
-        }
+    /// Statistics collected since the cache was created, or since the last
+    /// call to [SetAssocCache::reset_stats].
+    pub fn stats(&self) -> &CacheStats {
+        &self.core.stats
     }
 
-    /// Authoritatively fill a cache line with data from a remote memory.
-    pub fn fill(&mut self, addr: usize, data: &[u8; NBYTES]) {
-        if let Some((tag, line)) = self.snoop_mut_checked(addr) {
-        } else {
-            let set = Self::get_set_bits(addr);
-            let new_tag = Self::get_tag_bits(addr);
-
-            // If there's an invalid entry in this set, use it 
-            if let Some((tag, line)) = self.find_invalid_mut(set) {
-                tag.valid = true;
-                tag.dirty = false;
-                tag.tag = new_tag;
-                line.fill(&data);
-            } 
-            // Otherwise, we have to invoke some replacement policy
-            else {
-                let way = self._policy.replace(&self.tags[set]);
-                { 
-                    let tag = self.get_tag_mut(set, way);
-                    tag.valid = true;
-                    tag.dirty = false;
-                    tag.tag  = new_tag;
-                }
-                let line = self.get_line_mut(set, way);
-                line.fill(&data);
-            }
-        }
+    /// Reset the statistics for this cache.
+    pub fn reset_stats(&mut self) {
+        self.core.stats.reset();
     }
 
-}
-
-/// These are helper functions for retrieving the set index and tag from a 
-/// physical address. I figure we probably only care about users representing 
-/// physical addresses with `u32` or `usize`, so assuming `usize` seems fine.
-///
-/// This also assumes that all physical addresses are associated with the 
-/// following scheme for an address of N bits:
-/// 
-///   address bit N                                          address bit 0
-///   v                                                                  v
-///   [ remaining high bits (tag bits)     | set index   | byte offset   ]
-///   [(N+1 - NSET.log2() - NBYTES.log2()) | NSET.log2() | NBYTES.log2() ]
-///
-/// This is probably a reasonable assumption, for now. 
-///
-impl <const NBYTES: usize, const NSET: usize, const NWAY: usize, P>
-    SetAssocCache<NBYTES, NSET, NWAY, P> where P: ReplacementPolicy<NWAY>
-{
-    /// Get the offset for the provided address.
-    const fn get_offset_bits(addr: usize) -> usize {
-        (addr & ((1 << NBYTES.log2()) - 1))
-    }
-    /// Get the set index for the provided address.
-    const fn get_set_bits(addr: usize) -> usize {
-        ( (addr >> NBYTES.log2()) & ((1 << NSET.log2()) - 1) ) 
+    /// Use the given policy for writes which hit in the cache.
+    pub fn with_write_policy(mut self, policy: WritePolicy) -> Self {
+        self.core.write_policy = policy;
+        self
     }
-    /// Get the tag bits for the provided address.
-    const fn get_tag_bits(addr: usize) -> usize {
-        let bit_idx = NBYTES.log2() + NSET.log2();
-        ((addr & !((1 << bit_idx) - 1)) >> bit_idx)
+
+    /// Use the given policy for writes which miss in the cache.
+    pub fn with_allocate_policy(mut self, policy: AllocatePolicy) -> Self {
+        self.core.allocate_policy = policy;
+        self
     }
-}
 
-/// These are all private methods for interacting with the state. 
-impl <const NBYTES: usize, const NSET: usize, const NWAY: usize, P>
-    SetAssocCache<NBYTES, NSET, NWAY, P> where P: ReplacementPolicy<NWAY>
-{
+    /// Classify each miss in the statistics for this cache (see
+    /// [CacheStats::with_miss_classification]).
+    pub fn with_miss_classification(mut self) -> Self {
+        self.core = self.core.with_miss_classification();
+        self
+    }
 
-    /// Get a mutable reference to some [CacheTag].
-    fn get_tag_mut(&mut self, set: usize, way: usize) -> &mut CacheTag {
-        &mut self.tags[set][way]
+    /// Returns true if the line containing the provided address is present
+    /// in the cache and has been modified.
+    pub fn is_dirty(&self, addr: usize) -> bool {
+        self.core.is_dirty(addr)
     }
 
-    /// Get a mutable reference to some [CacheLine].
-    fn get_line_mut(&mut self, set: usize, way: usize) 
-        -> &mut CacheLine<NBYTES> 
-    {
-        &mut self.sets[set][way]
+    /// Returns true if the line containing the provided address is 
+    /// currently reserved by an atomic access.
+    ///
+    /// Reservations are lost when the line is invalidated or evicted.
+    pub fn is_reserved(&self, addr: usize) -> bool {
+        self.core.is_reserved(addr)
     }
 
-    /// Try to find an invalid entry in the given set.
-    fn find_invalid_mut(&mut self, set: usize) 
-        -> Option<(&mut CacheTag, &mut CacheLine<NBYTES>)> 
-    {
-        self.tags[set].iter_mut().zip(self.sets[set].iter_mut())
-            .find(|(tag, line)| !tag.valid)
+    /// Invalidate an entry in the cache.
+    pub fn invalidate(&mut self, addr: usize) {
+        self.core.invalidate(addr)
     }
 
-    /// If the provided address has a valid entry in the cache, get a mutable 
-    /// references to the associated tag and cache line.
-    fn snoop_mut_checked(&mut self, addr: usize) 
-        -> Option<(&mut CacheTag, &mut CacheLine<NBYTES>)>
-    {
-        let tgt_set = Self::get_set_bits(addr);
-        let tgt_tag = Self::get_tag_bits(addr);
+    /// Invalidate every entry in the cache, discarding any modified data.
+    pub fn invalidate_all(&mut self) {
+        self.core.invalidate_all()
+    }
 
-        self.tags[tgt_set].iter_mut().zip(self.sets[tgt_set].iter_mut())
-            .find(|(tag, _line)| { tag.valid && tag.tag == tgt_tag })
+    /// Mark every modified line as clean, returning the lines which must
+    /// be written back to memory.
+    pub fn clean(&mut self) -> Vec<Eviction<CacheLine<NBYTES>>> {
+        self.core.clean()
     }
 
-    /// Invalidate a particular tag.
-    fn invalidate_entry(&mut self, set: usize, way: usize) {
-        self.tags[set][way].invalidate();
+    /// Read an entry from the cache.
+    pub fn read(&mut self, addr: usize) -> Option<CacheLine<NBYTES>> {
+        let idx = self.core.read(addr)?;
+        Some(CacheLine::from_slice(self.core.line(idx)))
     }
 
-    /// Invalidate all ways in a particular set.
-    fn invalidate_set(&mut self, set: usize) {
-        for (tag, line) in self.tags[set].iter_mut()
-            .zip(self.sets[set].iter_mut()) 
-        {
-            tag.invalidate();
-        }
+    /// Authoritatively fill a cache line with data from a remote memory.
+    ///
+    /// Returns the line which was replaced, if any. Dirty lines must be 
+    /// written back by the caller. Filling a line which is already present
+    /// has no effect.
+    pub fn fill(&mut self, addr: usize, data: &[u8; NBYTES]) 
+        -> Option<Eviction<CacheLine<NBYTES>>>
+    {
+        self.core.fill(addr, data)
     }
 
-    /// Invalidate the entire cache.
-    fn invalidate_cache(&mut self) {
-        for (tags, lines) in self.tags.iter_mut().zip(self.sets.iter_mut()) {
-            for tag in tags {
-                tag.invalidate();
-            }
-        }
+    /// Write to [part of] a single cache line.
+    ///
+    /// When a write misses and the line must be allocated, `fetch` is 
+    /// called with the address of the line to obtain its contents from 
+    /// memory. If allocating the line evicts a dirty line, the caller is 
+    /// responsible for writing it back.
+    pub fn write<F>(&mut self, addr: usize, data: &[u8], fetch: F)
+        -> WriteResult<CacheLine<NBYTES>> 
+        where F: FnOnce(usize) -> [u8; NBYTES]
+    {
+        self.core.write(addr, data, |addr, line| {
+            line.copy_from_slice(&fetch(addr))
+        })
     }
+}
 
+impl <const NBYTES: usize, const NSET: usize, const NWAY: usize, P>
+    AtomicObserver for SetAssocCache<NBYTES, NSET, NWAY, P> 
+    where P: ReplacementPolicy<NWAY>
+{
+    fn observe_atomic(&mut self, addr: usize, kind: AtomicKind) {
+        self.core.observe_atomic(addr, kind)
+    }
 }
 
 
@@ -269,12 +912,30 @@ impl <const NBYTES: usize, const NSET: usize, const NWAY: usize, P>
 mod test {
     use crate::memory::*;
     use crate::memory::cache::*;
+    use crate::memory::cache::stats::*;
+
+    /// Generate pseudo-random accesses for the cache tests, yielding the
+    /// address, length, and whether or not each access is a write.
+    ///
+    /// Accesses are at most 'max_len' bytes long, and do not extend past 
+    /// 'size' bytes. The same accesses are generated for every call.
+    pub(crate) fn random_accesses(count: usize, size: usize, max_len: usize)
+        -> impl Iterator<Item = (usize, usize, bool)>
+    {
+        let mut state = 0x5eed;
+        (0..count).map(move |_| {
+            let r = splitmix64(&mut state);
+            let len = 1 + (r >> 32) as usize % max_len;
+            let addr = (r & 0xffff_ffff) as usize % (size - len + 1);
+            (addr, len, r >> 63 != 0)
+        })
+    }
 
     #[test]
     fn set_associative_random() {
         let mut ram: NaiveRAM<0x0010_0000> = NaiveRAM::new();
         let mut cache: SetAssocCache<64, 64, 4, RandomPolicy> 
-            = SetAssocCache::new();
+            = SetAssocCache::new().with_miss_classification();
 
         for (idx, addr) in (0x0000_0000..0x0000_6000usize)
             .step_by(64).enumerate() 
@@ -290,18 +951,429 @@ mod test {
         ];
 
         for r in ranges.iter_mut() {
-            for (idx, addr) in r.step_by(64).enumerate() 
-            {
+            for addr in r.step_by(64) {
                 if let Some(line) = cache.read(addr) {
-                    println!("Hit {:08x}: {:02x?}", addr, line.data[0]);
+                    assert_eq!(line.data[0], (addr / 64) as u8);
                 } else {
                     let mut data = [0u8; 64];
                     ram.read_bytes(addr, &mut data);
                     cache.fill(addr, &data);
-                    println!("Miss {:08x}: fill {:02x}", addr, data[0]);
                 }
             }
         }
+
+        let stats = cache.stats().total();
+        assert_eq!(stats.reads, 0x280);
+        assert_eq!(stats.hits + stats.misses, 0x280);
+        assert_eq!(stats.fills, stats.misses);
+        // Every line in the first two passes is accessed for the first time
+        assert_eq!(stats.compulsory, 0x180);
+        // The last pass only misses on lines which were displaced by the 
+        // second pass, which has the same footprint as the cache
+        assert_eq!(stats.capacity, stats.misses - 0x180);
+        assert_eq!(stats.conflict, 0);
+        assert_eq!(stats.evictions, stats.misses - 0x100);
+        assert_eq!(cache.stats().sets().len(), 64);
+        assert!(cache.stats().sets().iter().all(|s| s.reads == 10));
+    }
+
+    #[test]
+    fn cache_stats() {
+        // Two sets of one way each
+        let mut cache: SetAssocCache<16, 2, 1, LruPolicy<1>> 
+            = SetAssocCache::new().with_miss_classification();
+        let access = |cache: &mut SetAssocCache<16, 2, 1, LruPolicy<1>>, 
+            addr: usize| 
+        {
+            if cache.read(addr).is_none() {
+                cache.fill(addr, &[0; 16]);
+            }
+        };
+        // 0x00 and 0x20 conflict in set zero, although they would both fit 
+        // in a fully-associative cache with two lines
+        for addr in [0x00, 0x20, 0x00, 0x20, 0x00] {
+            access(&mut cache, addr);
+        }
+        let set0 = cache.stats().sets()[0];
+        assert_eq!(set0.misses, 5);
+        assert_eq!(set0.compulsory, 2);
+        assert_eq!(set0.conflict, 3);
+        assert_eq!(set0.capacity, 0);
+        assert_eq!(set0.evictions, 4);
+        assert_eq!(cache.stats().sets()[1], CacheCounters::default());
+
+        // Three lines do not fit in a fully-associative cache either
+        for addr in [0x10, 0x30, 0x00, 0x10] {
+            access(&mut cache, addr);
+        }
+        let total = cache.stats().total();
+        assert_eq!(total.compulsory, 4);
+        assert_eq!(total.capacity, 1);
+        assert_eq!(total.conflict, 3);
+
+        cache.reset_stats();
+        assert_eq!(cache.stats().total(), CacheCounters::default());
+
+        // Writes, write-backs, and invalidations
+        cache.write(0x00, &[1], |_| unreachable!());
+        cache.write(0x20, &[1], |_| [0; 16]);
+        cache.write(0x24, &[1], |_| unreachable!());
+        cache.invalidate(0x20);
+        cache.invalidate(0x20);
+        let total = cache.stats().total();
+        assert_eq!(total.writes, 3);
+        assert_eq!(total.hits, 2);
+        // Not compulsory, since the history is kept across a reset
+        assert_eq!(total.capacity, 1);
+        assert_eq!(total.writebacks, 1);
+        assert_eq!(total.invalidations, 1);
+        assert!((total.hit_rate() - 2.0 / 3.0).abs() < 1e-9);
+
+        // Misses are not classified by default
+        let mut cache: SetAssocCache<16, 2, 1, LruPolicy<1>> 
+            = SetAssocCache::new();
+        for addr in [0x00, 0x20, 0x00] {
+            access(&mut cache, addr);
+        }
+        let total = cache.stats().total();
+        assert_eq!(total.misses, 3);
+        assert_eq!(total.compulsory + total.capacity + total.conflict, 0);
+    }
+
+    /// Run the access pattern from [set_associative_random], returning the
+    /// addresses evicted from set zero and the number of hits in the last 
+    /// pass.
+    fn replacement_order<P: ReplacementPolicy<4>>() -> (Vec<usize>, usize) {
+        let mut cache: SetAssocCache<64, 64, 4, P> = SetAssocCache::new();
+        let ranges = [
+            (0x0000_0000..0x0000_4000usize),
+            (0x0000_4000..0x0000_6000usize),
+            (0x0000_0000..0x0000_4000usize),
+        ];
+        let mut evicted = Vec::new();
+        let mut hits = 0;
+        for r in ranges {
+            hits = 0;
+            for addr in r.step_by(64) {
+                if cache.read(addr).is_some() {
+                    hits += 1;
+                } else if let Some(e) = cache.fill(addr, &[0; 64]) {
+                    if e.addr & 0xfff == 0 {
+                        evicted.push(e.addr);
+                    }
+                }
+            }
+        }
+        (evicted, hits)
+    }
+
+    #[test]
+    fn replacement_policies() {
+        // Every line is evicted in the order it was last used
+        assert_eq!(replacement_order::<LruPolicy<4>>(), 
+            (vec![0x0000, 0x1000, 0x2000, 0x3000, 0x4000, 0x5000], 0));
+        // Without any hits, FIFO behaves like LRU
+        assert_eq!(replacement_order::<FifoPolicy<4>>(), 
+            (vec![0x0000, 0x1000, 0x2000, 0x3000, 0x4000, 0x5000], 0));
+        // The second fill is directed away from the half of the set which
+        // contains the first
+        assert_eq!(replacement_order::<TreePlruPolicy<4>>(), 
+            (vec![0x0000, 0x2000, 0x1000, 0x3000, 0x4000, 0x5000], 0));
+        // Filling the last way clears the reference bits for the first 
+        // three, so the line at 0x3000 survives
+        assert_eq!(replacement_order::<NruPolicy<4>>(), 
+            (vec![0x0000, 0x1000, 0x2000, 0x4000, 0x5000], 64));
+    }
+
+    /// Run a random access pattern, returning whether each access hit.
+    fn random_hits(seed: u64) -> Vec<bool> {
+        let mut cache: SetAssocCache<64, 16, 4, RandomPolicy> 
+            = SetAssocCache::with_seed(seed);
+        random_accesses(4096, 0x8000, 1).map(|(addr, _, _)| {
+            let hit = cache.read(addr).is_some();
+            if !hit {
+                cache.fill(addr, &[0; 64]);
+            }
+            hit
+        }).collect()
+    }
+
+    #[test]
+    fn random_seeded() {
+        assert_eq!(random_hits(1), random_hits(1));
+        assert_ne!(random_hits(1), random_hits(2));
+
+        // Every way is eventually chosen, for any associativity
+        let set = [CacheTag::default(); 3];
+        let mut policy = RandomPolicy::new(0);
+        let mut seen = [0; 3];
+        for _ in 0..300 {
+            seen[ReplacementPolicy::<3>::replace(&mut policy, &set)] += 1;
+        }
+        assert!(seen.iter().all(|n| *n > 50), "{:?}", seen);
+
+        // The default seed is fixed
+        let mut a = RandomPolicy::default();
+        let mut b = RandomPolicy::new(RandomPolicy::DEFAULT_SEED);
+        for _ in 0..16 {
+            let set = [CacheTag::default(); 8];
+            assert_eq!(a.replace(&set), b.replace(&set));
+        }
+    }
+
+    #[test]
+    fn replacement_hits() {
+        let mut cache: SetAssocCache<64, 1, 4, LruPolicy<4>> 
+            = SetAssocCache::new();
+        for addr in [0x000, 0x040, 0x080, 0x0c0] {
+            cache.fill(addr, &[0; 64]);
+        }
+        // A hit makes the line most-recently used
+        cache.read(0x000);
+        assert_eq!(cache.fill(0x100, &[0; 64]).unwrap().addr, 0x040);
+        // An invalidated way is reused before any other
+        cache.invalidate(0x0c0);
+        assert_eq!(cache.fill(0x140, &[0; 64]), None);
+        assert_eq!(cache.fill(0x180, &[0; 64]).unwrap().addr, 0x080);
+
+        // With FIFO, hits have no effect
+        let mut cache: SetAssocCache<64, 1, 4, FifoPolicy<4>> 
+            = SetAssocCache::new();
+        for addr in [0x000, 0x040, 0x080, 0x0c0] {
+            cache.fill(addr, &[0; 64]);
+        }
+        cache.read(0x000);
+        assert_eq!(cache.fill(0x100, &[0; 64]).unwrap().addr, 0x000);
+
+        let mut cache: SetAssocCache<64, 1, 8, TreePlruPolicy<8>> 
+            = SetAssocCache::new();
+        for way in 0..8 {
+            cache.fill(way * 0x40, &[0; 64]);
+        }
+        cache.read(0x000);
+        cache.read(0x100);
+        // Both halves of the tree were touched; the victim is the least 
+        // recently filled way in the least recently touched quarter
+        assert_eq!(cache.fill(0x200, &[0; 64]).unwrap().addr, 0x080);
+    }
+
+    #[test]
+    fn geometry_non_power_of_two() {
+        let g = CacheGeometry::new(64, 48, 3).unwrap();
+        for addr in [0x0, 0x40, 0xbff, 0xc00, 0x1234_5678, usize::MAX] {
+            let (set, tag) = (g.set(addr), g.tag(addr));
+            assert!(set < 48);
+            assert_eq!(g.addr(set, tag), g.line_addr(addr), "{:x}", addr);
+        }
+        assert_eq!(g.set(0xbc0), 47);
+        assert_eq!(g.set(0xc00), 0);
+        assert_eq!(g.tag(0xc00), 1);
+        // Equivalent to slicing bits when the number of sets is a power of two
+        let g = CacheGeometry::new(64, 64, 3).unwrap();
+        assert_eq!(g.set(0x1234_5678), (0x1234_5678 >> 6) & 0x3f);
+        assert_eq!(g.tag(0x1234_5678), 0x1234_5678 >> 12);
+
+        assert_eq!(CacheGeometry::new(48, 64, 4), 
+            Err(GeometryError::LineSize(48)));
+        assert_eq!(CacheGeometry::new(64, 0, 4), Err(GeometryError::Sets(0)));
+        assert_eq!(CacheGeometry::new(64, 4, 0), Err(GeometryError::Ways(0)));
+    }
+
+    /// Run random accesses against a cache, checking hits and evictions
+    /// against the set of lines which should be resident.
+    fn check_residency<const NSET: usize, const NWAY: usize, P>(mru: bool)
+        where P: ReplacementPolicy<NWAY>
+    {
+        let mut cache: SetAssocCache<64, NSET, NWAY, P> = SetAssocCache::new();
+        let mut resident = std::collections::HashSet::new();
+        let mut last = None;
+        for (addr, _, _) in random_accesses(0x2000, NSET * NWAY * 3 * 64, 1) {
+            let addr = addr & !63;
+            let hit = cache.read(addr).is_some();
+            assert_eq!(hit, resident.contains(&addr), "{:x}", addr);
+            if hit {
+                last = Some(addr);
+                continue;
+            }
+            if let Some(e) = cache.fill(addr, &[0; 64]) {
+                assert!(resident.remove(&e.addr), "{:x}", e.addr);
+                assert_eq!(e.addr / 64 % NSET, addr / 64 % NSET);
+                // The most recently used line is never the victim
+                if mru && last.is_some_and(|l| l / 64 % NSET == addr / 64 % NSET) {
+                    assert_ne!(Some(e.addr), last);
+                }
+            }
+            resident.insert(addr);
+            last = Some(addr);
+        }
+
+        // A contiguous footprint the size of the cache fits exactly
+        let mut cache: SetAssocCache<64, NSET, NWAY, P> = SetAssocCache::new();
+        for addr in (0..NSET * NWAY * 64).step_by(64) {
+            assert_eq!(cache.fill(addr + 0x10_0000, &[0; 64]), None);
+        }
+    }
+
+    #[test]
+    fn non_power_of_two_ways() {
+        // With three sets, lines 0x000, 0x0c0, 0x180, and 0x240 all map to
+        // set zero
+        let mut cache: SetAssocCache<64, 3, 3, LruPolicy<3>> 
+            = SetAssocCache::new();
+        for addr in [0x000, 0x0c0, 0x180, 0x040, 0x080] {
+            assert_eq!(cache.fill(addr, &[0; 64]), None);
+        }
+        cache.read(0x000);
+        assert_eq!(cache.fill(0x240, &[0; 64]).unwrap().addr, 0x0c0);
+        assert_eq!(cache.fill(0x0c0, &[0; 64]).unwrap().addr, 0x180);
+        assert!(cache.read(0x040).is_some() && cache.read(0x080).is_some());
+
+        check_residency::<4, 3, RandomPolicy>(false);
+        check_residency::<4, 3, LruPolicy<3>>(true);
+        check_residency::<4, 3, TreePlruPolicy<3>>(true);
+        check_residency::<4, 3, FifoPolicy<3>>(false);
+        check_residency::<4, 3, NruPolicy<3>>(true);
+        check_residency::<8, 6, LruPolicy<6>>(true);
+        check_residency::<8, 6, TreePlruPolicy<6>>(true);
+        check_residency::<8, 6, NruPolicy<6>>(true);
+        check_residency::<2, 12, RandomPolicy>(false);
+        check_residency::<2, 12, TreePlruPolicy<12>>(true);
+        check_residency::<2, 12, FifoPolicy<12>>(false);
+        check_residency::<48, 4, LruPolicy<4>>(true);
+        check_residency::<48, 4, TreePlruPolicy<4>>(true);
+        check_residency::<48, 12, RandomPolicy>(false);
+        check_residency::<48, 12, NruPolicy<12>>(true);
+
+        // Tree pseudo-LRU never selects an empty subtree
+        let mut policy = TreePlruPolicy::<3>::default();
+        let set = [CacheTag::default(); 3];
+        let mut victims = Vec::new();
+        for _ in 0..6 {
+            let way = policy.replace(&set);
+            policy.on_fill(way);
+            victims.push(way);
+        }
+        assert_eq!(victims, [0, 2, 1, 2, 0, 2]);
+    }
+
+    #[test]
+    fn write_back_allocate() {
+        // Direct-mapped, so the victim is always the line in the same set
+        let mut cache: SetAssocCache<16, 4, 1, RandomPolicy> 
+            = SetAssocCache::new();
+        let mut fetched = Vec::new();
+        let mut fetch = |addr: usize| { fetched.push(addr); [0xaa; 16] };
+
+        let res = cache.write(0x104, &[1, 2, 3, 4], &mut fetch);
+        assert_eq!(res, WriteResult { hit: false, forward: false, writeback: None });
+        assert!(cache.is_dirty(0x100));
+        let line = cache.read(0x100).unwrap();
+        assert_eq!(line.data[..8], [0xaa, 0xaa, 0xaa, 0xaa, 1, 2, 3, 4]);
+
+        let res = cache.write(0x10f, &[5], |_| unreachable!());
+        assert_eq!(res, WriteResult { hit: true, forward: false, writeback: None });
+
+        // A conflicting write evicts the dirty line
+        let res = cache.write(0x140, &[6], &mut fetch);
+        let mut data = [0xaa; 16];
+        data[4..8].copy_from_slice(&[1, 2, 3, 4]);
+        data[15] = 5;
+        let victim = Eviction { addr: 0x100, dirty: true, line: CacheLine { data } };
+        assert_eq!(res.writeback, Some(victim));
+        assert!(!res.hit);
+        assert!(cache.read(0x100).is_none());
+        assert_eq!(cache.read(0x140).unwrap().data[0], 6);
+
+        // Clean lines are not written back
+        cache.fill(0x210, &[0; 16]);
+        let res = cache.write(0x310, &[7], &mut fetch);
+        assert_eq!(res.writeback, None);
+        assert_eq!(fetched, [0x100, 0x140, 0x310]);
+    }
+
+    #[test]
+    fn write_through_no_allocate() {
+        let mut cache: SetAssocCache<16, 4, 1, RandomPolicy> 
+            = SetAssocCache::new()
+                .with_write_policy(WritePolicy::WriteThrough)
+                .with_allocate_policy(AllocatePolicy::NoWriteAllocate);
+
+        let res = cache.write(0x100, &[1], |_| unreachable!());
+        assert_eq!(res, WriteResult { hit: false, forward: true, writeback: None });
+        assert!(cache.read(0x100).is_none());
+
+        cache.fill(0x100, &[0; 16]);
+        let res = cache.write(0x101, &[1, 2], |_| unreachable!());
+        assert_eq!(res, WriteResult { hit: true, forward: true, writeback: None });
+        assert!(!cache.is_dirty(0x100));
+        assert_eq!(cache.read(0x100).unwrap().data[..3], [0, 1, 2]);
+    }
+
+    #[test]
+    fn fill_evictions() {
+        let mut cache: SetAssocCache<64, 4, 1, RandomPolicy> 
+            = SetAssocCache::new();
+
+        assert_eq!(cache.fill(0x1040, &[1; 64]), None);
+        // Already present
+        assert_eq!(cache.fill(0x1040, &[2; 64]), None);
+        assert_eq!(cache.read(0x1040).unwrap().data, [1; 64]);
+
+        // The victim address is rebuilt from the tag and set index
+        let evicted = cache.fill(0xdead_b040, &[2; 64]).unwrap();
+        assert_eq!(evicted, Eviction { 
+            addr: 0x1040, dirty: false, line: CacheLine { data: [1; 64] } 
+        });
+        cache.write(0xdead_b07f, &[3], |_| unreachable!());
+        let evicted = cache.fill(0x0000_0040, &[0; 64]).unwrap();
+        assert_eq!(evicted.addr, 0xdead_b040);
+        assert!(evicted.dirty);
+        assert_eq!(evicted.line.data[63], 3);
+
+        // Each fill evicts the line which previously mapped to its set
+        for addr in (0x1_0000..0x2_0000usize).step_by(64) {
+            let evicted = cache.fill(addr, &[0; 64]);
+            if addr >= 0x1_0100 {
+                assert_eq!(evicted.unwrap().addr, addr - 0x100);
+            }
+        }
+    }
+
+    #[test]
+    #[should_panic]
+    fn write_across_lines() {
+        let mut cache: SetAssocCache<16, 4, 1, RandomPolicy> 
+            = SetAssocCache::new();
+        cache.write(0x10e, &[0; 4], |_| [0; 16]);
+    }
+
+    #[test]
+    fn atomic_reservation() {
+        let mut cache: SetAssocCache<64, 4, 1, RandomPolicy> 
+            = SetAssocCache::new();
+        let data = [0u8; 64];
+
+        cache.fill(0x1000, &data);
+        cache.observe_atomic(0x1008, AtomicKind::LoadReserved);
+        assert!(cache.is_reserved(0x1000));
+        assert!(cache.is_reserved(0x103f));
+        assert!(!cache.is_reserved(0x1040));
+
+        cache.observe_atomic(0x1008, 
+            AtomicKind::StoreConditional { success: true });
+        assert!(!cache.is_reserved(0x1000));
+
+        // Evicting the reserved line releases the reservation
+        cache.observe_atomic(0x1008, AtomicKind::LoadReserved);
+        cache.fill(0x2040, &data);
+        assert!(cache.is_reserved(0x1000));
+        cache.fill(0x1100, &data);
+        assert!(!cache.is_reserved(0x1000));
+
+        // Invalidating the reserved line releases the reservation
+        cache.observe_atomic(0x1100, AtomicKind::LoadReserved);
+        cache.invalidate(0x1100);
+        assert!(!cache.is_reserved(0x1100));
     }
 }
 
@@ -0,0 +1,278 @@
+//! A set-associative cache whose geometry is chosen at runtime.
+//!
+//! Unlike [SetAssocCache], the geometry is not part of the type, so large
+//! caches can be modeled without recompiling for each configuration.
+
+use crate::memory::{AtomicKind, AtomicObserver};
+use crate::memory::cache::*;
+use crate::memory::cache::stats::CacheStats;
+
+/// Replacement policies which can be selected at runtime.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PolicyKind {
+    /// See [RandomPolicy].
+    Random,
+    /// See [LruPolicy].
+    Lru,
+    /// See [TreePlruPolicy].
+    TreePlru,
+    /// See [FifoPolicy].
+    Fifo,
+    /// See [NruPolicy].
+    Nru,
+}
+impl PolicyKind {
+    /// Returns true if this policy supports sets with 'ways' ways.
+    fn supports(&self, ways: usize) -> bool {
+        match self {
+            Self::TreePlru | Self::Nru => ways <= 64,
+            _ => true,
+        }
+    }
+
+    /// Create the replacement state for a set with 'ways' ways.
+    fn build(&self, ways: usize, seed: u64) -> Box<dyn SetPolicy> {
+        match self {
+            Self::Random => Box::new(RandomPolicy::new(seed)),
+            Self::Lru => Box::new(LruState(Stamps::new(vec![0; ways]))),
+            Self::TreePlru => Box::new(TreePlruState::default()),
+            Self::Fifo => Box::new(FifoState(Stamps::new(vec![0; ways]))),
+            Self::Nru => Box::new(NruState::default()),
+        }
+    }
+}
+
+/// A model of a set-associative cache with a geometry and replacement
+/// policy chosen at runtime.
+///
+/// This behaves like [SetAssocCache].
+pub struct DynCache {
+    kind: PolicyKind,
+    pub(super) core: CacheCore<Box<dyn SetPolicy>>,
+}
+impl DynCache {
+    /// Create a cache, checking that the geometry is supported by the 
+    /// replacement policy.
+    pub fn new(geometry: CacheGeometry, kind: PolicyKind)
+        -> Result<Self, GeometryError>
+    {
+        if !kind.supports(geometry.ways) {
+            return Err(GeometryError::Ways(geometry.ways));
+        }
+        let res = Self { kind, core: CacheCore::new(geometry, Vec::new()) };
+        Ok(res.with_seed(RandomPolicy::DEFAULT_SEED))
+    }
+
+    /// Seed the replacement policy for each set from a single seed.
+    /// Caches created with the same seed behave identically.
+    pub fn with_seed(mut self, seed: u64) -> Self {
+        let (kind, g) = (self.kind, self.core.geometry);
+        let mut state = seed;
+        self.core.policy = (0..g.sets).map(|_| {
+            kind.build(g.ways, splitmix64(&mut state))
+        }).collect();
+        self
+    }
+
+    /// Use the given policy for writes which hit in the cache.
+    pub fn with_write_policy(mut self, policy: WritePolicy) -> Self {
+        self.core.write_policy = policy;
+        self
+    }
+
+    /// Use the given policy for writes which miss in the cache.
+    pub fn with_allocate_policy(mut self, policy: AllocatePolicy) -> Self {
+        self.core.allocate_policy = policy;
+        self
+    }
+
+    /// Classify each miss in the statistics for this cache (see
+    /// [CacheStats::with_miss_classification]).
+    pub fn with_miss_classification(mut self) -> Self {
+        self.core = self.core.with_miss_classification();
+        self
+    }
+
+    pub fn geometry(&self) -> CacheGeometry {
+        self.core.geometry
+    }
+
+    /// Statistics collected since the cache was created, or since the last
+    /// call to [DynCache::reset_stats].
+    pub fn stats(&self) -> &CacheStats {
+        &self.core.stats
+    }
+
+    /// Reset the statistics for this cache.
+    pub fn reset_stats(&mut self) {
+        self.core.stats.reset();
+    }
+
+    /// See [SetAssocCache::is_dirty].
+    pub fn is_dirty(&self, addr: usize) -> bool {
+        self.core.is_dirty(addr)
+    }
+
+    /// See [SetAssocCache::is_reserved].
+    pub fn is_reserved(&self, addr: usize) -> bool {
+        self.core.is_reserved(addr)
+    }
+
+    /// See [SetAssocCache::invalidate].
+    pub fn invalidate(&mut self, addr: usize) {
+        self.core.invalidate(addr)
+    }
+
+    /// See [SetAssocCache::invalidate_all].
+    pub fn invalidate_all(&mut self) {
+        self.core.invalidate_all()
+    }
+
+    /// See [SetAssocCache::clean].
+    pub fn clean(&mut self) -> Vec<Eviction<Vec<u8>>> {
+        self.core.clean()
+    }
+
+    /// See [SetAssocCache::read].
+    pub fn read(&mut self, addr: usize) -> Option<&[u8]> {
+        let idx = self.core.read(addr)?;
+        Some(self.core.line(idx))
+    }
+
+    /// See [SetAssocCache::fill]. The length of 'data' must be the line 
+    /// size.
+    pub fn fill(&mut self, addr: usize, data: &[u8])
+        -> Option<Eviction<Vec<u8>>>
+    {
+        self.core.fill(addr, data)
+    }
+
+    /// See [SetAssocCache::write]. Here, `fetch` is called with the 
+    /// address of the line and a buffer to fill with its contents.
+    pub fn write<F>(&mut self, addr: usize, data: &[u8], fetch: F)
+        -> WriteResult<Vec<u8>> where F: FnOnce(usize, &mut [u8])
+    {
+        self.core.write(addr, data, fetch)
+    }
+}
+
+impl AtomicObserver for DynCache {
+    fn observe_atomic(&mut self, addr: usize, kind: AtomicKind) {
+        self.core.observe_atomic(addr, kind)
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use crate::memory::cache::*;
+    use crate::memory::cache::dynamic::*;
+    use crate::memory::cache::test::random_accesses;
+
+    #[test]
+    fn dyn_geometry_errors() {
+        let g = CacheGeometry::new(64, 64, 128).unwrap();
+        assert_eq!(g.capacity(), 0x8_0000);
+        assert!(DynCache::new(g, PolicyKind::Lru).is_ok());
+        assert!(matches!(DynCache::new(g, PolicyKind::TreePlru),
+            Err(GeometryError::Ways(128))));
+        let g = CacheGeometry::new(64, 1, 64).unwrap();
+        assert!(DynCache::new(g, PolicyKind::Nru).is_ok());
+        let g = CacheGeometry::new(64, 1, 65).unwrap();
+        assert!(matches!(DynCache::new(g, PolicyKind::Nru),
+            Err(GeometryError::Ways(65))));
+        // Invalid geometries cannot be constructed at all
+        assert_eq!(CacheGeometry::new(48, 3, 1),
+            Err(GeometryError::LineSize(48)));
+        assert_eq!(GeometryError::LineSize(48).to_string(),
+            "line size must be a power of two (got 48)");
+    }
+
+    /// Run the same accesses on a [SetAssocCache] and a [DynCache] with the
+    /// same geometry, checking that they behave identically.
+    fn compare_with<const NSET: usize, const NWAY: usize, P>(kind: PolicyKind)
+        where P: ReplacementPolicy<NWAY>
+    {
+        let mut a: SetAssocCache<32, NSET, NWAY, P> 
+            = SetAssocCache::with_seed(7);
+        let g = CacheGeometry::new(32, NSET, NWAY).unwrap();
+        let mut b = DynCache::new(g, kind).unwrap().with_seed(7);
+        let accesses = random_accesses(0x4000, 0x4000, 1);
+        for (i, (addr, _, write)) in accesses.enumerate() {
+            if write {
+                let ra = a.write(addr & !3, &[i as u8; 4], |_| [1; 32]);
+                let rb = b.write(addr & !3, &[i as u8; 4], |_, d| d.fill(1));
+                assert_eq!((ra.hit, ra.forward), (rb.hit, rb.forward));
+                assert_eq!(ra.writeback.map(|e| (e.addr, e.line.data.to_vec())),
+                    rb.writeback.map(|e| (e.addr, e.line)));
+            } else {
+                let ra = a.read(addr).map(|l| l.data.to_vec());
+                let rb = b.read(addr).map(|l| l.to_vec());
+                assert_eq!(ra, rb);
+                if ra.is_none() {
+                    let ea = a.fill(addr, &[2; 32]);
+                    let eb = b.fill(addr, &[2; 32]);
+                    assert_eq!(ea.map(|e| e.addr), eb.map(|e| e.addr));
+                }
+            }
+        }
+        assert_eq!(a.stats().total(), b.stats().total());
+    }
+
+    #[test]
+    fn dyn_policies() {
+        // Fill every way of a single set, then hit in the first way
+        let victim = |kind| {
+            let g = CacheGeometry::new(64, 1, 4).unwrap();
+            let mut cache = DynCache::new(g, kind).unwrap();
+            for addr in [0x000, 0x040, 0x080, 0x0c0] {
+                assert_eq!(cache.fill(addr, &[0; 64]), None);
+            }
+            assert!(cache.read(0x000).is_some());
+            cache.fill(0x100, &[0; 64]).unwrap().addr
+        };
+        assert_eq!(victim(PolicyKind::Lru), 0x040);
+        assert_eq!(victim(PolicyKind::Fifo), 0x000);
+        assert_eq!(victim(PolicyKind::TreePlru), 0x080);
+        assert_eq!(victim(PolicyKind::Nru), 0x040);
+        assert_ne!(victim(PolicyKind::Random), 0x100);
+    }
+
+    #[test]
+    fn dyn_matches_static() {
+        compare_with::<16, 4, RandomPolicy>(PolicyKind::Random);
+        compare_with::<16, 4, LruPolicy<4>>(PolicyKind::Lru);
+        compare_with::<16, 4, TreePlruPolicy<4>>(PolicyKind::TreePlru);
+        compare_with::<16, 4, FifoPolicy<4>>(PolicyKind::Fifo);
+        compare_with::<16, 4, NruPolicy<4>>(PolicyKind::Nru);
+    }
+
+    #[test]
+    fn dyn_non_power_of_two() {
+        compare_with::<48, 3, LruPolicy<3>>(PolicyKind::Lru);
+        compare_with::<48, 6, TreePlruPolicy<6>>(PolicyKind::TreePlru);
+        compare_with::<12, 12, NruPolicy<12>>(PolicyKind::Nru);
+        compare_with::<3, 12, RandomPolicy>(PolicyKind::Random);
+    }
+
+    #[test]
+    fn dyn_large_cache() {
+        // 8 MiB, which would not fit on the stack
+        let g = CacheGeometry::new(128, 0x1000, 16).unwrap();
+        let mut cache = DynCache::new(g, PolicyKind::Lru).unwrap()
+            .with_write_policy(WritePolicy::WriteThrough);
+        for addr in (0..g.capacity()).step_by(g.line_bytes()) {
+            assert_eq!(cache.fill(addr, &[addr as u8; 128]), None);
+        }
+        let res = cache.write(0x1_0004, &[0xff; 4], |_, _| unreachable!());
+        assert!(res.hit && res.forward);
+        assert!(!cache.is_dirty(0x1_0000));
+        assert_eq!(cache.read(0x1_0000).unwrap()[..8], 
+            [0, 0, 0, 0, 0xff, 0xff, 0xff, 0xff]);
+        let evicted = cache.fill(g.capacity(), &[0; 128]).unwrap();
+        assert_eq!(evicted.addr, 0);
+        cache.invalidate(0x80);
+        assert!(cache.read(0x80).is_none());
+        assert_eq!(cache.stats().total().invalidations, 1);
+    }
+}
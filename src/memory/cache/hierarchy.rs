@@ -0,0 +1,954 @@
+//! A hierarchy of caches in front of a memory.
+//!
+//! A [CacheHierarchy] chains multiple levels of cache (ie. L1, L2, and a
+//! last-level cache) in front of some [Bus], and handles misses, fills,
+//! and write-backs between levels.
+
+use crate::memory::*;
+use crate::memory::cache::*;
+use crate::memory::cache::dynamic::DynCache;
+use crate::memory::cache::stats::CacheStats;
+
+/// Interface to a cache which can be used as a level in a
+/// [CacheHierarchy].
+///
+/// Lines are passed around as byte slices, so that levels with different
+/// line sizes (and different kinds of cache) can be chained together.
+pub trait CacheLevel {
+    /// The size of each line (in bytes).
+    fn line_bytes(&self) -> usize;
+
+    /// Statistics collected by this cache.
+    fn stats(&self) -> &CacheStats;
+
+    /// Reset the statistics for this cache.
+    fn reset_stats(&mut self);
+
+    /// Copy [part of] a single line into 'dst'. Returns false if the line
+    /// is not present in the cache.
+    fn read_line(&mut self, addr: usize, dst: &mut [u8]) -> bool;
+
+    /// Fill a line with data from the next level, returning the line
+    /// which was replaced, if any.
+    fn fill_line(&mut self, addr: usize, data: &[u8])
+        -> Option<Eviction<Vec<u8>>>;
+
+    /// Write to [part of] a single line. When the line must be allocated,
+    /// `fetch` is called to read its contents from the next level.
+    fn write_line(&mut self, addr: usize, data: &[u8],
+        fetch: &mut dyn FnMut(usize, &mut [u8])) -> WriteResult<Vec<u8>>;
+
+    /// Put back a line which was replaced when allocating the line for
+    /// 'addr', dropping the line for 'addr'. This is used when the
+    /// replaced line cannot be written back.
+    fn restore_line(&mut self, addr: usize, victim: &Eviction<Vec<u8>>);
+
+    /// Invalidate the line containing the provided address.
+    fn invalidate(&mut self, addr: usize);
+
+    /// Invalidate every line in the cache.
+    fn invalidate_all(&mut self);
+
+    /// Mark every modified line as clean, returning the lines which must
+    /// be written back to the next level.
+    fn clean(&mut self) -> Vec<Eviction<Vec<u8>>>;
+
+    /// Observe an atomic access to the provided address. Caches which do
+    /// not model reservations ignore this.
+    fn observe_atomic(&mut self, _addr: usize, _kind: AtomicKind) {}
+
+    /// Returns true if the line containing the provided address is held
+    /// by a reservation.
+    fn is_reserved(&self, _addr: usize) -> bool {
+        false
+    }
+}
+
+/// Copy [part of] a single line into 'dst', returning false if the line
+/// is not present in the cache.
+fn read_line<P: SetPolicy>(core: &mut CacheCore<P>, addr: usize,
+    dst: &mut [u8]) -> bool
+{
+    let off = core.geometry.offset(addr);
+    match core.read(addr) {
+        Some(idx) => {
+            dst.copy_from_slice(&core.line(idx)[off..(off + dst.len())]);
+            true
+        },
+        None => false,
+    }
+}
+
+impl <const NBYTES: usize, const NSET: usize, const NWAY: usize, P>
+    CacheLevel for SetAssocCache<NBYTES, NSET, NWAY, P>
+    where P: ReplacementPolicy<NWAY>
+{
+    fn line_bytes(&self) -> usize {
+        NBYTES
+    }
+    fn stats(&self) -> &CacheStats {
+        &self.core.stats
+    }
+    fn reset_stats(&mut self) {
+        self.core.stats.reset()
+    }
+    fn read_line(&mut self, addr: usize, dst: &mut [u8]) -> bool {
+        read_line(&mut self.core, addr, dst)
+    }
+    fn fill_line(&mut self, addr: usize, data: &[u8])
+        -> Option<Eviction<Vec<u8>>>
+    {
+        self.core.fill(addr, data)
+    }
+    fn write_line(&mut self, addr: usize, data: &[u8],
+        fetch: &mut dyn FnMut(usize, &mut [u8])) -> WriteResult<Vec<u8>>
+    {
+        self.core.write(addr, data, fetch)
+    }
+    fn restore_line(&mut self, addr: usize, victim: &Eviction<Vec<u8>>) {
+        self.core.restore(addr, victim)
+    }
+    fn invalidate(&mut self, addr: usize) {
+        self.core.invalidate(addr)
+    }
+    fn invalidate_all(&mut self) {
+        self.core.invalidate_all()
+    }
+    fn clean(&mut self) -> Vec<Eviction<Vec<u8>>> {
+        self.core.clean()
+    }
+    fn observe_atomic(&mut self, addr: usize, kind: AtomicKind) {
+        self.core.observe_atomic(addr, kind)
+    }
+    fn is_reserved(&self, addr: usize) -> bool {
+        self.core.is_reserved(addr)
+    }
+}
+
+impl CacheLevel for DynCache {
+    fn line_bytes(&self) -> usize {
+        self.core.geometry.line_bytes()
+    }
+    fn stats(&self) -> &CacheStats {
+        &self.core.stats
+    }
+    fn reset_stats(&mut self) {
+        self.core.stats.reset()
+    }
+    fn read_line(&mut self, addr: usize, dst: &mut [u8]) -> bool {
+        read_line(&mut self.core, addr, dst)
+    }
+    fn fill_line(&mut self, addr: usize, data: &[u8])
+        -> Option<Eviction<Vec<u8>>>
+    {
+        self.core.fill(addr, data)
+    }
+    fn write_line(&mut self, addr: usize, data: &[u8],
+        fetch: &mut dyn FnMut(usize, &mut [u8])) -> WriteResult<Vec<u8>>
+    {
+        self.core.write(addr, data, fetch)
+    }
+    fn restore_line(&mut self, addr: usize, victim: &Eviction<Vec<u8>>) {
+        self.core.restore(addr, victim)
+    }
+    fn invalidate(&mut self, addr: usize) {
+        self.core.invalidate(addr)
+    }
+    fn invalidate_all(&mut self) {
+        self.core.invalidate_all()
+    }
+    fn clean(&mut self) -> Vec<Eviction<Vec<u8>>> {
+        self.core.clean()
+    }
+    fn observe_atomic(&mut self, addr: usize, kind: AtomicKind) {
+        self.core.observe_atomic(addr, kind)
+    }
+    fn is_reserved(&self, addr: usize) -> bool {
+        self.core.is_reserved(addr)
+    }
+}
+
+/// A single cache in a [CacheHierarchy].
+pub struct Level {
+    /// A name used to identify this level (ie. "L2").
+    pub name: String,
+    cache: Box<dyn CacheLevel>,
+}
+impl Level {
+    pub fn cache(&self) -> &dyn CacheLevel {
+        self.cache.as_ref()
+    }
+
+    /// Statistics collected by the cache at this level.
+    pub fn stats(&self) -> &CacheStats {
+        self.cache.stats()
+    }
+}
+
+/// Split an access into pieces which do not cross a line boundary,
+/// yielding the address and the range of the access covered by each
+/// piece.
+fn lines(addr: usize, len: usize, line_bytes: usize)
+    -> impl Iterator<Item = (usize, std::ops::Range<usize>)>
+{
+    let mut pos = 0;
+    std::iter::from_fn(move || {
+        if pos == len {
+            return None;
+        }
+        let addr = addr + pos;
+        let n = (line_bytes - (addr & (line_bytes - 1))).min(len - pos);
+        let res = (addr, pos..(pos + n));
+        pos += n;
+        Some(res)
+    })
+}
+
+/// Read from the first of 'levels', handling misses with the remaining
+/// levels and (finally) the memory.
+///
+/// When a line cannot be filled (ie. because it runs past the end of the
+/// memory), the access is sent to the remaining levels without allocating
+/// the line.
+fn read_from(levels: &mut [Level], mem: &mut impl Bus, addr: usize,
+    dst: &mut [u8]) -> Result<(), AccessFault>
+{
+    let (first, rest) = match levels.split_first_mut() {
+        Some(res) => res,
+        None => return mem.read(addr, dst),
+    };
+    let n = first.cache.line_bytes();
+    for (addr, range) in lines(addr, dst.len(), n) {
+        let dst = &mut dst[range];
+        if first.cache.read_line(addr, dst) {
+            continue;
+        }
+        let line_addr = addr & !(n - 1);
+        let mut line = vec![0; n];
+        if read_from(rest, mem, line_addr, &mut line).is_err() {
+            read_from(rest, mem, addr, dst)?;
+            continue;
+        }
+        if let Some(e) = first.cache.fill_line(line_addr, &line) {
+            if e.dirty {
+                // Never drop a line which could not be written back
+                if let Err(f) = write_to(rest, mem, e.addr, &e.line) {
+                    first.cache.restore_line(line_addr, &e);
+                    return Err(f);
+                }
+            }
+        }
+        let off = addr - line_addr;
+        dst.copy_from_slice(&line[off..(off + dst.len())]);
+    }
+    Ok(())
+}
+
+/// Write to the first of 'levels', passing write-backs and writes which
+/// are forwarded by each level down to the remaining levels and (finally)
+/// the memory.
+///
+/// As with [read_from], lines which cannot be filled are not allocated.
+fn write_to(levels: &mut [Level], mem: &mut impl Bus, addr: usize,
+    src: &[u8]) -> Result<(), AccessFault>
+{
+    let (first, rest) = match levels.split_first_mut() {
+        Some(res) => res,
+        None => return mem.write(addr, src),
+    };
+    for (addr, range) in lines(addr, src.len(), first.cache.line_bytes()) {
+        let src = &src[range];
+        let mut fault = false;
+        let res = first.cache.write_line(addr, src, &mut |line_addr, line| {
+            fault = read_from(rest, mem, line_addr, line).is_err();
+        });
+        if let Some(e) = res.writeback {
+            if let Err(f) = write_to(rest, mem, e.addr, &e.line) {
+                first.cache.restore_line(addr, &e);
+                return Err(f);
+            }
+        }
+        // Don't keep a line which could not be fetched
+        if fault {
+            first.cache.invalidate(addr);
+            write_to(rest, mem, addr, src)?;
+            continue;
+        }
+        if res.forward {
+            write_to(rest, mem, addr, src)?;
+        }
+    }
+    Ok(())
+}
+
+/// How accesses to a range of addresses are handled by a
+/// [CacheHierarchy].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RegionPolicy {
+    /// Reads and writes bypass every cache (ie. for memory-mapped devices).
+    Uncached,
+    /// Reads are cached, but writes bypass every cache (ie. for ROM), so
+    /// that a write which faults is reported immediately. Cached copies
+    /// of the written lines are invalidated.
+    ReadOnly,
+}
+
+/// Write back every modified line in the first of 'levels' to the
+/// remaining levels. Lines which cannot be written back are kept, and the
+/// first fault is returned.
+fn clean(levels: &mut [Level], mem: &mut impl Bus) -> Result<(), AccessFault> {
+    let (first, rest) = match levels.split_first_mut() {
+        Some(res) => res,
+        None => return Ok(()),
+    };
+    let mut res = Ok(());
+    for e in first.cache.clean() {
+        if let Err(f) = write_to(rest, mem, e.addr, &e.line) {
+            first.cache.restore_line(e.addr, &e);
+            res = res.and(Err(f));
+        }
+    }
+    res
+}
+
+/// A hierarchy of caches in front of a memory.
+///
+/// Accesses are sent to the first level, and misses are handled by each
+/// following level until they reach the memory. Each level may have a
+/// different line size, and any cache implementing [CacheLevel] can be
+/// used as a level. Levels are non-inclusive: lines evicted from one
+/// level are not invalidated in the levels above it.
+///
+/// Lines which cannot be filled from the level below (ie. lines which run
+/// past the end of the memory) are not cached at that level. Faults are
+/// always reported with the address, length, and kind of the original 
+/// access.
+///
+/// By default, every address is cached. Memory-mapped devices and
+/// read-only memories must be described with [CacheHierarchy::with_region]
+/// so that accesses to them are not served from (or held in) a cache.
+///
+/// An optional instruction cache is used (in place of the first level)
+/// for instruction fetches. Instruction fetches are not coherent with
+/// writes: after modifying instructions, [CacheHierarchy::sync_icache]
+/// must be called (as with 'fence.i').
+pub struct CacheHierarchy<B: Bus> {
+    /// An instruction cache which is used in place of the first level.
+    icache: Option<Level>,
+    /// Levels ordered from the processor towards the memory.
+    levels: Vec<Level>,
+    /// Ranges of addresses which are not cached normally.
+    regions: Vec<(std::ops::Range<usize>, RegionPolicy)>,
+    mem: B,
+}
+impl <B: Bus> CacheHierarchy<B> {
+    /// Create a hierarchy with no caches in front of the provided memory.
+    pub fn new(mem: B) -> Self {
+        Self { icache: None, levels: Vec::new(), regions: Vec::new(), mem }
+    }
+
+    /// Handle accesses to a range of addresses according to 'policy'.
+    ///
+    /// Lines are cached as a whole, so the range should be aligned to the
+    /// largest line size in the hierarchy.
+    pub fn with_region(mut self, range: std::ops::Range<usize>,
+        policy: RegionPolicy) -> Self
+    {
+        self.regions.push((range, policy));
+        self
+    }
+
+    /// Returns the policy for an access which overlaps any of the regions
+    /// given with [CacheHierarchy::with_region]. 
+    fn region(&self, addr: usize, len: usize) -> Option<RegionPolicy> {
+        let end = addr.saturating_add(len);
+        let mut res = None;
+        for (range, policy) in self.regions.iter() {
+            if range.start < end && addr < range.end {
+                if *policy == RegionPolicy::Uncached {
+                    return Some(*policy);
+                }
+                res = Some(*policy);
+            }
+        }
+        res
+    }
+
+    /// Add a level below the existing levels.
+    pub fn with_level(mut self, name: &str, cache: impl CacheLevel + 'static)
+        -> Self
+    {
+        let name = name.to_string();
+        self.levels.push(Level { name, cache: Box::new(cache) });
+        self
+    }
+
+    /// Use a separate instruction cache for instruction fetches. Misses
+    /// are handled by the second level.
+    pub fn with_icache(mut self, name: &str,
+        cache: impl CacheLevel + 'static) -> Self
+    {
+        let name = name.to_string();
+        self.icache = Some(Level { name, cache: Box::new(cache) });
+        self
+    }
+
+    /// Returns the levels, ordered from the processor towards the memory.
+    pub fn levels(&self) -> &[Level] {
+        &self.levels
+    }
+
+    /// Returns the instruction cache, if any.
+    pub fn icache(&self) -> Option<&Level> {
+        self.icache.as_ref()
+    }
+
+    /// Returns the level (or instruction cache) with the provided name.
+    pub fn level(&self, name: &str) -> Option<&Level> {
+        self.icache.iter().chain(self.levels.iter()).find(|l| l.name == name)
+    }
+
+    /// Reset the statistics for every level.
+    pub fn reset_stats(&mut self) {
+        for level in self.icache.iter_mut().chain(self.levels.iter_mut()) {
+            level.cache.reset_stats();
+        }
+    }
+
+    pub fn mem(&self) -> &B {
+        &self.mem
+    }
+
+    /// Access the memory directly, bypassing all caches.
+    pub fn mem_mut(&mut self) -> &mut B {
+        &mut self.mem
+    }
+
+    /// Write modified lines in the first level back to the second level,
+    /// and invalidate the instruction cache, so that following
+    /// instruction fetches observe all previous writes.
+    pub fn sync_icache(&mut self) -> Result<(), AccessFault> {
+        let icache = match &mut self.icache {
+            Some(icache) => icache,
+            None => return Ok(()),
+        };
+        icache.cache.invalidate_all();
+        clean(&mut self.levels, &mut self.mem)
+    }
+
+    /// Write all modified lines in every level back to memory.
+    pub fn flush(&mut self) -> Result<(), AccessFault> {
+        for idx in 0..self.levels.len() {
+            clean(&mut self.levels[idx..], &mut self.mem)?;
+        }
+        Ok(())
+    }
+}
+impl <B: Bus> Bus for CacheHierarchy<B> {
+    fn read(&mut self, addr: usize, dst: &mut [u8]) -> Result<(), AccessFault> {
+        let fault = AccessFault::new(addr, dst.len(), AccessKind::Read);
+        if self.region(addr, dst.len()) == Some(RegionPolicy::Uncached) {
+            return self.mem.read(addr, dst).map_err(|_| fault);
+        }
+        read_from(&mut self.levels, &mut self.mem, addr, dst)
+            .map_err(|_| fault)
+    }
+    fn write(&mut self, addr: usize, src: &[u8]) -> Result<(), AccessFault> {
+        let fault = AccessFault::new(addr, src.len(), AccessKind::Write);
+        match self.region(addr, src.len()) {
+            Some(RegionPolicy::Uncached) => {
+                self.mem.write(addr, src).map_err(|_| fault)
+            },
+            Some(RegionPolicy::ReadOnly) => {
+                let levels = self.icache.iter_mut().chain(self.levels.iter_mut());
+                for level in levels {
+                    let n = level.cache.line_bytes();
+                    for (addr, _) in lines(addr, src.len(), n) {
+                        level.cache.invalidate(addr);
+                    }
+                }
+                self.mem.write(addr, src).map_err(|_| fault)
+            },
+            None => {
+                write_to(&mut self.levels, &mut self.mem, addr, src)
+                    .map_err(|_| fault)
+            },
+        }
+    }
+    fn endian(&self) -> Endian {
+        self.mem.endian()
+    }
+    /// Instruction fetches go through the instruction cache (if any), and
+    /// misses are handled by the second level.
+    fn fetch(&mut self, addr: usize, dst: &mut [u8]) -> Result<(), AccessFault> {
+        let fault = AccessFault::new(addr, dst.len(), AccessKind::Read);
+        if self.region(addr, dst.len()) == Some(RegionPolicy::Uncached) {
+            return self.mem.fetch(addr, dst).map_err(|_| fault);
+        }
+        let icache = match &mut self.icache {
+            Some(icache) => icache,
+            None => {
+                return read_from(&mut self.levels, &mut self.mem, addr, dst)
+                    .map_err(|_| fault)
+            },
+        };
+        let (first, rest) = (&mut icache.cache,
+            self.levels.get_mut(1..).unwrap_or_default());
+        let n = first.line_bytes();
+        for (addr, range) in lines(addr, dst.len(), n) {
+            let dst = &mut dst[range];
+            if first.read_line(addr, dst) {
+                continue;
+            }
+            // The instruction cache is never modified, so lines which are
+            // replaced don't need to be written back
+            let line_addr = addr & !(n - 1);
+            let mut line = vec![0; n];
+            if read_from(rest, &mut self.mem, line_addr, &mut line).is_err() {
+                read_from(rest, &mut self.mem, addr, dst)
+                    .map_err(|_| fault)?;
+                continue;
+            }
+            first.fill_line(line_addr, &line);
+            let off = addr - line_addr;
+            dst.copy_from_slice(&line[off..(off + dst.len())]);
+        }
+        Ok(())
+    }
+    fn observe_atomic(&mut self, addr: usize, kind: AtomicKind) {
+        for level in self.levels.iter_mut() {
+            level.cache.observe_atomic(addr, kind);
+        }
+        self.mem.observe_atomic(addr, kind);
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use crate::memory::*;
+    use crate::memory::cache::*;
+    use crate::memory::cache::dynamic::*;
+    use crate::memory::cache::hierarchy::*;
+    use crate::memory::cache::test::random_accesses;
+    use crate::memory::sparse::SparseRAM;
+    use crate::memory::system::SystemBus;
+
+    /// Run random accesses (which may span multiple lines) against a
+    /// hierarchy, checking the results against a flat copy of memory.
+    fn check_model<B: Bus>(h: &mut CacheHierarchy<B>, model: &mut [u8]) {
+        let accesses = random_accesses(0x4000, model.len(), 200);
+        for (i, (addr, len, write)) in accesses.enumerate() {
+            if write {
+                let src: Vec<u8> = (0..len).map(|j| (i + j) as u8).collect();
+                h.write(addr, &src).unwrap();
+                model[addr..(addr + len)].copy_from_slice(&src);
+            } else {
+                let mut dst = vec![0; len];
+                h.read(addr, &mut dst).unwrap();
+                assert_eq!(dst, &model[addr..(addr + len)], "{:x}", addr);
+            }
+        }
+    }
+
+    #[test]
+    fn hierarchy_read_write() {
+        let mut ram = NaiveRAM::<0x4_0000>::new();
+        let mut model: Vec<u8> = (0..0x4_0000).map(|i| (i >> 4) as u8)
+            .collect();
+        ram.write_bytes(0, &model);
+
+        let g2 = CacheGeometry::new(64, 64, 4).unwrap();
+        let g3 = CacheGeometry::new(128, 48, 12).unwrap();
+        let mut h = CacheHierarchy::new(ram)
+            .with_level("L1D",
+                SetAssocCache::<32, 16, 2, LruPolicy<2>>::new())
+            .with_level("L2", DynCache::new(g2, PolicyKind::TreePlru).unwrap())
+            .with_level("LLC", DynCache::new(g3, PolicyKind::Random).unwrap());
+        check_model(&mut h, &mut model);
+
+        // Each level is only accessed on misses and write-backs from the
+        // level above it, which fit within a single line
+        let names: Vec<&str> = h.levels().iter().map(|l| l.name.as_str())
+            .collect();
+        assert_eq!(names, ["L1D", "L2", "LLC"]);
+        for pair in h.levels().windows(2) {
+            let (upper, lower) = (pair[0].stats().total(),
+                pair[1].stats().total());
+            assert!(upper.misses > 0 && upper.writebacks > 0);
+            assert_eq!(lower.reads, upper.fills);
+            assert_eq!(lower.writes, upper.writebacks);
+        }
+        assert_eq!(h.level("LLC").unwrap().stats().sets().len(), 48);
+
+        // Memory is stale until modified lines are written back
+        let mut buf = vec![0; model.len()];
+        h.mem().read_bytes(0, &mut buf);
+        assert_ne!(buf, model);
+        h.flush().unwrap();
+        h.mem().read_bytes(0, &mut buf);
+        assert_eq!(buf, model);
+    }
+
+    #[test]
+    fn hierarchy_writeback_order() {
+        // A single line in the first level, and two sets of one line in 
+        // the second level
+        let mut h = CacheHierarchy::new(NaiveRAM::<0x1000>::new())
+            .with_level("L1D", SetAssocCache::<32, 1, 1, LruPolicy<1>>::new())
+            .with_level("L2", SetAssocCache::<32, 2, 1, LruPolicy<1>>::new());
+        h.write_u32(0x000, 1).unwrap();
+
+        // Evicting the modified line from the first level only writes it
+        // back to the second level
+        assert_eq!(h.read_u32(0x020), Ok(0));
+        assert_eq!(h.level("L1D").unwrap().stats().total().writebacks, 1);
+        let l2 = h.level("L2").unwrap().stats().total();
+        assert_eq!((l2.writes, l2.hits), (1, 1));
+        assert_eq!(h.mem_mut().read_u32(0x000), Ok(0));
+
+        // Replacing it in the second level writes it back to memory before
+        // the first level is refilled
+        assert_eq!(h.read_u32(0x040), Ok(0));
+        assert_eq!(h.level("L2").unwrap().stats().total().writebacks, 1);
+        assert_eq!(h.mem_mut().read_u32(0x000), Ok(1));
+        assert_eq!(h.read_u32(0x000), Ok(1));
+
+        // A line written back to a level which no longer holds it is 
+        // allocated there again
+        h.write_u32(0x000, 2).unwrap();
+        h.read_u32(0x040).unwrap();
+        h.read_u32(0x060).unwrap();
+        assert_eq!(h.level("L2").unwrap().stats().total().writebacks, 1);
+        h.flush().unwrap();
+        assert_eq!(h.mem_mut().read_u32(0x000), Ok(2));
+    }
+
+    #[test]
+    fn hierarchy_line_sizes() {
+        // A line in the first level is filled with four lines from the
+        // second level, and written back as four lines
+        let g1 = CacheGeometry::new(128, 8, 2).unwrap();
+        let g2 = CacheGeometry::new(32, 32, 6).unwrap();
+        let mut h = CacheHierarchy::new(NaiveRAM::<0x2000>::new())
+            .with_level("L1D", DynCache::new(g1, PolicyKind::Lru).unwrap())
+            .with_level("L2", DynCache::new(g2, PolicyKind::Lru).unwrap());
+        h.write_u8(0x1050, 0xff).unwrap();
+        let l2 = h.level("L2").unwrap().stats().total();
+        assert_eq!((l2.reads, l2.misses, l2.fills), (4, 4, 4));
+        assert_eq!(h.read_u32(0x107c), Ok(0));
+        h.flush().unwrap();
+        let l2 = h.level("L2").unwrap().stats().total();
+        assert_eq!((l2.reads, l2.writes, l2.hits), (4, 4, 4));
+        assert_eq!(h.mem_mut().read_u8(0x1050), Ok(0xff));
+
+        // Two lines in the first level share a line in the second level
+        let mut h = CacheHierarchy::new(NaiveRAM::<0x2000>::new())
+            .with_level("L1D", SetAssocCache::<32, 4, 1, LruPolicy<1>>::new())
+            .with_level("L2", SetAssocCache::<64, 4, 1, LruPolicy<1>>::new());
+        h.read_u32(0x1000).unwrap();
+        h.read_u32(0x1020).unwrap();
+        let l2 = h.level("L2").unwrap().stats().total();
+        assert_eq!((l2.reads, l2.misses, l2.hits), (2, 1, 1));
+        // An access which spans both is split at the first level's line
+        h.write_u32(0x103e, 0x1234_5678).unwrap();
+        assert_eq!(h.level("L1D").unwrap().stats().total().writes, 2);
+        assert_eq!(h.read_u32(0x103e), Ok(0x1234_5678));
+    }
+
+    #[test]
+    fn hierarchy_line_sizes_random() {
+        // The first level has larger lines than the levels below it
+        let g1 = CacheGeometry::new(128, 8, 2).unwrap();
+        let g2 = CacheGeometry::new(32, 32, 6).unwrap();
+        let mut h = CacheHierarchy::new(SparseRAM::<0x1_0000>::new())
+            .with_level("L1D", DynCache::new(g1, PolicyKind::Lru).unwrap())
+            .with_level("L2", DynCache::new(g2, PolicyKind::Nru).unwrap());
+        let mut model = vec![0; 0x1_0000];
+        check_model(&mut h, &mut model);
+
+        let (l1, l2) = (h.levels()[0].stats().total(),
+            h.levels()[1].stats().total());
+        assert_eq!(l2.reads, 4 * l1.fills);
+        assert_eq!(l2.writes, 4 * l1.writebacks);
+        h.flush().unwrap();
+        let mut buf = vec![0; model.len()];
+        h.mem().read_bytes(0, &mut buf);
+        assert_eq!(buf, model);
+    }
+
+    #[test]
+    fn hierarchy_write_through() {
+        let l1 = SetAssocCache::<16, 4, 2, FifoPolicy<2>>::new()
+            .with_write_policy(WritePolicy::WriteThrough)
+            .with_allocate_policy(AllocatePolicy::NoWriteAllocate);
+        let mut h = CacheHierarchy::new(NaiveRAM::<0x1000>::new())
+            .with_level("L1D", l1)
+            .with_level("L2", SetAssocCache::<64, 4, 4, LruPolicy<4>>::new());
+
+        // Writes which miss in the first level are only sent to the second
+        h.write_u32(0x100, 0x1122_3344).unwrap();
+        assert_eq!(h.levels()[0].stats().total().fills, 0);
+        assert_eq!(h.levels()[1].stats().total().writes, 1);
+        assert_eq!(h.read_u32(0x100), Ok(0x1122_3344));
+        // Writes which hit are also sent to the second level
+        h.write_u16(0x102, 0x5566).unwrap();
+        let l2 = h.levels()[1].stats().total();
+        assert_eq!((l2.reads, l2.writes, l2.hits), (1, 2, 2));
+        assert_eq!(h.mem_mut().read_u32(0x100), Ok(0));
+        h.flush().unwrap();
+        assert_eq!(h.mem_mut().read_u32(0x100), Ok(0x5566_3344));
+    }
+
+    #[test]
+    fn hierarchy_icache() {
+        let mut ram = NaiveRAM::<0x1000>::new();
+        ram.write_u32(0x100, 0x0000_0013).unwrap();
+        let mut h = CacheHierarchy::new(ram)
+            .with_icache("L1I", SetAssocCache::<32, 8, 2, LruPolicy<2>>::new())
+            .with_level("L1D", SetAssocCache::<32, 8, 2, LruPolicy<2>>::new())
+            .with_level("L2", SetAssocCache::<64, 16, 4, LruPolicy<4>>::new());
+
+        let mut buf = [0; 4];
+        h.fetch(0x100, &mut buf).unwrap();
+        assert_eq!(buf, [0x13, 0, 0, 0]);
+        // Writes are not observed by fetches until the caches are synced
+        h.write_u32(0x100, 0x0010_0093).unwrap();
+        h.fetch(0x100, &mut buf).unwrap();
+        assert_eq!(buf, [0x13, 0, 0, 0]);
+        h.sync_icache().unwrap();
+        h.fetch(0x100, &mut buf).unwrap();
+        assert_eq!(buf, [0x93, 0, 0x10, 0]);
+
+        let l1i = h.level("L1I").unwrap().stats().total();
+        assert_eq!((l1i.reads, l1i.hits, l1i.invalidations), (3, 1, 1));
+        assert_eq!(h.level("L1D").unwrap().stats().total().writebacks, 0);
+        // Fetches and write-backs from the first level both go to the L2
+        let l2 = h.level("L2").unwrap().stats().total();
+        assert_eq!((l2.reads, l2.writes), (3, 1));
+        h.reset_stats();
+        assert_eq!(h.icache().unwrap().stats().total(), Default::default());
+        // Modified lines are only written to the L2
+        assert_eq!(h.mem_mut().read_u32(0x100), Ok(0x13));
+    }
+
+    #[test]
+    fn hierarchy_faults() {
+        // The last line in each level runs past the end of the memory
+        let mut h = CacheHierarchy::new(NaiveRAM::<0x110>::new())
+            .with_icache("L1I", SetAssocCache::<32, 2, 1, LruPolicy<1>>::new())
+            .with_level("L1D", SetAssocCache::<32, 2, 1, LruPolicy<1>>::new())
+            .with_level("L2", SetAssocCache::<64, 2, 1, LruPolicy<1>>::new());
+        // Faults are reported for the original access
+        assert_eq!(h.read_u32(0x110),
+            Err(AccessFault::new(0x110, 4, AccessKind::Read)));
+        assert_eq!(h.write_u8(0x123, 1),
+            Err(AccessFault::new(0x123, 1, AccessKind::Write)));
+        assert_eq!(h.read_u32(0x10e),
+            Err(AccessFault::new(0x10e, 4, AccessKind::Read)));
+        let mut buf = [0; 4];
+        assert_eq!(h.fetch(0x110, &mut buf),
+            Err(AccessFault::new(0x110, 4, AccessKind::Read)));
+        // Lines which could not be fetched are not kept
+        assert_eq!(h.levels()[0].stats().total().invalidations, 1);
+
+        // Other accesses within the memory are not cached
+        h.write_u32(0x104, 0xdead_beef).unwrap();
+        assert_eq!(h.read_u32(0x104), Ok(0xdead_beef));
+        h.fetch(0x104, &mut buf).unwrap();
+        assert_eq!(buf, 0xdead_beefu32.to_le_bytes());
+        assert_eq!(h.mem_mut().read_u32(0x104), Ok(0xdead_beef));
+        for level in h.levels() {
+            let total = level.stats().total();
+            assert_eq!(total.fills, total.invalidations);
+        }
+        // Lines before the end of the memory are still cached
+        h.write_u32(0xfc, 0x1234_5678).unwrap();
+        assert_eq!(h.read_u32(0xfc), Ok(0x1234_5678));
+        assert_eq!(h.mem_mut().read_u32(0xfc), Ok(0));
+
+        // Without any caches, accesses go directly to memory
+        let mut h = CacheHierarchy::new(NaiveRAM::<0x100>::new());
+        h.write_u32(0x10, 7).unwrap();
+        assert_eq!(h.mem_mut().read_u32(0x10), Ok(7));
+        let mut buf = [0; 4];
+        h.fetch(0x10, &mut buf).unwrap();
+        assert_eq!(buf, [7, 0, 0, 0]);
+    }
+
+    /// A device which returns a different value for every read.
+    struct Counter(u8);
+    impl Bus for Counter {
+        fn read(&mut self, _addr: usize, dst: &mut [u8]) -> Result<(), AccessFault> {
+            self.0 += 1;
+            dst.fill(self.0);
+            Ok(())
+        }
+        fn write(&mut self, _addr: usize, src: &[u8]) -> Result<(), AccessFault> {
+            self.0 = src[0];
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn hierarchy_regions() {
+        let mut rom = NaiveRAM::<0x100>::new();
+        rom.write_bytes(0, &0x1234_5678u32.to_le_bytes());
+        let mut ram = NaiveRAM::<0x1000>::new();
+        let mut counter = Counter(0);
+        let mut bus = SystemBus::new();
+        bus.map("ram", 0, 0x1000, &mut ram).unwrap();
+        bus.map_rom("rom", 0x1000, 0x100, &mut rom).unwrap();
+        bus.map("counter", 0x2000, 0x40, &mut counter).unwrap();
+        let mut h = CacheHierarchy::new(bus)
+            .with_level("L1D", SetAssocCache::<32, 2, 1, LruPolicy<1>>::new())
+            .with_region(0x1000..0x1100, RegionPolicy::ReadOnly)
+            .with_region(0x2000..0x2040, RegionPolicy::Uncached);
+
+        // Reads from ROM are cached, but writes fault immediately and
+        // invalidate the cached line
+        assert_eq!(h.read_u32(0x1000), Ok(0x1234_5678));
+        assert_eq!(h.read_u32(0x1000), Ok(0x1234_5678));
+        assert_eq!(h.write_u32(0x1000, 0),
+            Err(AccessFault::new(0x1000, 4, AccessKind::Write)));
+        assert_eq!(h.read_u32(0x1000), Ok(0x1234_5678));
+        let total = h.levels()[0].stats().total();
+        assert_eq!((total.reads, total.misses), (3, 2));
+        assert_eq!((total.writes, total.invalidations), (0, 1));
+
+        // Evicting a line from ROM doesn't write it back
+        h.write_u32(0x0, 1).unwrap();
+        h.flush().unwrap();
+        assert_eq!(h.mem_mut().read_u32(0x0), Ok(1));
+
+        // Device registers are never cached
+        assert_eq!(h.read_u8(0x2000), Ok(1));
+        assert_eq!(h.read_u8(0x2000), Ok(2));
+        h.write_u8(0x2001, 10).unwrap();
+        assert_eq!(h.read_u8(0x2000), Ok(11));
+        let total = h.levels()[0].stats().total();
+        assert_eq!((total.reads, total.writes), (3, 1));
+        drop(h);
+
+        // Without a region, a write to ROM is held in the cache, and the
+        // write-back faults when the line is evicted
+        let mut bus = SystemBus::new();
+        bus.map("ram", 0, 0x1000, &mut ram).unwrap();
+        bus.map_rom("rom", 0x1000, 0x100, &mut rom).unwrap();
+        let mut h = CacheHierarchy::new(bus)
+            .with_level("L1D", SetAssocCache::<32, 2, 1, LruPolicy<1>>::new());
+        h.write_u32(0x1000, 0xdead_beef).unwrap();
+        assert_eq!(h.read_u32(0x40),
+            Err(AccessFault::new(0x40, 4, AccessKind::Read)));
+        assert_eq!(h.write_u32(0x40, 0),
+            Err(AccessFault::new(0x40, 4, AccessKind::Write)));
+        assert!(h.flush().is_err());
+        // The modified line is never dropped
+        assert_eq!(h.read_u32(0x1000), Ok(0xdead_beef));
+        assert_eq!(h.mem_mut().read_u32(0x1000), Ok(0x1234_5678));
+        assert_eq!(h.mem_mut().read_u32(0x40), Ok(0));
+    }
+
+    #[test]
+    fn hierarchy_exec() {
+        use crate::isa::rv32i::*;
+        use crate::isa::rv32i::asm::assemble;
+
+        // Sum an array through the caches
+        let mut ram = NaiveRAM::<0x1_0000>::new();
+        ram.write_bytes(0, &assemble("
+            li   a0, 0x1000
+            li   a1, 0x1400
+            li   a2, 0
+            loop:
+            lw   t0, 0(a0)
+            add  a2, a2, t0
+            sw   a2, 0(a0)
+            addi a0, a0, 4
+            bne  a0, a1, loop
+            ebreak
+        ").unwrap());
+        for i in 0..0x100 {
+            ram.write_u32(0x1000 + i * 4, i as u32).unwrap();
+        }
+        let mut h = CacheHierarchy::new(ram)
+            .with_level("L1D", SetAssocCache::<32, 4, 2, LruPolicy<2>>::new())
+            .with_level("L2", SetAssocCache::<64, 8, 4, LruPolicy<4>>::new());
+        let mut s = Rv32State::new(0);
+        while s.step(&mut h).is_ok() {}
+        let sum = (0..0x100).sum::<u32>();
+        assert_eq!(s.reg(Reg::from_name("a2").unwrap()), sum);
+        h.flush().unwrap();
+        assert_eq!(h.mem_mut().read_u32(0x13fc), Ok(sum));
+        assert!(h.levels()[0].stats().total().writebacks > 0);
+    }
+
+    #[test]
+    fn hierarchy_exec_icache() {
+        use crate::isa::rv32i::*;
+        use crate::isa::rv32i::asm::assemble;
+
+        // Count down in a loop, storing the counter each time
+        let mut ram = NaiveRAM::<0x1_0000>::new();
+        ram.write_bytes(0, &assemble("
+            li   a0, 0x1000
+            li   t0, 100
+            loop:
+            sw   t0, 0(a0)
+            addi t0, t0, -1
+            bnez t0, loop
+            ebreak
+        ").unwrap());
+        let mut h = CacheHierarchy::new(ram)
+            .with_icache("L1I", SetAssocCache::<32, 4, 2, LruPolicy<2>>::new())
+            .with_level("L1D", SetAssocCache::<32, 4, 2, LruPolicy<2>>::new())
+            .with_level("L2", SetAssocCache::<64, 8, 4, LruPolicy<4>>::new());
+        let mut s = Rv32State::new(0);
+        while s.step(&mut h).is_ok() {}
+        assert_eq!(s.pc, 0x14);
+
+        // Each instruction is a single fetch, and the program fits in a
+        // single line of the instruction cache
+        let l1i = h.level("L1I").unwrap().stats().total();
+        assert_eq!((l1i.reads, l1i.misses), (2 + 3 * 100 + 1, 1));
+        // The first level only sees the stores
+        let l1d = h.level("L1D").unwrap().stats().total();
+        assert_eq!((l1d.reads, l1d.writes, l1d.misses), (0, 100, 1));
+        // The instruction cache is filled from the second level
+        let l2 = h.level("L2").unwrap().stats().total();
+        assert_eq!((l2.reads, l2.misses), (2, 2));
+    }
+
+    #[test]
+    fn hierarchy_reservation() {
+        use crate::isa::rv32i::*;
+        use crate::isa::rv32i::asm::assemble;
+
+        let mut ram = NaiveRAM::<0x1_0000>::new();
+        ram.write_bytes(0, &assemble("
+            li   a0, 0x1008
+            li   t1, 5
+            lr.w t0, (a0)
+            sc.w t2, t1, (a0)
+            sc.w t3, t1, (a0)
+        ").unwrap());
+        let mut h = CacheHierarchy::new(ram)
+            .with_level("L1D", SetAssocCache::<32, 4, 2, LruPolicy<2>>::new());
+        let mut s = Rv32State::new(0);
+        // Step up to (and including) the 'lr'
+        for _ in 0..4 {
+            s.step(&mut h).unwrap();
+        }
+        let l1d = h.levels()[0].cache();
+        assert!(l1d.is_reserved(0x1000));
+        assert!(!l1d.is_reserved(0x1020));
+
+        // A successful store-conditional releases the line
+        s.step(&mut h).unwrap();
+        assert_eq!(s.reg(Reg::from_name("t2").unwrap()), 0);
+        assert!(!h.levels()[0].cache().is_reserved(0x1000));
+        s.step(&mut h).unwrap();
+        assert_eq!(s.reg(Reg::from_name("t3").unwrap()), 1);
+        assert_eq!(h.read_u32(0x1008), Ok(5));
+    }
+}
@@ -0,0 +1,202 @@
+//! Statistics describing the behavior of a cache.
+
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+/// Counters for events in a cache (or in a single set of a cache).
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct CacheCounters {
+    pub reads: u64,
+    pub writes: u64,
+    pub hits: u64,
+    pub misses: u64,
+    /// Misses to a line which was never accessed before. This and the
+    /// other kinds of miss are only counted when miss classification is
+    /// enabled (see [CacheStats::with_miss_classification]).
+    pub compulsory: u64,
+    /// Misses which would also occur in a fully-associative LRU cache
+    /// with the same capacity.
+    pub capacity: u64,
+    /// Misses which are caused by lines competing for the same set.
+    pub conflict: u64,
+    /// Lines which were allocated.
+    pub fills: u64,
+    /// Valid lines which were replaced by a fill.
+    pub evictions: u64,
+    /// Evicted lines which were dirty, and must be written back.
+    pub writebacks: u64,
+    /// Valid lines which were invalidated.
+    pub invalidations: u64,
+}
+impl CacheCounters {
+    /// The total number of reads and writes.
+    pub fn accesses(&self) -> u64 {
+        self.reads + self.writes
+    }
+
+    /// The fraction of accesses which hit, or zero if there were none.
+    pub fn hit_rate(&self) -> f64 {
+        if self.accesses() == 0 {
+            return 0.0;
+        }
+        self.hits as f64 / self.accesses() as f64
+    }
+}
+impl std::ops::AddAssign for CacheCounters {
+    fn add_assign(&mut self, rhs: Self) {
+        self.reads += rhs.reads;
+        self.writes += rhs.writes;
+        self.hits += rhs.hits;
+        self.misses += rhs.misses;
+        self.compulsory += rhs.compulsory;
+        self.capacity += rhs.capacity;
+        self.conflict += rhs.conflict;
+        self.fills += rhs.fills;
+        self.evictions += rhs.evictions;
+        self.writebacks += rhs.writebacks;
+        self.invalidations += rhs.invalidations;
+    }
+}
+
+/// A fully-associative LRU cache which only tracks line addresses, used
+/// to separate capacity misses from conflict misses.
+struct ShadowLru {
+    capacity: usize,
+    clock: u64,
+    /// The time of the most recent access to each resident line.
+    stamps: HashMap<usize, u64>,
+    /// Resident lines, ordered by the time of their most recent access.
+    order: BTreeMap<u64, usize>,
+}
+impl ShadowLru {
+    fn new(capacity: usize) -> Self {
+        Self { capacity, clock: 0, stamps: HashMap::new(), order: BTreeMap::new() }
+    }
+
+    /// Access a line, returning true if it was resident.
+    fn access(&mut self, line: usize) -> bool {
+        self.clock += 1;
+        let hit = match self.stamps.insert(line, self.clock) {
+            Some(old) => { self.order.remove(&old); true },
+            None => false,
+        };
+        self.order.insert(self.clock, line);
+        if self.order.len() > self.capacity {
+            let (_, victim) = self.order.pop_first().unwrap();
+            self.stamps.remove(&victim);
+        }
+        hit
+    }
+}
+
+/// History used to classify each miss as compulsory, capacity, or
+/// conflict.
+struct MissClassifier {
+    /// Every line which has ever been accessed.
+    seen: HashSet<usize>,
+    shadow: ShadowLru,
+}
+impl MissClassifier {
+    /// Record an access to a line, classifying it if it missed.
+    fn classify(&mut self, c: &mut CacheCounters, line: usize, hit: bool) {
+        let first = self.seen.insert(line);
+        let shadow_hit = self.shadow.access(line);
+        if hit {
+            return;
+        }
+        if first {
+            c.compulsory += 1;
+        } else if shadow_hit {
+            c.conflict += 1;
+        } else {
+            c.capacity += 1;
+        }
+    }
+}
+
+/// Statistics collected by a cache.
+pub struct CacheStats {
+    /// Counters for each set.
+    sets: Vec<CacheCounters>,
+    /// The capacity of the cache (in lines).
+    nlines: usize,
+    classifier: Option<MissClassifier>,
+}
+impl CacheStats {
+    /// Create statistics for a cache with 'nset' sets and a capacity of
+    /// 'nlines' lines.
+    pub fn new(nset: usize, nlines: usize) -> Self {
+        Self {
+            sets: vec![CacheCounters::default(); nset],
+            nlines,
+            classifier: None,
+        }
+    }
+
+    /// Classify each miss as compulsory, capacity, or conflict.
+    ///
+    /// This keeps a record of every line which was ever accessed, and
+    /// simulates a fully-associative LRU cache alongside the cache, which
+    /// makes each access considerably more expensive.
+    pub fn with_miss_classification(mut self) -> Self {
+        self.classifier = Some(MissClassifier {
+            seen: HashSet::new(),
+            shadow: ShadowLru::new(self.nlines),
+        });
+        self
+    }
+
+    /// Counters for the entire cache.
+    pub fn total(&self) -> CacheCounters {
+        let mut res = CacheCounters::default();
+        for set in self.sets.iter() {
+            res += *set;
+        }
+        res
+    }
+
+    /// Counters for each set in the cache.
+    pub fn sets(&self) -> &[CacheCounters] {
+        &self.sets
+    }
+
+    /// Reset all counters.
+    ///
+    /// The history used to classify misses is kept, so that misses after
+    /// warming up a cache are not counted as compulsory.
+    pub fn reset(&mut self) {
+        self.sets.fill(CacheCounters::default());
+    }
+
+    /// Record a read or write to the line at 'line' in set 'set'.
+    pub(crate) fn access(&mut self, set: usize, line: usize, write: bool,
+        hit: bool)
+    {
+        let c = &mut self.sets[set];
+        if write {
+            c.writes += 1;
+        } else {
+            c.reads += 1;
+        }
+        if hit {
+            c.hits += 1;
+        } else {
+            c.misses += 1;
+        }
+        if let Some(classifier) = self.classifier.as_mut() {
+            classifier.classify(c, line, hit);
+        }
+    }
+
+    /// Record a fill, and whether a valid or dirty line was evicted.
+    pub(crate) fn fill(&mut self, set: usize, evicted: bool, dirty: bool) {
+        let c = &mut self.sets[set];
+        c.fills += 1;
+        c.evictions += evicted as u64;
+        c.writebacks += dirty as u64;
+    }
+
+    /// Record the invalidation of a valid line.
+    pub(crate) fn invalidate(&mut self, set: usize) {
+        self.sets[set].invalidations += 1;
+    }
+}
@@ -0,0 +1,201 @@
+//! A sparse memory model for large address spaces.
+
+use std::collections::BTreeMap;
+use crate::memory::*;
+
+/// A model of a random-access memory with `SIZE` bytes, where storage is
+/// only allocated (in pages of `PAGE` bytes) when it is first written.
+///
+/// Reads from pages which have never been written return zero.
+pub struct SparseRAM<const SIZE: usize, const PAGE: usize = 0x1000> {
+    /// Resident pages, indexed by page number.
+    pages: BTreeMap<usize, Box<[u8; PAGE]>>,
+    endian: Endian,
+}
+impl <const SIZE: usize, const PAGE: usize> Default for SparseRAM<SIZE, PAGE> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+impl <const SIZE: usize, const PAGE: usize> SparseRAM<SIZE, PAGE> {
+    pub fn new() -> Self {
+        const { assert!(PAGE.is_power_of_two()) };
+        Self {
+            pages: BTreeMap::new(),
+            endian: Endian::Little,
+        }
+    }
+
+    /// Create a RAM which uses the given byte order for typed accesses.
+    pub fn with_endian(endian: Endian) -> Self {
+        Self { endian, ..Self::new() }
+    }
+
+    /// Returns the number of pages which are currently allocated.
+    pub fn resident_pages(&self) -> usize {
+        self.pages.len()
+    }
+
+    /// Returns the number of bytes which are currently allocated.
+    pub fn resident_bytes(&self) -> usize {
+        self.pages.len() * PAGE
+    }
+
+    /// Returns a fault if an access is not within the bounds of RAM.
+    fn check(addr: usize, len: usize, kind: AccessKind)
+        -> Result<(), AccessFault>
+    {
+        match addr.checked_add(len) {
+            Some(end) if end <= SIZE => Ok(()),
+            _ => Err(AccessFault::new(addr, len, kind)),
+        }
+    }
+
+    /// Split an access into pieces which do not cross a page boundary,
+    /// yielding the page number, the offset within the page, and the
+    /// range of the access covered by each piece.
+    fn pages(off: usize, len: usize)
+        -> impl Iterator<Item = (usize, usize, std::ops::Range<usize>)>
+    {
+        let mut pos = 0;
+        std::iter::from_fn(move || {
+            if pos == len {
+                return None;
+            }
+            let addr = off + pos;
+            let page_off = addr & (PAGE - 1);
+            let n = (PAGE - page_off).min(len - pos);
+            let res = (addr / PAGE, page_off, pos..(pos + n));
+            pos += n;
+            Some(res)
+        })
+    }
+
+    /// Copy data from RAM at offset `off`, into a slice `dst`. 
+    /// Returns a fault if any part of the access is out of range.
+    pub fn try_read_bytes(&self, off: usize, dst: &mut [u8])
+        -> Result<(), AccessFault>
+    {
+        Self::check(off, dst.len(), AccessKind::Read)?;
+        for (page, page_off, range) in Self::pages(off, dst.len()) {
+            let dst = &mut dst[range];
+            match self.pages.get(&page) {
+                Some(data) => {
+                    dst.copy_from_slice(&data[page_off..(page_off + dst.len())])
+                },
+                None => dst.fill(0),
+            }
+        }
+        Ok(())
+    }
+
+    /// Copy data from a slice `src` into RAM, starting at offset `off`.
+    /// Returns a fault if any part of the access is out of range.
+    pub fn try_write_bytes(&mut self, off: usize, src: &[u8])
+        -> Result<(), AccessFault>
+    {
+        Self::check(off, src.len(), AccessKind::Write)?;
+        for (page, page_off, range) in Self::pages(off, src.len()) {
+            let src = &src[range];
+            // Avoid building a temporary page on the stack
+            let data = self.pages.entry(page).or_insert_with(|| {
+                vec![0u8; PAGE].into_boxed_slice().try_into().unwrap()
+            });
+            data[page_off..(page_off + src.len())].copy_from_slice(src);
+        }
+        Ok(())
+    }
+
+    /// Copy data from RAM at offset `off`, into a slice `dst`.
+    /// Panics if any part of the access is out of range.
+    pub fn read_bytes(&self, off: usize, dst: &mut [u8]) {
+        self.try_read_bytes(off, dst).unwrap_or_else(|f| panic!("{}", f))
+    }
+
+    /// Copy data from a slice `src` into RAM, starting at offset `off`.
+    /// Panics if any part of the access is out of range.
+    pub fn write_bytes(&mut self, off: usize, src: &[u8]) {
+        self.try_write_bytes(off, src).unwrap_or_else(|f| panic!("{}", f))
+    }
+}
+impl <const SIZE: usize, const PAGE: usize> Bus for SparseRAM<SIZE, PAGE> {
+    fn read(&mut self, addr: usize, dst: &mut [u8]) -> Result<(), AccessFault> {
+        self.try_read_bytes(addr, dst)
+    }
+    fn write(&mut self, addr: usize, src: &[u8]) -> Result<(), AccessFault> {
+        self.try_write_bytes(addr, src)
+    }
+    fn endian(&self) -> Endian {
+        self.endian
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use crate::memory::*;
+    use crate::memory::sparse::*;
+
+    #[test]
+    fn sparse_ram() {
+        // The entire 32-bit address space
+        let mut ram = SparseRAM::<0x1_0000_0000>::new();
+        assert_eq!(ram.resident_pages(), 0);
+
+        // Reads from untouched pages are zero, and do not allocate
+        let mut buf = [0xffu8; 16];
+        ram.read_bytes(0x8000_0000, &mut buf);
+        assert_eq!(buf, [0; 16]);
+        assert_eq!(ram.read_u64(0xffff_fff8), Ok(0));
+        assert_eq!(ram.resident_pages(), 0);
+
+        ram.write_u32(0x8000_0000, 0x1122_3344).unwrap();
+        ram.write_u32(0x0000_0010, 0x5566_7788).unwrap();
+        assert_eq!(ram.resident_pages(), 2);
+        assert_eq!(ram.resident_bytes(), 0x2000);
+        assert_eq!(ram.read_u32(0x8000_0000), Ok(0x1122_3344));
+        assert_eq!(ram.read_u32(0x0000_0010), Ok(0x5566_7788));
+
+        // Accesses which cross a page boundary
+        ram.write_u64(0x8000_0ffc, 0x0102_0304_0506_0708).unwrap();
+        assert_eq!(ram.resident_pages(), 3);
+        assert_eq!(ram.read_u32(0x8000_1000), Ok(0x0102_0304));
+        let data: Vec<u8> = (0..0x2100).map(|i| i as u8).collect();
+        ram.write_bytes(0x4000_0f80, &data);
+        assert_eq!(ram.resident_pages(), 7);
+        let mut buf = vec![0u8; data.len()];
+        ram.read_bytes(0x4000_0f80, &mut buf);
+        assert_eq!(buf, data);
+
+        // The last bytes of RAM are accessible
+        ram.write_u32(0xffff_fffc, 0xdead_beef).unwrap();
+        assert_eq!(ram.read_u32(0xffff_fffc), Ok(0xdead_beef));
+        assert_eq!(ram.read_u32(0xffff_fffe),
+            Err(AccessFault::new(0xffff_fffe, 4, AccessKind::Read)));
+        assert_eq!(ram.write_u8(0x1_0000_0000, 0),
+            Err(AccessFault::new(0x1_0000_0000, 1, AccessKind::Write)));
+        assert_eq!(ram.read_u8(usize::MAX),
+            Err(AccessFault::new(usize::MAX, 1, AccessKind::Read)));
+        assert_eq!(ram.try_write_bytes(usize::MAX - 1, &[0; 4]),
+            Err(AccessFault::new(usize::MAX - 1, 4, AccessKind::Write)));
+        assert_eq!(ram.resident_pages(), 8);
+    }
+
+    #[test]
+    fn sparse_ram_small_pages() {
+        let mut ram = SparseRAM::<0x100, 0x10>::with_endian(Endian::Big);
+        ram.write_u32(0x0e, 0x1122_3344).unwrap();
+        assert_eq!(ram.resident_pages(), 2);
+        assert_eq!(ram.read_u16(0x10), Ok(0x3344));
+        assert_eq!(ram.read_u8(0x0e), Ok(0x11));
+    }
+
+    #[test]
+    fn sparse_ram_large_pages() {
+        // Pages larger than the stack of a test thread
+        let mut ram = SparseRAM::<0x1000_0000, 0x0400_0000>::new();
+        ram.write_u32(0x0bff_fff0, 1).unwrap();
+        assert_eq!(ram.resident_pages(), 1);
+        assert_eq!(ram.read_u32(0x0bff_fff0), Ok(1));
+    }
+}
@@ -0,0 +1,318 @@
+//! An address-decoded system bus.
+//!
+//! A [SystemBus] routes each access to one of a set of attached regions
+//! (ie. RAM, ROM, or memory-mapped devices) according to a memory map.
+
+use crate::memory::*;
+
+/// Reasons why a region cannot be attached to a [SystemBus].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum MapError {
+    /// The region has a size of zero bytes.
+    Empty,
+    /// The region extends beyond the end of the address space.
+    Overflow { base: usize, size: usize },
+    /// The region overlaps with a region that was already attached.
+    Overlap { base: usize, size: usize, other: String },
+}
+impl std::fmt::Display for MapError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Empty => write!(f, "empty region"),
+            Self::Overflow { base, size } => {
+                write!(f, "region at 0x{:08x} ({} bytes) overflows the \
+                    address space", base, size)
+            },
+            Self::Overlap { base, size, other } => {
+                write!(f, "region at 0x{:08x} ({} bytes) overlaps '{}'",
+                    base, size, other)
+            },
+        }
+    }
+}
+impl std::error::Error for MapError {}
+
+/// A range of addresses on a [SystemBus] which is backed by some device.
+pub struct Region<'a> {
+    /// A name used to identify this region.
+    pub name: String,
+    /// The first address in this region.
+    pub base: usize,
+    /// The size of this region (in bytes).
+    pub size: usize,
+    /// When set, writes to this region fault.
+    pub read_only: bool,
+    /// The device backing this region, which is accessed with an offset
+    /// relative to 'base'.
+    dev: Box<dyn Bus + 'a>,
+}
+impl Region<'_> {
+    /// Returns true if the provided address falls within this region.
+    pub fn contains(&self, addr: usize) -> bool {
+        addr >= self.base && addr - self.base < self.size
+    }
+}
+
+/// A bus which routes accesses to the region containing each address.
+///
+/// Accesses to addresses outside of any region, and accesses which span
+/// more than one region, fault.
+#[derive(Default)]
+pub struct SystemBus<'a> {
+    /// Attached regions, sorted by base address.
+    regions: Vec<Region<'a>>,
+    endian: Endian,
+}
+impl <'a> SystemBus<'a> {
+    pub fn new() -> Self {
+        Self { regions: Vec::new(), endian: Endian::Little }
+    }
+
+    /// Create a bus which uses the given byte order for typed accesses.
+    pub fn with_endian(endian: Endian) -> Self {
+        Self { endian, ..Self::new() }
+    }
+
+    /// Attach a device to 'size' bytes of the address space starting at
+    /// 'base'.
+    pub fn map(&mut self, name: &str, base: usize, size: usize,
+        dev: impl Bus + 'a) -> Result<(), MapError>
+    {
+        self.attach(name, base, size, false, Box::new(dev))
+    }
+
+    /// Attach a device which cannot be written through this bus.
+    ///
+    /// The contents of a read-only region are expected to be initialized
+    /// before the device is attached.
+    pub fn map_rom(&mut self, name: &str, base: usize, size: usize,
+        dev: impl Bus + 'a) -> Result<(), MapError>
+    {
+        self.attach(name, base, size, true, Box::new(dev))
+    }
+
+    /// Returns the attached regions, sorted by base address.
+    pub fn regions(&self) -> &[Region<'a>] {
+        &self.regions
+    }
+
+    /// Returns the region containing the provided address.
+    pub fn region(&self, addr: usize) -> Option<&Region<'a>> {
+        self.find(addr).map(|idx| &self.regions[idx])
+    }
+
+    fn attach(&mut self, name: &str, base: usize, size: usize,
+        read_only: bool, dev: Box<dyn Bus + 'a>) -> Result<(), MapError>
+    {
+        if size == 0 {
+            return Err(MapError::Empty);
+        }
+        let end = base.checked_add(size - 1)
+            .ok_or(MapError::Overflow { base, size })?;
+        let idx = self.regions.partition_point(|r| r.base < base);
+        let prev = idx.checked_sub(1).map(|i| &self.regions[i]);
+        let next = self.regions.get(idx);
+        for other in [prev, next].into_iter().flatten() {
+            if other.base <= end && base <= other.base + (other.size - 1) {
+                return Err(MapError::Overlap {
+                    base, size, other: other.name.clone()
+                });
+            }
+        }
+        let region = Region { name: name.to_string(), base, size, read_only, dev };
+        self.regions.insert(idx, region);
+        Ok(())
+    }
+
+    /// Find the index of the region containing the provided address.
+    fn find(&self, addr: usize) -> Option<usize> {
+        let idx = self.regions.partition_point(|r| r.base <= addr);
+        let idx = idx.checked_sub(1)?;
+        self.regions[idx].contains(addr).then_some(idx)
+    }
+
+    /// Find the region which contains an entire access, returning the
+    /// region and the offset of the access within it.
+    fn decode(&mut self, addr: usize, len: usize, kind: AccessKind)
+        -> Result<(&mut Region<'a>, usize), AccessFault>
+    {
+        let fault = AccessFault::new(addr, len, kind);
+        let idx = self.find(addr).ok_or(fault)?;
+        let region = &mut self.regions[idx];
+        let off = addr - region.base;
+        if len > region.size - off {
+            return Err(fault);
+        }
+        if kind == AccessKind::Write && region.read_only {
+            return Err(fault);
+        }
+        Ok((region, off))
+    }
+}
+impl Bus for SystemBus<'_> {
+    fn read(&mut self, addr: usize, dst: &mut [u8]) -> Result<(), AccessFault> {
+        let (region, off) = self.decode(addr, dst.len(), AccessKind::Read)?;
+        let base = region.base;
+        region.dev.read(off, dst)
+            .map_err(|f| AccessFault::new(base + f.addr, f.len, f.kind))
+    }
+    fn write(&mut self, addr: usize, src: &[u8]) -> Result<(), AccessFault> {
+        let (region, off) = self.decode(addr, src.len(), AccessKind::Write)?;
+        let base = region.base;
+        region.dev.write(off, src)
+            .map_err(|f| AccessFault::new(base + f.addr, f.len, f.kind))
+    }
+    fn endian(&self) -> Endian {
+        self.endian
+    }
+    fn fetch(&mut self, addr: usize, dst: &mut [u8]) -> Result<(), AccessFault> {
+        let (region, off) = self.decode(addr, dst.len(), AccessKind::Read)?;
+        let base = region.base;
+        region.dev.fetch(off, dst)
+            .map_err(|f| AccessFault::new(base + f.addr, f.len, f.kind))
+    }
+    fn observe_atomic(&mut self, addr: usize, kind: AtomicKind) {
+        if let Some(idx) = self.find(addr) {
+            let region = &mut self.regions[idx];
+            region.dev.observe_atomic(addr - region.base, kind);
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use crate::memory::*;
+    use crate::memory::system::*;
+
+    /// A device which records the last value written to it.
+    #[derive(Default)]
+    struct Uart { last: Option<u8>, atomic: Option<(usize, AtomicKind)> }
+    impl Bus for Uart {
+        fn read(&mut self, addr: usize, dst: &mut [u8]) -> Result<(), AccessFault> {
+            if addr != 0 || dst.len() != 1 {
+                return Err(AccessFault::new(addr, dst.len(), AccessKind::Read));
+            }
+            dst[0] = self.last.unwrap_or(0);
+            Ok(())
+        }
+        fn write(&mut self, addr: usize, src: &[u8]) -> Result<(), AccessFault> {
+            if addr != 0 || src.len() != 1 {
+                return Err(AccessFault::new(addr, src.len(), AccessKind::Write));
+            }
+            self.last = Some(src[0]);
+            Ok(())
+        }
+        fn observe_atomic(&mut self, addr: usize, kind: AtomicKind) {
+            self.atomic = Some((addr, kind));
+        }
+    }
+
+    #[test]
+    fn system_bus_routing() {
+        let mut rom = NaiveRAM::<0x100>::new();
+        rom.write_bytes(0, &0x1234_5678u32.to_le_bytes());
+        let mut ram = NaiveRAM::<0x1000>::new();
+        let mut uart = Uart::default();
+        {
+            let mut bus = SystemBus::new();
+            bus.map_rom("rom", 0x0000_1000, 0x100, &mut rom).unwrap();
+            bus.map("ram", 0x8000_0000, 0x1000, &mut ram).unwrap();
+            bus.map("uart", 0x1000_0000, 1, &mut uart).unwrap();
+            let names: Vec<&str> = bus.regions().iter()
+                .map(|r| r.name.as_str()).collect();
+            assert_eq!(names, ["rom", "uart", "ram"]);
+            assert_eq!(bus.region(0x8000_0fff).unwrap().name, "ram");
+            assert!(bus.region(0x8000_1000).is_none());
+
+            assert_eq!(bus.read_u32(0x1000), Ok(0x1234_5678));
+            let mut buf = [0; 2];
+            bus.fetch(0x1002, &mut buf).unwrap();
+            assert_eq!(buf, [0x34, 0x12]);
+            assert_eq!(bus.fetch(0x10ff, &mut buf),
+                Err(AccessFault::new(0x10ff, 2, AccessKind::Read)));
+            bus.write_u32(0x8000_0ffc, 0xdead_beef).unwrap();
+            assert_eq!(bus.read_u16(0x8000_0ffe), Ok(0xdead));
+            bus.write_u8(0x1000_0000, b'!').unwrap();
+
+            // Unmapped addresses
+            assert_eq!(bus.read_u8(0x0),
+                Err(AccessFault::new(0x0, 1, AccessKind::Read)));
+            assert_eq!(bus.write_u32(0x7fff_fffc, 0),
+                Err(AccessFault::new(0x7fff_fffc, 4, AccessKind::Write)));
+            // Accesses which run off the end of a region
+            assert_eq!(bus.read_u32(0x8000_0ffe),
+                Err(AccessFault::new(0x8000_0ffe, 4, AccessKind::Read)));
+            // Writes to a read-only region
+            assert_eq!(bus.write_u8(0x1000, 0),
+                Err(AccessFault::new(0x1000, 1, AccessKind::Write)));
+            assert_eq!(bus.read_u32(0x1000), Ok(0x1234_5678));
+            // Faults from a device are reported with the bus address
+            assert_eq!(bus.read_u16(0x1000_0000),
+                Err(AccessFault::new(0x1000_0000, 2, AccessKind::Read)));
+            // Atomic accesses are observed with an offset into the region
+            bus.observe_atomic(0x1000_0000, AtomicKind::LoadReserved);
+            bus.observe_atomic(0x2000_0000, AtomicKind::ReadModifyWrite);
+        }
+        assert_eq!(uart.atomic, Some((0, AtomicKind::LoadReserved)));
+        assert_eq!(uart.last, Some(b'!'));
+        assert_eq!(ram.read_u32(0xffc), Ok(0xdead_beef));
+    }
+
+    #[test]
+    fn system_bus_map_errors() {
+        let mut bus = SystemBus::new();
+        bus.map("a", 0x1000, 0x1000, NaiveRAM::<0x1000>::new()).unwrap();
+        bus.map("b", 0x3000, 0x1000, NaiveRAM::<0x1000>::new()).unwrap();
+        // Adjacent regions are allowed
+        bus.map("c", 0x2000, 0x1000, NaiveRAM::<0x1000>::new()).unwrap();
+
+        let overlaps = [(0x0800, 0x1000, "a"), (0x1fff, 1, "a"),
+            (0x3800, 0x100, "b"), (0x0000, 0x10000, "a")];
+        for (base, size, other) in overlaps {
+            assert_eq!(bus.map("x", base, size, NaiveRAM::<0x10>::new()),
+                Err(MapError::Overlap { base, size, other: other.to_string() }));
+        }
+        assert_eq!(bus.map("x", 0x8000, 0, NaiveRAM::<0x10>::new()),
+            Err(MapError::Empty));
+        assert_eq!(bus.map("x", usize::MAX, 2, NaiveRAM::<0x10>::new()),
+            Err(MapError::Overflow { base: usize::MAX, size: 2 }));
+        // The top of the address space can be mapped
+        bus.map("top", usize::MAX - 0xf, 0x10, NaiveRAM::<0x10>::new()).unwrap();
+        assert_eq!(bus.regions().len(), 4);
+    }
+
+    #[test]
+    fn system_bus_exec() {
+        use crate::isa::rv32i::*;
+        use crate::isa::rv32i::asm::assemble;
+        use crate::isa::rv32i::exec::Rv32Trap;
+
+        // Boot from ROM, copy a word into RAM, and write it to a device
+        let mut rom = NaiveRAM::<0x100>::new();
+        rom.write_bytes(0, &assemble("
+            li   a0, 0x80000000
+            li   a1, 0x10000000
+            li   t0, 0x41
+            sw   t0, 0(a0)
+            lw   t1, 0(a0)
+            sb   t1, 0(a1)
+            sw   t1, 0(a1)
+        ").unwrap());
+        let mut uart = Uart::default();
+        let mut bus = SystemBus::new();
+        bus.map_rom("rom", 0x1000, 0x100, rom).unwrap();
+        bus.map("ram", 0x8000_0000, 0x1000, NaiveRAM::<0x1000>::new()).unwrap();
+        bus.map("uart", 0x1000_0000, 1, &mut uart).unwrap();
+
+        let mut s = Rv32State::new(0x1000);
+        let trap = loop {
+            if let Err(trap) = s.step(&mut bus) {
+                break trap;
+            }
+        };
+        assert_eq!(trap, Rv32Trap::StoreAccessFault(0x1000_0000));
+        drop(bus);
+        assert_eq!(uart.last, Some(0x41));
+    }
+}